@@ -6,6 +6,7 @@ use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 pub use utils::*;
 use utoipa::ToSchema;
+use uuid::Uuid;
 
 #[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
@@ -20,6 +21,73 @@ pub struct User {
     #[serde(skip)]
     pub password_hash: Option<String>,
     pub created_at: DateTime<Utc>,
+    /// set when authenticated with an API key, limits what the request can do
+    #[sqlx(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Vec<String>>,
+    #[sqlx(default)]
+    #[serde(default)]
+    pub role: WorkspaceRole,
+    /// session the token was issued for, one per signin
+    #[sqlx(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub sid: Option<Uuid>,
+    /// set in impersonation tokens: the system admin acting as this user
+    #[sqlx(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub impersonated_by: Option<i64>,
+}
+
+/// Role of a user in its workspace, from the most to the least privileged
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    ToSchema,
+    Serialize,
+    Deserialize,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    sqlx::Type,
+)]
+#[sqlx(type_name = "workspace_role", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum WorkspaceRole {
+    Owner,
+    Admin,
+    #[default]
+    Member,
+    /// only sees the chats it was added to
+    Guest,
+}
+
+/// Presence of a user, derived from its connections to notify_server
+#[derive(
+    Debug, Clone, Copy, Default, ToSchema, Serialize, Deserialize, PartialEq, Eq, sqlx::Type,
+)]
+#[sqlx(type_name = "presence_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceStatus {
+    Online,
+    /// connected, but no activity reported for a while
+    Away,
+    #[default]
+    Offline,
+}
+
+/// Status set by a user, e.g. 🌴 on vacation, shown to the users it shares a chat with
+#[derive(Debug, Clone, Default, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct UserStatus {
+    pub user_id: i64,
+    pub emoji: Option<String>,
+    pub text: Option<String>,
+    /// the status is cleared then
+    pub expires_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
@@ -28,6 +96,14 @@ pub struct ChatUser {
     pub id: i64,
     pub fullname: String,
     pub email: String,
+    /// chat file of the avatar, if any
+    #[sqlx(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avatar_url: Option<String>,
+    /// deactivated in the workspace, kept to show who sent its past messages
+    #[sqlx(default)]
+    #[serde(default)]
+    pub deactivated: bool,
 }
 
 #[derive(Debug, FromRow, ToSchema, Serialize, Deserialize)]
@@ -92,6 +168,26 @@ impl User {
             email: email.to_string(),
             password_hash: None,
             created_at: DateTime::default(),
+            scopes: None,
+            role: WorkspaceRole::Member,
+            sid: None,
+            impersonated_by: None,
+        }
+    }
+}
+
+impl WorkspaceRole {
+    /// Whether the role has at least the privileges of `role`
+    pub fn is_at_least(&self, role: WorkspaceRole) -> bool {
+        *self <= role
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Owner => "owner",
+            Self::Admin => "admin",
+            Self::Member => "member",
+            Self::Guest => "guest",
         }
     }
 }
@@ -1,7 +1,10 @@
-use crate::middlewares::TokenVerify;
+use crate::{
+    User,
+    middlewares::{ClientInfo, TokenVerify},
+};
 use axum::{
     extract::{FromRequestParts, Query, Request, State},
-    http::StatusCode,
+    http::{StatusCode, request::Parts},
     middleware::Next,
     response::{IntoResponse, Response},
 };
@@ -14,58 +17,121 @@ use tracing::warn;
 
 #[derive(Debug, Deserialize)]
 struct Params {
-    token: String,
+    token: Option<String>,
+    ticket: Option<String>,
 }
 
+/// Authenticate the request with a bearer token, a cookie when cookie sessions are on, or for
+/// clients which can't set headers with a single-use `?ticket=`, or a `?token=` if the state
+/// allows it.
 pub async fn verify_token<T>(State(state): State<T>, req: Request, next: Next) -> Response
 where
     T: TokenVerify + Clone + Send + Sync + 'static,
 {
     let (mut parts, body) = req.into_parts();
-    let token =
-        match TypedHeader::<Authorization<Bearer>>::from_request_parts(&mut parts, &state).await {
-            Ok(TypedHeader(Authorization(bearer))) => bearer.token().to_string(),
-            Err(e) => {
-                if e.is_missing() {
-                    match Query::<Params>::from_request_parts(&mut parts, &state).await {
-                        Ok(params) => params.token.clone(),
-                        Err(e) => {
-                            let msg = format!("parse query params failed: {}", e);
-                            warn!(msg);
-                            return (StatusCode::UNAUTHORIZED, msg).into_response();
-                        }
-                    }
-                } else {
-                    let msg = format!("parse Authorization header failed: {}", e);
-                    warn!(msg);
-                    return (StatusCode::UNAUTHORIZED, msg).into_response();
+    let user = match authenticate(&state, &mut parts).await {
+        Ok(user) => user,
+        Err((status, msg)) => {
+            warn!(msg);
+            return (status, msg).into_response();
+        }
+    };
+
+    state.touch(&user, &ClientInfo::from_parts(&parts)).await;
+    let mut req = Request::from_parts(parts, body);
+    req.extensions_mut().insert(user);
+    next.run(req).await
+}
+
+async fn authenticate<T>(state: &T, parts: &mut Parts) -> Result<User, (StatusCode, String)>
+where
+    T: TokenVerify + Send + Sync,
+{
+    let token = match TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, state).await {
+        Ok(TypedHeader(Authorization(bearer))) => bearer.token().to_string(),
+        Err(e) if !e.is_missing() => {
+            let msg = format!("parse Authorization header failed: {}", e);
+            return Err((StatusCode::UNAUTHORIZED, msg));
+        }
+        Err(_) => match state
+            .cookie_policy()
+            .and_then(|policy| Some((policy, policy.token(&parts.headers)?)))
+        {
+            Some((policy, token)) => {
+                // the browser sends cookies along with forged requests too
+                if !policy.verify_csrf(&parts.method, &parts.headers) {
+                    let msg = "missing or invalid csrf token".to_string();
+                    return Err((StatusCode::FORBIDDEN, msg));
                 }
+                token
             }
-        };
+            None => return authenticate_with_query(state, parts).await,
+        },
+    };
 
-    let req = match state.verify(&token) {
-        Ok(user) => {
-            let mut req = Request::from_parts(parts, body);
-            req.extensions_mut().insert(user);
-            req
-        }
+    state.verify(&token).await.map_err(|e| {
+        let msg = format!("verify token failed: {:?}", e);
+        (StatusCode::FORBIDDEN, msg)
+    })
+}
+
+async fn authenticate_with_query<T>(
+    state: &T,
+    parts: &mut Parts,
+) -> Result<User, (StatusCode, String)>
+where
+    T: TokenVerify + Send + Sync,
+{
+    let params = match Query::<Params>::from_request_parts(parts, state).await {
+        Ok(Query(params)) => params,
         Err(e) => {
-            let msg = format!("verify token failed: {:?}", e);
-            warn!(msg);
-            return (StatusCode::FORBIDDEN, msg).into_response();
+            let msg = format!("parse query params failed: {}", e);
+            return Err((StatusCode::UNAUTHORIZED, msg));
         }
     };
 
-    next.run(req).await
+    match params {
+        Params {
+            ticket: Some(ticket),
+            ..
+        } => match state.redeem_ticket(&ticket).await {
+            Ok(Some(user)) => Ok(user),
+            Ok(None) => Err((
+                StatusCode::FORBIDDEN,
+                "invalid or expired ticket".to_string(),
+            )),
+            Err(e) => {
+                let msg = format!("redeem ticket failed: {:?}", e);
+                Err((StatusCode::FORBIDDEN, msg))
+            }
+        },
+        Params {
+            token: Some(token), ..
+        } if state.allow_query_token() => state.verify(&token).await.map_err(|e| {
+            let msg = format!("verify token failed: {:?}", e);
+            (StatusCode::FORBIDDEN, msg)
+        }),
+        Params { token: Some(_), .. } => Err((
+            StatusCode::UNAUTHORIZED,
+            "token in query params not allowed, use a ticket".to_string(),
+        )),
+        _ => Err((
+            StatusCode::UNAUTHORIZED,
+            "missing Authorization header".to_string(),
+        )),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{DecodingKey, EncodingKey, User};
+    use crate::{CookiePolicy, DecodingKey, EncodingKey};
     use anyhow::Result;
     use axum::{Router, body::Body, middleware::from_fn_with_state, routing::get};
-    use std::sync::Arc;
+    use std::sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    };
     use tower::ServiceExt;
 
     #[derive(Clone)]
@@ -74,14 +140,33 @@ mod tests {
     struct AppStateInner {
         ek: EncodingKey,
         dk: DecodingKey,
+        touched: AtomicUsize,
+        allow_query_token: AtomicBool,
+        cookie: CookiePolicy,
     }
 
     impl TokenVerify for AppState {
         type Error = ();
 
-        fn verify(&self, token: &str) -> std::result::Result<User, Self::Error> {
+        async fn verify(&self, token: &str) -> std::result::Result<User, Self::Error> {
             self.0.dk.verify(token).map_err(|_| ())
         }
+
+        async fn redeem_ticket(&self, ticket: &str) -> std::result::Result<Option<User>, ()> {
+            Ok((ticket == "good-ticket").then(|| User::new(1, "TeamMeng", "TeamMeng@123.com")))
+        }
+
+        fn allow_query_token(&self) -> bool {
+            self.0.allow_query_token.load(Ordering::Relaxed)
+        }
+
+        fn cookie_policy(&self) -> Option<&CookiePolicy> {
+            Some(&self.0.cookie)
+        }
+
+        async fn touch(&self, _user: &User, _client: &ClientInfo) {
+            self.0.touched.fetch_add(1, Ordering::Relaxed);
+        }
     }
 
     async fn handler(_req: Request) -> impl IntoResponse {
@@ -94,15 +179,24 @@ mod tests {
         let decoding_pem = include_str!("../../fixtures/decoding.pem");
         let ek = EncodingKey::load(encoding_pem)?;
         let dk = DecodingKey::load(decoding_pem)?;
-        let state = AppState(Arc::new(AppStateInner { ek, dk }));
+        let state = AppState(Arc::new(AppStateInner {
+            ek,
+            dk,
+            touched: AtomicUsize::new(0),
+            allow_query_token: AtomicBool::new(false),
+            cookie: CookiePolicy {
+                enabled: true,
+                ..Default::default()
+            },
+        }));
 
         let user = User::new(1, "TeamMeng", "TeamMeng@123.com");
         let token = state.0.ek.sign(user)?;
 
         let app = Router::new()
-            .route("/", get(handler))
+            .route("/", get(handler).post(handler))
             .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
-            .with_state(state);
+            .with_state(state.clone());
 
         // good token
         let req = Request::builder()
@@ -112,13 +206,33 @@ mod tests {
         let res = app.clone().oneshot(req).await?;
         assert_eq!(StatusCode::OK, res.status());
 
-        // good token in query params
+        // good token in query params, refused unless allowed
+        let req = Request::builder()
+            .uri(format!("/?token={}", token))
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        state.0.allow_query_token.store(true, Ordering::Relaxed);
         let req = Request::builder()
             .uri(format!("/?token={}", token))
             .body(Body::empty())?;
         let res = app.clone().oneshot(req).await?;
         assert_eq!(res.status(), StatusCode::OK);
 
+        // tickets
+        let req = Request::builder()
+            .uri("/?ticket=good-ticket")
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let req = Request::builder()
+            .uri("/?ticket=bad-ticket")
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
         // no token
         let req = Request::builder().uri("/").body(Body::empty())?;
         let res = app.clone().oneshot(req).await?;
@@ -136,9 +250,38 @@ mod tests {
         let req = Request::builder()
             .uri("/?token=bad-token")
             .body(Body::empty())?;
-        let res = app.oneshot(req).await?;
+        let res = app.clone().oneshot(req).await?;
         assert_eq!(res.status(), StatusCode::FORBIDDEN);
 
+        // token in a cookie, changes need the csrf token
+        let cookie = format!("chat_token={}; chat_csrf=xyz", token);
+        let req = Request::builder()
+            .uri("/")
+            .header("Cookie", &cookie)
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let req = Request::builder()
+            .method("POST")
+            .uri("/")
+            .header("Cookie", &cookie)
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let req = Request::builder()
+            .method("POST")
+            .uri("/")
+            .header("Cookie", &cookie)
+            .header("X-CSRF-Token", "xyz")
+            .body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // only verified tokens are seen
+        assert_eq!(state.0.touched.load(Ordering::Relaxed), 5);
+
         Ok(())
     }
 }
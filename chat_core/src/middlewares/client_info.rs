@@ -0,0 +1,68 @@
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header::USER_AGENT, request::Parts},
+};
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+};
+
+const USER_AGENT_MAX_LEN: usize = 256;
+
+/// Where a request comes from, to tell the sessions of a user apart
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    /// only known when the server is run with connect info
+    pub ip: Option<IpAddr>,
+}
+
+impl ClientInfo {
+    pub fn from_parts(parts: &Parts) -> Self {
+        let user_agent = parts
+            .headers
+            .get(USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.chars().take(USER_AGENT_MAX_LEN).collect());
+        let ip = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        Self { user_agent, ip }
+    }
+}
+
+impl<S> FromRequestParts<S> for ClientInfo
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self::from_parts(parts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+
+    #[test]
+    fn client_info_should_read_user_agent_and_ip() {
+        let mut req = Request::builder()
+            .header(USER_AGENT, "curl/8.0")
+            .body(())
+            .unwrap();
+        let addr = SocketAddr::from(([10, 0, 0, 1], 4000));
+        req.extensions_mut().insert(ConnectInfo(addr));
+        let (parts, _) = req.into_parts();
+
+        let info = ClientInfo::from_parts(&parts);
+        assert_eq!(info.user_agent.as_deref(), Some("curl/8.0"));
+        assert_eq!(info.ip, Some(addr.ip()));
+
+        let (parts, _) = Request::new(()).into_parts();
+        assert_eq!(ClientInfo::from_parts(&parts), ClientInfo::default());
+    }
+}
@@ -1,13 +1,14 @@
 mod auth;
+mod client_info;
 mod request_id;
 mod server_time;
 
 use crate::{
-    User,
+    CookiePolicy, User,
     middlewares::{request_id::set_request_id, server_time::server_time},
 };
 use axum::{Router, middleware::from_fn};
-use std::fmt;
+use std::{fmt, future::Future};
 use tower::ServiceBuilder;
 use tower_http::{
     LatencyUnit,
@@ -17,10 +18,35 @@ use tower_http::{
 use tracing::Level;
 
 pub use auth::verify_token;
+pub use client_info::ClientInfo;
 
 pub trait TokenVerify {
     type Error: fmt::Debug;
-    fn verify(&self, token: &str) -> Result<User, Self::Error>;
+    fn verify(&self, token: &str) -> impl Future<Output = Result<User, Self::Error>> + Send;
+
+    /// Exchange a single-use ticket from `?ticket=` for its user, for clients which can't set
+    /// headers such as `EventSource`. `None` if the ticket isn't valid or tickets aren't supported.
+    fn redeem_ticket(
+        &self,
+        _ticket: &str,
+    ) -> impl Future<Output = Result<Option<User>, Self::Error>> + Send {
+        async { Ok(None) }
+    }
+
+    /// Whether a token in `?token=` is accepted, it ends up in access logs and traces
+    fn allow_query_token(&self) -> bool {
+        false
+    }
+
+    /// How tokens are read from cookies, `None` if cookie sessions are off
+    fn cookie_policy(&self) -> Option<&CookiePolicy> {
+        None
+    }
+
+    /// Called for every verified token, e.g. to record when and where its session was last seen
+    fn touch(&self, _user: &User, _client: &ClientInfo) -> impl Future<Output = ()> + Send {
+        async {}
+    }
 }
 
 const REQUEST_ID_HEADER: &str = "x-request-id";
@@ -0,0 +1,158 @@
+use axum::http::{HeaderMap, Method, header::COOKIE};
+use serde::{Deserialize, Serialize};
+
+/// Cookie sessions for browsers, so that tokens are out of reach of scripts.
+///
+/// The token cookies are `HttpOnly`, requests changing something must repeat the value of the
+/// CSRF cookie in the CSRF header (double submit).
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct CookiePolicy {
+    /// set cookies on signin and accept the access token from them
+    pub enabled: bool,
+    /// cookie of the access token
+    pub name: String,
+    /// cookie of the refresh token
+    pub refresh_name: String,
+    /// cookie of the CSRF token, readable by scripts
+    pub csrf_name: String,
+    /// header the CSRF token is sent back in
+    pub csrf_header: String,
+    pub same_site: SameSite,
+    /// only send the cookies over https
+    pub secure: bool,
+    /// share the cookies with subdomains, e.g. when notify_server is on another host
+    pub domain: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SameSite {
+    Strict,
+    #[default]
+    Lax,
+    /// needs `secure`
+    None,
+}
+
+impl Default for CookiePolicy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            name: "chat_token".to_string(),
+            refresh_name: "chat_refresh".to_string(),
+            csrf_name: "chat_csrf".to_string(),
+            csrf_header: "x-csrf-token".to_string(),
+            same_site: SameSite::Lax,
+            secure: true,
+            domain: None,
+        }
+    }
+}
+
+impl CookiePolicy {
+    /// The access token from the cookies of a request
+    pub fn token(&self, headers: &HeaderMap) -> Option<String> {
+        read_cookie(headers, &self.name)
+    }
+
+    /// The refresh token from the cookies of a request
+    pub fn refresh_token(&self, headers: &HeaderMap) -> Option<String> {
+        read_cookie(headers, &self.refresh_name)
+    }
+
+    /// Safe methods pass, others must carry the CSRF cookie value in the CSRF header
+    pub fn verify_csrf(&self, method: &Method, headers: &HeaderMap) -> bool {
+        if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+            return true;
+        }
+        let header = headers
+            .get(self.csrf_header.as_str())
+            .and_then(|v| v.to_str().ok());
+        match (read_cookie(headers, &self.csrf_name), header) {
+            (Some(cookie), Some(header)) => !cookie.is_empty() && cookie == header,
+            _ => false,
+        }
+    }
+
+    /// A `Set-Cookie` value, an empty value with no max age removes the cookie
+    pub fn set_cookie(&self, name: &str, value: &str, max_age: u64, http_only: bool) -> String {
+        let mut cookie = format!(
+            "{}={}; Path=/; Max-Age={}; SameSite={}",
+            name,
+            value,
+            max_age,
+            self.same_site.as_str()
+        );
+        if let Some(domain) = &self.domain {
+            cookie.push_str(&format!("; Domain={}", domain));
+        }
+        if self.secure {
+            cookie.push_str("; Secure");
+        }
+        if http_only {
+            cookie.push_str("; HttpOnly");
+        }
+        cookie
+    }
+}
+
+impl SameSite {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Strict => "Strict",
+            Self::Lax => "Lax",
+            Self::None => "None",
+        }
+    }
+}
+
+fn read_cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn cookies_should_be_read_and_set() {
+        let policy = CookiePolicy::default();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            COOKIE,
+            HeaderValue::from_static("other=1; chat_token=abc; chat_csrf=xyz"),
+        );
+        assert_eq!(policy.token(&headers).as_deref(), Some("abc"));
+        assert_eq!(policy.refresh_token(&headers), None);
+
+        let cookie = policy.set_cookie("chat_token", "abc", 900, true);
+        assert_eq!(
+            cookie,
+            "chat_token=abc; Path=/; Max-Age=900; SameSite=Lax; Secure; HttpOnly"
+        );
+    }
+
+    #[test]
+    fn verify_csrf_should_need_matching_header() {
+        let policy = CookiePolicy::default();
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, HeaderValue::from_static("chat_csrf=xyz"));
+        assert!(policy.verify_csrf(&Method::GET, &headers));
+        assert!(!policy.verify_csrf(&Method::POST, &headers));
+
+        headers.insert("x-csrf-token", HeaderValue::from_static("bad"));
+        assert!(!policy.verify_csrf(&Method::POST, &headers));
+
+        headers.insert("x-csrf-token", HeaderValue::from_static("xyz"));
+        assert!(policy.verify_csrf(&Method::POST, &headers));
+    }
+}
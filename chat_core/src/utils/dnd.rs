@@ -0,0 +1,36 @@
+use sqlx::PgPool;
+
+/// The users among `user_ids` in do-not-disturb right now, either until a set time or by
+/// their weekly schedule in their own time zone
+pub async fn users_in_dnd(pool: &PgPool, user_ids: &[i64]) -> Result<Vec<i64>, sqlx::Error> {
+    if user_ids.is_empty() {
+        return Ok(vec![]);
+    }
+    sqlx::query_scalar(
+        "
+        SELECT u.id
+        FROM users u
+        CROSS JOIN LATERAL (
+            SELECT NOW() AT TIME ZONE COALESCE(u.timezone, 'UTC') AS local
+        ) l
+        WHERE u.id = ANY($1) AND (
+            u.dnd_until > NOW() OR EXISTS (
+                SELECT 1 FROM dnd_windows w
+                WHERE w.user_id = u.id AND CASE
+                    WHEN w.start_time < w.end_time THEN
+                        EXTRACT(ISODOW FROM l.local) = w.weekday
+                        AND l.local::TIME >= w.start_time AND l.local::TIME < w.end_time
+                    -- the window ends the next day
+                    ELSE
+                        (EXTRACT(ISODOW FROM l.local) = w.weekday AND l.local::TIME >= w.start_time)
+                        OR (EXTRACT(ISODOW FROM l.local) = w.weekday % 7 + 1
+                            AND l.local::TIME < w.end_time)
+                END
+            )
+        )
+        ",
+    )
+    .bind(user_ids)
+    .fetch_all(pool)
+    .await
+}
@@ -1,41 +1,198 @@
 use crate::User;
-use jwt_simple::prelude::*;
-use std::ops::Deref;
+use jwt_simple::{JWTError, prelude::*};
+use std::{collections::HashMap, ops::Deref, sync::RwLock};
+use utoipa::ToSchema;
+use uuid::Uuid;
 
-const JWT_DURATION: u64 = 60 * 60 * 24 * 7;
+// access tokens are short-lived, clients renew them with a refresh token
+const JWT_DURATION: u64 = 60 * 15;
 const JWT_ISSUER: &str = "chat_server";
 const JWT_AUDIENCE: &str = "chat_web";
+const JWT_LEEWAY: u64 = 60;
 
-pub struct EncodingKey(Ed25519KeyPair);
+/// The active signing key, its `kid` is put in the header of every token
+pub struct EncodingKey {
+    key: Ed25519KeyPair,
+    policy: TokenPolicy,
+}
+
+/// A keyset of verification keys identified by `kid`
+pub struct DecodingKey {
+    keys: RwLock<HashMap<String, Ed25519PublicKey>>,
+    policy: TokenPolicy,
+}
+
+/// How tokens are issued and which ones are accepted, set per deployment
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct TokenPolicy {
+    /// lifetime of access tokens, in seconds
+    pub duration: u64,
+    /// issuer put in tokens, only tokens from this issuer are accepted
+    pub issuer: String,
+    /// audience put in tokens
+    pub audience: String,
+    /// other audiences accepted besides `audience`
+    pub allowed_audiences: Vec<String>,
+    /// clock skew tolerance when checking `exp` and `nbf`, in seconds
+    pub leeway: u64,
+}
+
+/// JSON Web Key Set, as served by `/.well-known/jwks.json`
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+/// An Ed25519 public key in JWK format (RFC 8037)
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct Jwk {
+    pub kty: String,
+    pub crv: String,
+    pub alg: String,
+    #[serde(rename = "use")]
+    pub r#use: String,
+    pub kid: String,
+    pub x: String,
+}
 
-pub struct DecodingKey(Ed25519PublicKey);
+impl Default for TokenPolicy {
+    fn default() -> Self {
+        Self {
+            duration: JWT_DURATION,
+            issuer: JWT_ISSUER.to_string(),
+            audience: JWT_AUDIENCE.to_string(),
+            allowed_audiences: vec![],
+            leeway: JWT_LEEWAY,
+        }
+    }
+}
 
 impl EncodingKey {
     pub fn load(pem: &str) -> Result<Self, jwt_simple::Error> {
-        Ok(Self(Ed25519KeyPair::from_pem(pem)?))
+        let key = Ed25519KeyPair::from_pem(pem)?;
+        let kid = key_id(key.public_key());
+        Ok(Self {
+            key: key.with_key_id(&kid),
+            policy: TokenPolicy::default(),
+        })
+    }
+
+    pub fn with_policy(mut self, policy: TokenPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn key_id(&self) -> &str {
+        self.key.key_id().as_deref().unwrap_or_default()
     }
 
     pub fn sign(&self, user: User) -> Result<String, jwt_simple::Error> {
-        let claims = Claims::with_custom_claims(user, Duration::from_secs(JWT_DURATION))
-            .with_issuer(JWT_ISSUER)
-            .with_audience(JWT_AUDIENCE);
-        self.0.sign(claims)
+        self.sign_with_duration(user, self.policy.duration)
+    }
+
+    /// Sign a token living `duration` seconds instead of the policy duration
+    pub fn sign_with_duration(
+        &self,
+        user: User,
+        duration: u64,
+    ) -> Result<String, jwt_simple::Error> {
+        let policy = &self.policy;
+        let claims = Claims::with_custom_claims(user, Duration::from_secs(duration))
+            .with_issuer(&policy.issuer)
+            .with_audience(&policy.audience)
+            .with_jwt_id(Uuid::now_v7());
+        self.key.sign(claims)
     }
 }
 
 impl DecodingKey {
     pub fn load(pem: &str) -> Result<Self, jwt_simple::Error> {
-        Ok(Self(Ed25519PublicKey::from_pem(pem)?))
+        Self::load_all([pem])
+    }
+
+    /// Load several PEM public keys, e.g. the active one and the ones being rotated out
+    pub fn load_all<'a>(
+        pems: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Self, jwt_simple::Error> {
+        let mut keys = HashMap::new();
+        for pem in pems {
+            let key = Ed25519PublicKey::from_pem(pem)?;
+            keys.insert(key_id(key.clone()), key);
+        }
+        Ok(Self::new(keys))
+    }
+
+    pub fn from_jwks(jwks: &Jwks) -> Result<Self, jwt_simple::Error> {
+        Ok(Self::new(jwks_to_keys(jwks)?))
+    }
+
+    pub fn with_policy(mut self, policy: TokenPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    fn new(keys: HashMap<String, Ed25519PublicKey>) -> Self {
+        Self {
+            keys: RwLock::new(keys),
+            policy: TokenPolicy::default(),
+        }
+    }
+
+    /// Replace the keyset, e.g. with a freshly fetched JWKS
+    pub fn update(&self, jwks: &Jwks) -> Result<(), jwt_simple::Error> {
+        let keys = jwks_to_keys(jwks)?;
+        *self.keys.write().expect("keyset lock poisoned") = keys;
+        Ok(())
+    }
+
+    pub fn jwks(&self) -> Jwks {
+        let keys = self.keys.read().expect("keyset lock poisoned");
+        let mut keys: Vec<_> = keys
+            .iter()
+            .map(|(kid, key)| Jwk {
+                kty: "OKP".to_string(),
+                crv: "Ed25519".to_string(),
+                alg: "EdDSA".to_string(),
+                r#use: "sig".to_string(),
+                kid: kid.clone(),
+                x: Base64UrlSafeNoPadding::encode_to_string(key.to_bytes())
+                    .expect("encode public key"),
+            })
+            .collect();
+        keys.sort_by(|a, b| a.kid.cmp(&b.kid));
+        Jwks { keys }
     }
 
     pub fn verify(&self, token: &str) -> Result<User, jwt_simple::Error> {
+        Ok(self.decode(token)?.custom)
+    }
+
+    /// Verify the token and return all its claims, including `jti` and `exp`
+    pub fn decode(&self, token: &str) -> Result<JWTClaims<User>, jwt_simple::Error> {
+        let policy = &self.policy;
+        let mut allowed_audiences = HashSet::from_strings(&policy.allowed_audiences);
+        allowed_audiences.insert(policy.audience.clone());
         let options = VerificationOptions {
-            allowed_issuers: Some(HashSet::from_strings(&[JWT_ISSUER])),
-            allowed_audiences: Some(HashSet::from_strings(&[JWT_AUDIENCE])),
+            allowed_issuers: Some(HashSet::from_strings(&[&policy.issuer])),
+            allowed_audiences: Some(allowed_audiences),
+            time_tolerance: Some(Duration::from_secs(policy.leeway)),
             ..Default::default()
         };
-        let claims = self.0.verify_token::<User>(token, Some(options))?;
-        Ok(claims.custom)
+        let metadata = Token::decode_metadata(token)?;
+        let keys = self.keys.read().expect("keyset lock poisoned");
+
+        match metadata.key_id() {
+            Some(kid) => {
+                let key = keys.get(kid).ok_or(JWTError::KeyIdentifierMismatch)?;
+                key.verify_token::<User>(token, Some(options))
+            }
+            // tokens signed before keys had a kid, try every key
+            None => keys
+                .values()
+                .find_map(|key| key.verify_token::<User>(token, Some(options.clone())).ok())
+                .ok_or_else(|| JWTError::InvalidSignature.into()),
+        }
     }
 }
 
@@ -43,16 +200,26 @@ impl Deref for EncodingKey {
     type Target = Ed25519KeyPair;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.key
     }
 }
 
-impl Deref for DecodingKey {
-    type Target = Ed25519PublicKey;
+// derived from the public key, so every service loading the same key agrees on its kid
+fn key_id(mut key: Ed25519PublicKey) -> String {
+    key.create_key_id().to_string()
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+fn jwks_to_keys(jwks: &Jwks) -> Result<HashMap<String, Ed25519PublicKey>, jwt_simple::Error> {
+    let mut keys = HashMap::new();
+    for jwk in &jwks.keys {
+        if jwk.kty != "OKP" || jwk.crv != "Ed25519" {
+            continue;
+        }
+        let raw = Base64UrlSafeNoPadding::decode_to_vec(&jwk.x, None)?;
+        let key = Ed25519PublicKey::from_bytes(&raw)?.with_key_id(&jwk.kid);
+        keys.insert(jwk.kid.clone(), key);
     }
+    Ok(keys)
 }
 
 #[cfg(test)]
@@ -76,6 +243,91 @@ mod tests {
 
         assert_eq!(ret, user);
 
+        let claims = dk.decode(&token)?;
+        assert!(claims.jwt_id.is_some());
+
+        let metadata = Token::decode_metadata(&token)?;
+        assert_eq!(metadata.key_id(), Some(ek.key_id()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn jwt_keyset_rotation_should_work() -> Result<()> {
+        let old_ek = EncodingKey::load(include_str!("../../fixtures/encoding.pem"))?;
+        let new_kp = Ed25519KeyPair::generate();
+        let new_ek = EncodingKey::load(&new_kp.to_pem())?;
+        let user = User::new(1, "TeamMeng", "TeamMeng@123.com");
+
+        let old_token = old_ek.sign(user.clone())?;
+        let new_token = new_ek.sign(user.clone())?;
+
+        // both keys are accepted during rotation
+        let dk = DecodingKey::load_all([
+            include_str!("../../fixtures/decoding.pem"),
+            new_kp.public_key().to_pem().as_str(),
+        ])?;
+        assert_eq!(dk.verify(&old_token)?, user);
+        assert_eq!(dk.verify(&new_token)?, user);
+
+        // jwks round trip
+        let jwks = dk.jwks();
+        assert_eq!(jwks.keys.len(), 2);
+        let dk = DecodingKey::from_jwks(&jwks)?;
+        assert_eq!(dk.verify(&old_token)?, user);
+
+        // old key retired
+        let jwks = Jwks {
+            keys: jwks
+                .keys
+                .into_iter()
+                .filter(|k| k.kid == new_ek.key_id())
+                .collect(),
+        };
+        dk.update(&jwks)?;
+        assert!(dk.verify(&old_token).is_err());
+        assert_eq!(dk.verify(&new_token)?, user);
+
+        Ok(())
+    }
+
+    #[test]
+    fn jwt_token_policy_should_work() -> Result<()> {
+        let encoding_pem = include_str!("../../fixtures/encoding.pem");
+        let decoding_pem = include_str!("../../fixtures/decoding.pem");
+        let user = User::new(1, "TeamMeng", "TeamMeng@123.com");
+
+        let staging = TokenPolicy {
+            issuer: "chat_server_staging".to_string(),
+            audience: "chat_web_staging".to_string(),
+            ..Default::default()
+        };
+        let ek = EncodingKey::load(encoding_pem)?.with_policy(staging.clone());
+        let token = ek.sign(user.clone())?;
+
+        // a token from staging isn't valid on prod
+        let dk = DecodingKey::load(decoding_pem)?;
+        assert!(dk.verify(&token).is_err());
+
+        let dk = DecodingKey::load(decoding_pem)?.with_policy(staging.clone());
+        assert_eq!(dk.verify(&token)?, user);
+        let claims = dk.decode(&token)?;
+        let lifetime = claims.expires_at.unwrap() - claims.issued_at.unwrap();
+        assert_eq!(lifetime.as_secs(), staging.duration);
+
+        // extra audiences are accepted
+        let ek = EncodingKey::load(encoding_pem)?.with_policy(TokenPolicy {
+            audience: "chat_mobile".to_string(),
+            ..staging.clone()
+        });
+        let token = ek.sign(user.clone())?;
+        assert!(dk.verify(&token).is_err());
+        let dk = DecodingKey::load(decoding_pem)?.with_policy(TokenPolicy {
+            allowed_audiences: vec!["chat_mobile".to_string()],
+            ..staging
+        });
+        assert_eq!(dk.verify(&token)?, user);
+
         Ok(())
     }
 }
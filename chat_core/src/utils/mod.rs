@@ -1,3 +1,13 @@
+mod cookie;
+mod dnd;
 mod jwt;
+mod revocation;
+mod session;
+mod ticket;
 
-pub use jwt::{DecodingKey, EncodingKey};
+pub use cookie::{CookiePolicy, SameSite};
+pub use dnd::users_in_dnd;
+pub use jwt::{DecodingKey, EncodingKey, Jwk, Jwks, TokenPolicy};
+pub use revocation::{is_token_revoked, revoke_token, revoke_user_tokens};
+pub use session::touch_session;
+pub use ticket::{hash_token, redeem_ticket};
@@ -0,0 +1,124 @@
+use crate::User;
+use chrono::{DateTime, Utc};
+use jwt_simple::prelude::JWTClaims;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Check if a token was revoked, either on its own (`jti`), with its session or together with all
+/// tokens of its user, or if its user was deleted, or isn't an active member of the workspace of
+/// the token anymore
+pub async fn is_token_revoked(
+    pool: &PgPool,
+    claims: &JWTClaims<User>,
+) -> Result<bool, sqlx::Error> {
+    let jti = claims.jwt_id.as_deref().unwrap_or_default();
+    let issued_at = issued_at(claims);
+
+    let (revoked,): (bool,) = sqlx::query_as(
+        "
+        SELECT EXISTS (
+            SELECT 1 FROM revoked_tokens WHERE jti = $1
+        ) OR EXISTS (
+            SELECT 1 FROM users WHERE id = $2 AND tokens_revoked_at > $3
+        ) OR EXISTS (
+            SELECT 1 FROM sessions WHERE id = $4 AND revoked_at IS NOT NULL
+        ) OR NOT EXISTS (
+            SELECT 1 FROM users WHERE id = $2
+        ) OR ($5 <> 0 AND NOT EXISTS (
+            SELECT 1 FROM workspace_members
+            WHERE user_id = $2 AND ws_id = $5 AND deactivated_at IS NULL
+        ))
+        ",
+    )
+    .bind(jti)
+    .bind(claims.custom.id)
+    .bind(issued_at)
+    .bind(claims.custom.sid)
+    .bind(claims.custom.ws_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(revoked)
+}
+
+/// Revoke a single token until it expires
+pub async fn revoke_token(pool: &PgPool, claims: &JWTClaims<User>) -> Result<(), sqlx::Error> {
+    let Some(jti) = claims.jwt_id.as_deref() else {
+        return Ok(());
+    };
+    let expires_at = claims
+        .expires_at
+        .and_then(|v| DateTime::from_timestamp(v.as_secs() as _, 0))
+        .unwrap_or_else(Utc::now);
+
+    // expired tokens are rejected anyway, no need to keep them
+    sqlx::query("DELETE FROM revoked_tokens WHERE expires_at < NOW()")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        "
+        INSERT INTO revoked_tokens (jti, user_id, expires_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (jti) DO NOTHING
+        ",
+    )
+    .bind(jti)
+    .bind(claims.custom.id)
+    .bind(expires_at)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Revoke every token and refresh token issued to the user so far
+pub async fn revoke_user_tokens(pool: &PgPool, user_id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("SELECT revoke_user_tokens($1, $2)")
+        .bind(user_id)
+        .bind(revocation_cutoff(Utc::now()))
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// The cut-off comes from the clock signing the tokens, not from the database one, so a token
+// signed right after a revocation isn't rejected when the database clock is ahead. It's cut to
+// the millisecond precision of `jti`, a token signed within the same millisecond is kept.
+fn revocation_cutoff(now: DateTime<Utc>) -> DateTime<Utc> {
+    DateTime::from_timestamp_millis(now.timestamp_millis()).unwrap_or(now)
+}
+
+// `iat` only has second precision, but `jti` is a uuid v7 which carries the issue time in
+// milliseconds, so a token signed right after a revocation isn't rejected with the old ones.
+fn issued_at(claims: &JWTClaims<User>) -> DateTime<Utc> {
+    let from_jti = claims
+        .jwt_id
+        .as_deref()
+        .and_then(|v| Uuid::parse_str(v).ok())
+        .and_then(|v| v.get_timestamp())
+        .and_then(|v| {
+            let (secs, nanos) = v.to_unix();
+            DateTime::from_timestamp(secs as _, nanos)
+        });
+
+    from_jti.unwrap_or_else(|| {
+        let secs = claims.issued_at.map(|v| v.as_secs()).unwrap_or_default();
+        DateTime::from_timestamp(secs as _, 0).unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_signed_after_revocation_should_not_be_cut_off() {
+        let cutoff = revocation_cutoff(Utc::now());
+        assert_eq!(cutoff.timestamp_subsec_micros() % 1000, 0);
+
+        let (secs, nanos) = Uuid::now_v7().get_timestamp().unwrap().to_unix();
+        let issued_at = DateTime::from_timestamp(secs as _, nanos).unwrap();
+        assert!(issued_at >= cutoff);
+    }
+}
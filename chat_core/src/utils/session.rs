@@ -0,0 +1,27 @@
+use sqlx::PgPool;
+use std::net::IpAddr;
+use uuid::Uuid;
+
+/// Sessions are seen at most this often, so that busy clients don't write on every request
+const SESSION_SEEN_INTERVAL: i64 = 60;
+
+/// Record that a session was just used, from the given ip if known
+pub async fn touch_session(
+    pool: &PgPool,
+    session_id: Uuid,
+    ip: Option<IpAddr>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "
+        UPDATE sessions
+        SET last_seen_at = NOW(), ip = COALESCE($2, ip)
+        WHERE id = $1 AND last_seen_at < NOW() - make_interval(secs => $3)
+        ",
+    )
+    .bind(session_id)
+    .bind(ip.map(|ip| ip.to_string()))
+    .bind(SESSION_SEEN_INTERVAL as f64)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
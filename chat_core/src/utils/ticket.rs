@@ -0,0 +1,37 @@
+use crate::User;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+/// Opaque tokens are only stored hashed
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Exchange a single-use ticket for the user it was issued to, in the workspace of the token
+/// it was taken with. `None` if it is unknown, expired or already used, or if the user isn't an
+/// active member of that workspace anymore.
+pub async fn redeem_ticket(pool: &PgPool, ticket: &str) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as(
+        "
+        WITH t AS (
+            DELETE FROM event_tickets
+            WHERE ticket_hash = $1 AND expires_at > NOW()
+            RETURNING user_id, ws_id, session_id
+        )
+        SELECT u.id, t.ws_id, u.fullname, u.email, u.created_at,
+            COALESCE(m.role, u.role) AS role, t.session_id AS sid
+        FROM t
+        JOIN users u ON u.id = t.user_id
+        -- users waiting in workspace 0 aren't members of it
+        LEFT JOIN workspace_members m
+            ON m.user_id = t.user_id AND m.ws_id = t.ws_id AND m.deactivated_at IS NULL
+        WHERE (m.user_id IS NOT NULL OR (t.ws_id = 0 AND u.ws_id = 0))
+            AND NOT EXISTS (
+                SELECT 1 FROM sessions s WHERE s.id = t.session_id AND s.revoked_at IS NOT NULL
+            )
+        ",
+    )
+    .bind(hash_token(ticket))
+    .fetch_optional(pool)
+    .await
+}
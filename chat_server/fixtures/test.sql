@@ -12,6 +12,9 @@ VALUES
 (1, 'Charlie Test', 'Charlie@123.com', '$argon2id$v=19$m=19456,t=2,p=1$MxGhY+ib/kplwBPLa7u2ug$c5h9u7Sc8Px8J5+qgNdOjSY7ZJO2QN4rugKpapGW4XU'),
 (1, 'Daisy Test', 'Daisy@123.com', '$argon2id$v=19$m=19456,t=2,p=1$MxGhY+ib/kplwBPLa7u2ug$c5h9u7Sc8Px8J5+qgNdOjSY7ZJO2QN4rugKpapGW4XU');
 
+-- test users have verified their email
+UPDATE users SET email_verified_at = NOW();
+
 -- insert 4 chats
 INSERT INTO chats (ws_id, name, type, members)
     VALUES (1, 'general', 'public_channel', '{1, 2, 3, 4, 5}'),
@@ -1,4 +1,6 @@
 use anyhow::{Result, bail};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chat_core::{CookiePolicy, DecodingKey, EncodingKey, TokenPolicy};
 use serde::{Deserialize, Serialize};
 use std::{env, fs::File, path::PathBuf};
 
@@ -6,12 +8,126 @@ use std::{env, fs::File, path::PathBuf};
 pub struct AppConfig {
     pub server: ServerConfig,
     pub auth: AuthConfig,
+    #[serde(default)]
+    pub mailer: MailerConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthConfig {
+    /// active signing key
     pub sk: String,
+    /// public key of the active signing key
     pub pk: String,
+    /// public keys of retired signing keys, still accepted until their tokens expire
+    #[serde(default)]
+    pub verification_keys: Vec<String>,
+    /// lifetime, issuer, audiences and leeway of access tokens
+    #[serde(default)]
+    pub token: TokenPolicy,
+    /// lifetime of refresh tokens, in seconds
+    #[serde(default = "default_refresh_duration")]
+    pub refresh_duration: u64,
+    /// lifetime of password reset tokens, in seconds
+    #[serde(default = "default_password_reset_duration")]
+    pub password_reset_duration: u64,
+    /// lifetime of email verification tokens, in seconds
+    #[serde(default = "default_email_verification_duration")]
+    pub email_verification_duration: u64,
+    /// lifetime of the challenge between the password and the 2fa signin steps, in seconds
+    #[serde(default = "default_signin_challenge_duration")]
+    pub signin_challenge_duration: u64,
+    /// where identity providers send the browser back to after an sso login
+    #[serde(default = "default_sso_redirect_url")]
+    pub sso_redirect_url: String,
+    /// how long an sso login can take at the identity provider, in seconds
+    #[serde(default = "default_sso_login_duration")]
+    pub sso_login_duration: u64,
+    /// lifetime of workspace invites when not given, in seconds
+    #[serde(default = "default_invite_duration")]
+    pub invite_duration: u64,
+    /// lifetime of the tickets notify_server takes for `/events`, in seconds
+    #[serde(default = "default_event_ticket_duration")]
+    pub event_ticket_duration: u64,
+    /// lifetime of impersonation tokens, in seconds
+    #[serde(default = "default_impersonation_duration")]
+    pub impersonation_duration: u64,
+    /// accept access tokens in `?token=`, they end up in access logs and traces
+    #[serde(default)]
+    pub allow_query_token: bool,
+    /// cookie sessions for browsers
+    #[serde(default)]
+    pub cookie: CookiePolicy,
+    /// unverified users can't send messages or join an existing workspace
+    #[serde(default)]
+    pub require_verified_email: bool,
+    /// delays and lockouts after failed signins
+    #[serde(default)]
+    pub signin_limit: SigninLimitConfig,
+    /// strength rules for new passwords
+    #[serde(default)]
+    pub password_policy: PasswordPolicy,
+    /// cost of password hashes
+    #[serde(default)]
+    pub argon2: Argon2Config,
+}
+
+/// Cost of the argon2id password hashes, hashes made with other ones are redone on signin
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Argon2Config {
+    /// memory used, in KiB
+    pub memory_cost: u32,
+    /// number of passes
+    pub time_cost: u32,
+    /// degree of parallelism
+    pub parallelism: u32,
+}
+
+/// What a new password must look like
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    /// at least one character which is neither a letter nor a digit
+    pub require_symbol: bool,
+}
+
+/// How failed signins are throttled, per account and per source ip
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SigninLimitConfig {
+    /// failures before an account is locked
+    pub max_failures: u32,
+    /// failures before an ip is locked, higher as many users can share one
+    pub max_ip_failures: u32,
+    /// how long a lockout lasts, also how long failures are remembered, in seconds
+    pub lockout_duration: u64,
+    /// wait after the first failure, doubled after each following one, in seconds
+    pub delay: u64,
+    /// longest wait between two attempts, in seconds
+    pub max_delay: u64,
+}
+
+/// How mails are delivered
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MailerConfig {
+    Smtp {
+        host: String,
+        #[serde(default = "default_smtp_port")]
+        port: u16,
+        #[serde(default)]
+        username: Option<String>,
+        #[serde(default)]
+        password: Option<String>,
+        from: String,
+    },
+    /// write mails to a directory and log them, for tests and local development
+    File { dir: PathBuf },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -19,6 +135,122 @@ pub struct ServerConfig {
     pub port: u16,
     pub db_url: String,
     pub base_dir: PathBuf,
+    /// public url of the web app, used for links in mails
+    #[serde(default = "default_web_url")]
+    pub web_url: String,
+}
+
+fn default_refresh_duration() -> u64 {
+    60 * 60 * 24 * 30
+}
+
+fn default_password_reset_duration() -> u64 {
+    60 * 60
+}
+
+fn default_email_verification_duration() -> u64 {
+    60 * 60 * 24
+}
+
+fn default_signin_challenge_duration() -> u64 {
+    60 * 5
+}
+
+fn default_sso_redirect_url() -> String {
+    "http://localhost:6688/api/sso/callback".to_string()
+}
+
+fn default_sso_login_duration() -> u64 {
+    60 * 10
+}
+
+fn default_invite_duration() -> u64 {
+    60 * 60 * 24 * 7
+}
+
+fn default_event_ticket_duration() -> u64 {
+    30
+}
+
+fn default_impersonation_duration() -> u64 {
+    60 * 10
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+fn default_web_url() -> String {
+    "http://localhost:6688".to_string()
+}
+
+impl Default for SigninLimitConfig {
+    fn default() -> Self {
+        Self {
+            max_failures: 5,
+            max_ip_failures: 50,
+            lockout_duration: 60 * 15,
+            delay: 1,
+            max_delay: 30,
+        }
+    }
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            max_length: 128,
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+        }
+    }
+}
+
+impl Default for Argon2Config {
+    fn default() -> Self {
+        Self {
+            memory_cost: Params::DEFAULT_M_COST,
+            time_cost: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl Default for MailerConfig {
+    fn default() -> Self {
+        Self::File {
+            dir: env::temp_dir().join("chat_server_mails"),
+        }
+    }
+}
+
+impl Argon2Config {
+    pub fn params(&self) -> Result<Params, argon2::Error> {
+        Params::new(self.memory_cost, self.time_cost, self.parallelism, None)
+    }
+
+    pub fn hasher(&self) -> Result<Argon2<'static>, argon2::Error> {
+        Ok(Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            self.params()?,
+        ))
+    }
+}
+
+impl AuthConfig {
+    pub fn load_encoding_key(&self) -> Result<EncodingKey, jwt_simple::Error> {
+        Ok(EncodingKey::load(&self.sk)?.with_policy(self.token.clone()))
+    }
+
+    /// Keyset of the active public key and all retired ones
+    pub fn load_decoding_key(&self) -> Result<DecodingKey, jwt_simple::Error> {
+        let pems = std::iter::once(&self.pk).chain(&self.verification_keys);
+        Ok(DecodingKey::load_all(pems.map(String::as_str))?.with_policy(self.token.clone()))
+    }
 }
 
 impl AppConfig {
@@ -1,4 +1,8 @@
-use axum::{Json, http::StatusCode, response::IntoResponse};
+use axum::{
+    Json,
+    http::{HeaderValue, StatusCode, header::RETRY_AFTER},
+    response::IntoResponse,
+};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use utoipa::ToSchema;
@@ -6,6 +10,16 @@ use utoipa::ToSchema;
 #[derive(Debug, Serialize, ToSchema, Deserialize)]
 pub struct ErrorOutput {
     pub error: String,
+    /// invalid fields of the request, if any
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldError>,
+}
+
+/// why a field of the request was rejected
+#[derive(Debug, Clone, Serialize, ToSchema, Deserialize, PartialEq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
 }
 
 #[derive(Debug, Error)]
@@ -13,6 +27,12 @@ pub enum AppError {
     #[error("email already exists: {0}")]
     EmailAleardyExists(String),
 
+    #[error("workspace already exists: {0}")]
+    WorkspaceAlreadyExists(String),
+
+    #[error("invalid input: {}", .0.iter().map(|e| e.field.as_str()).collect::<Vec<_>>().join(", "))]
+    InvalidInput(Vec<FieldError>),
+
     #[error("create chat error: {0}")]
     CreateChatError(String),
 
@@ -22,6 +42,72 @@ pub enum AppError {
     #[error("{0}")]
     ChatFileError(String),
 
+    #[error("invalid refresh token: {0}")]
+    InvalidRefreshToken(String),
+
+    #[error("token revoked")]
+    TokenRevoked,
+
+    #[error("invalid password reset token")]
+    InvalidResetToken,
+
+    #[error("invalid email verification token")]
+    InvalidVerificationToken,
+
+    #[error("email not verified")]
+    EmailNotVerified,
+
+    #[error("user deactivated")]
+    UserDeactivated,
+
+    #[error("wrong password")]
+    WrongPassword,
+
+    #[error("too many failed signins, retry in {0} seconds")]
+    TooManySigninAttempts(u64),
+
+    #[error("missing or invalid csrf token")]
+    InvalidCsrfToken,
+
+    #[error("invalid two-factor code")]
+    InvalidTwoFactorCode,
+
+    #[error("invalid signin challenge")]
+    InvalidSigninChallenge,
+
+    #[error("two-factor authentication required by the workspace")]
+    TwoFactorRequired,
+
+    #[error("two-factor error: {0}")]
+    TwoFactorError(String),
+
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+
+    #[error("sso error: {0}")]
+    SsoError(String),
+
+    #[error("identity provider error: {0}")]
+    IdpError(String),
+
+    #[error("an invite is needed to join workspace {0}")]
+    InviteRequired(String),
+
+    #[error("invalid invite")]
+    InvalidInvite,
+
+    #[error("invite error: {0}")]
+    InviteError(String),
+
+    #[error("invalid api key")]
+    InvalidApiKey,
+
+    #[error("api key error: {0}")]
+    ApiKeyError(String),
+
+    #[error("mail error: {0}")]
+    MailError(String),
+
     #[error("not found error: {0}")]
     NotFound(String),
 
@@ -36,11 +122,26 @@ pub enum AppError {
 
     #[error("jwt error: {0}")]
     JwtError(#[from] jwt_simple::Error),
+
+    #[error("invalid header value: {0}")]
+    InvalidHeaderValue(#[from] axum::http::header::InvalidHeaderValue),
 }
 
 impl ErrorOutput {
     pub fn new(error: String) -> Self {
-        Self { error }
+        Self {
+            error,
+            fields: vec![],
+        }
+    }
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: impl Into<String>) -> Self {
+        Self {
+            field: field.to_string(),
+            message: message.into(),
+        }
     }
 }
 
@@ -48,16 +149,53 @@ impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
         let status = match &self {
             Self::EmailAleardyExists(_) => StatusCode::CONFLICT,
+            Self::WorkspaceAlreadyExists(_) => StatusCode::CONFLICT,
+            Self::InvalidInput(_) => StatusCode::UNPROCESSABLE_ENTITY,
             Self::CreateChatError(_) | Self::CreateMessageError(_) | Self::ChatFileError(_) => {
                 StatusCode::BAD_REQUEST
             }
+            Self::InvalidRefreshToken(_) => StatusCode::FORBIDDEN,
+            Self::TokenRevoked => StatusCode::FORBIDDEN,
+            Self::InvalidResetToken => StatusCode::FORBIDDEN,
+            Self::InvalidVerificationToken => StatusCode::FORBIDDEN,
+            Self::EmailNotVerified => StatusCode::FORBIDDEN,
+            Self::UserDeactivated => StatusCode::FORBIDDEN,
+            Self::WrongPassword => StatusCode::FORBIDDEN,
+            Self::TooManySigninAttempts(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::InvalidCsrfToken => StatusCode::FORBIDDEN,
+            Self::InvalidTwoFactorCode => StatusCode::FORBIDDEN,
+            Self::InvalidSigninChallenge => StatusCode::FORBIDDEN,
+            Self::TwoFactorRequired => StatusCode::FORBIDDEN,
+            Self::TwoFactorError(_) => StatusCode::BAD_REQUEST,
+            Self::PermissionDenied(_) => StatusCode::FORBIDDEN,
+            Self::SsoError(_) => StatusCode::FORBIDDEN,
+            Self::IdpError(_) => StatusCode::BAD_GATEWAY,
+            Self::InviteRequired(_) => StatusCode::FORBIDDEN,
+            Self::InvalidInvite => StatusCode::FORBIDDEN,
+            Self::InviteError(_) => StatusCode::BAD_REQUEST,
+            Self::InvalidApiKey => StatusCode::FORBIDDEN,
+            Self::ApiKeyError(_) => StatusCode::BAD_REQUEST,
+            Self::MailError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::NotFound(_) => StatusCode::NOT_FOUND,
             Self::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::SqlxError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::Argon2Error(_) => StatusCode::UNPROCESSABLE_ENTITY,
             Self::JwtError(_) => StatusCode::FORBIDDEN,
+            Self::InvalidHeaderValue(_) => StatusCode::INTERNAL_SERVER_ERROR,
         };
 
-        (status, Json(ErrorOutput::new(self.to_string()))).into_response()
+        let retry_after = match &self {
+            Self::TooManySigninAttempts(secs) => Some(HeaderValue::from(*secs)),
+            _ => None,
+        };
+        let mut output = ErrorOutput::new(self.to_string());
+        if let Self::InvalidInput(fields) = self {
+            output.fields = fields;
+        }
+        let mut res = (status, Json(output)).into_response();
+        if let Some(retry_after) = retry_after {
+            res.headers_mut().insert(RETRY_AFTER, retry_after);
+        }
+        res
     }
 }
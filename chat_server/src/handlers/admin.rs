@@ -0,0 +1,38 @@
+use crate::{AppError, AppState, error::ErrorOutput, models::ImpersonationOutput};
+use axum::{
+    Extension, Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use chat_core::User;
+
+/// Act as a user, for system admins reproducing what the user sees
+#[utoipa::path(
+    post,
+    path = "/api/admin/users/{id}/impersonate",
+    params(
+        ("id" = i64, Path, description = "User id")
+    ),
+    responses(
+        (status = 201, description = "Impersonation token issued", body = ImpersonationOutput),
+        (status = 403, description = "Not a system admin, or the user is an admin", body = ErrorOutput),
+        (status = 404, description = "User not found", body = ErrorOutput)
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+/// - The token carries the id of the admin in `impersonatedBy`, expires within minutes and
+///   can't be refreshed.
+/// - Every request made with it is logged, and it can't change the password, 2fa or api keys,
+///   list or end the sessions of the user, or take event tickets.
+/// - System admins and workspace admins can't be impersonated.
+pub(crate) async fn impersonate_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let ret = state.impersonate(&user, id).await?;
+    Ok((StatusCode::CREATED, Json(ret)))
+}
@@ -1,37 +1,69 @@
 use crate::{
     AppError, AppState,
     error::ErrorOutput,
-    models::{CreateUser, SigninUser},
+    models::{
+        ChangePassword, CreateUser, EventTicket, ForgotPassword, LogoutUser, RefreshToken,
+        ResetPassword, Session, SigninChallenge, SigninUser, VerifyEmail, VerifySigninChallenge,
+        generate_token,
+    },
+};
+use axum::{
+    Extension, Json,
+    extract::{Path, State},
+    http::{HeaderMap, HeaderValue, Method, StatusCode, header::SET_COOKIE},
+    response::{IntoResponse, Response},
+};
+use axum_extra::{
+    TypedHeader,
+    headers::{Authorization, authorization::Bearer},
+};
+use chat_core::{
+    Jwks, User,
+    middlewares::{ClientInfo, TokenVerify},
+    revoke_token, revoke_user_tokens,
 };
-use axum::{Json, extract::State, http::StatusCode, response::IntoResponse};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
+use uuid::Uuid;
 
 #[derive(Debug, Serialize, ToSchema, Deserialize)]
 pub struct AuthOutput {
-    token: String,
+    /// Short-lived access token
+    pub(crate) token: String,
+    /// Long-lived refresh token, single use
+    pub(crate) refresh_token: String,
 }
 
 #[utoipa::path(
     post,
     path = "/api/signup",
     responses(
-        (status = 201, description = "User created", body = AuthOutput)
+        (status = 201, description = "User created", body = AuthOutput),
+        (status = 403, description = "Missing or invalid invite", body = ErrorOutput),
+        (status = 409, description = "Email already exists", body = ErrorOutput),
+        (status = 422, description = "Invalid fields, listed in `fields`", body = ErrorOutput)
     )
 )]
 /// Create a new user in the chat system with email, password, workspace, and fullname.
 ///
+/// - The email is lower-cased, and the fields are checked against the length limits and the
+///   password policy: every invalid field is listed in a 422 response.
 /// - If the email already exists, it will return 409.
-/// - Otherwise, it will return 201 with a token.
-/// - If the workspace doesn't exist, it will create one.
+/// - Otherwise, it will return 201 with an access token and a refresh token.
+/// - If the workspace doesn't exist, it will create one and the user owns it.
+/// - Joining an existing workspace needs an invite, unless the workspace lets in the email
+///   domain: the user then joins once the email is verified.
+/// - A verification link is mailed, when unverified users are blocked the user only joins an
+///   existing workspace once the email is verified.
 pub(crate) async fn signup_handler(
     State(state): State<AppState>,
+    client: ClientInfo,
     Json(input): Json<CreateUser>,
 ) -> Result<impl IntoResponse, AppError> {
     let user = state.create_user(&input).await?;
-    let token = state.ek.sign(user)?;
-    let body = Json(AuthOutput::new(&token));
-    Ok((StatusCode::CREATED, body))
+    state.send_email_verification(&user).await?;
+    let output = AuthOutput::new_session(&state, user, &client).await?;
+    output.respond(&state, StatusCode::CREATED)
 }
 
 /// Sign in a user with email and password
@@ -39,41 +71,450 @@ pub(crate) async fn signup_handler(
     post,
     path = "/api/signin",
     responses(
-        (status = 200, description = "User signed in", body = AuthOutput)
+        (status = 200, description = "User signed in", body = AuthOutput),
+        (status = 202, description = "Password accepted, 2fa code needed", body = SigninChallenge),
+        (status = 429, description = "Too many failed signins, see Retry-After", body = ErrorOutput)
     )
 )]
 /// User signin
+///
+/// - Users with 2fa enabled get a challenge instead of tokens, to be completed at `/api/signin/2fa`.
+/// - Failed signins are counted per account and per ip: each one makes the next attempt wait
+///   longer, and past a threshold the account or ip is locked for a while.
+/// - A completed signin resets the account counter, with 2fa only once the code is accepted.
+/// - With cookie sessions the tokens are also set in `HttpOnly` cookies, along with a CSRF
+///   cookie whose value must be sent back in the CSRF header on changes.
 pub(crate) async fn signin_handler(
     State(state): State<AppState>,
+    client: ClientInfo,
     Json(input): Json<SigninUser>,
 ) -> Result<impl IntoResponse, AppError> {
+    let ip = client.ip;
+    state.check_signin_allowed(&input.email, ip).await?;
     let user = state.verify_user(&input).await?;
     match user {
+        Some(user) if state.is_totp_enabled(user.id).await? => {
+            let challenge = state.create_signin_challenge(&user).await?;
+            let body = Json(SigninChallenge { challenge });
+            Ok((StatusCode::ACCEPTED, body).into_response())
+        }
         Some(user) => {
-            let token = state.ek.sign(user)?;
-            let body = Json(AuthOutput::new(&token));
-            Ok((StatusCode::OK, body).into_response())
+            state.reset_signin_failures(&input.email).await?;
+            let output = AuthOutput::new_session(&state, user, &client).await?;
+            output.respond(&state, StatusCode::OK)
         }
         None => {
+            state.record_signin_failure(&input.email, ip).await?;
             let body = Json(ErrorOutput::new("Invalid email or password".to_string()));
             Ok((StatusCode::FORBIDDEN, body).into_response())
         }
     }
 }
 
+/// Complete a signin with a 2fa code
+#[utoipa::path(
+    post,
+    path = "/api/signin/2fa",
+    responses(
+        (status = 200, description = "User signed in", body = AuthOutput),
+        (status = 403, description = "Invalid code or challenge", body = ErrorOutput),
+        (status = 429, description = "Too many failed signins, see Retry-After", body = ErrorOutput)
+    )
+)]
+/// - The code is either from the authenticator app or a recovery code.
+/// - Wrong codes count as failed signins of the account, like wrong passwords.
+pub(crate) async fn signin_two_factor_handler(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(input): Json<VerifySigninChallenge>,
+) -> Result<impl IntoResponse, AppError> {
+    let email = state.find_signin_challenge_email(&input.challenge).await?;
+    if let Some(email) = &email {
+        state.check_signin_allowed(email, client.ip).await?;
+    }
+    let user = match state.verify_signin_challenge(&input).await {
+        Ok(user) => user,
+        Err(e) => {
+            if let (AppError::InvalidTwoFactorCode, Some(email)) = (&e, &email) {
+                state.record_signin_failure(email, client.ip).await?;
+            }
+            return Err(e);
+        }
+    };
+    state.reset_signin_failures(&user.email).await?;
+    let output = AuthOutput::new_session(&state, user, &client).await?;
+    output.respond(&state, StatusCode::OK)
+}
+
+/// Exchange a refresh token for a new token pair
+#[utoipa::path(
+    post,
+    path = "/api/refresh",
+    request_body(content = Option<RefreshToken>),
+    responses(
+        (status = 200, description = "Token refreshed", body = AuthOutput),
+        (status = 403, description = "Invalid, expired or reused refresh token, or bad csrf token", body = ErrorOutput)
+    )
+)]
+/// - The refresh token is single use, the response carries its replacement.
+/// - Reusing a refresh token revokes every token issued from the same signin.
+/// - With cookie sessions the body can be left out, the refresh token is then read from its
+///   cookie and the CSRF header is required.
+pub(crate) async fn refresh_handler(
+    State(state): State<AppState>,
+    method: Method,
+    headers: HeaderMap,
+    input: Option<Json<RefreshToken>>,
+) -> Result<impl IntoResponse, AppError> {
+    let refresh_token = match input {
+        Some(Json(input)) => input.refresh_token,
+        None => refresh_token_from_cookie(&state, &method, &headers)?,
+    };
+    let (user, refresh_token) = state.rotate_refresh_token(&refresh_token).await?;
+    let output = AuthOutput::try_new(&state, user, refresh_token)?;
+    output.respond(&state, StatusCode::OK)
+}
+
+/// Log out the current device
+#[utoipa::path(
+    post,
+    path = "/api/logout",
+    request_body(content = Option<LogoutUser>),
+    responses(
+        (status = 204, description = "User logged out")
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+/// - The access token used for this request is revoked.
+/// - If a refresh token is given, it can't be used anymore either.
+/// - With cookie sessions both tokens are taken from the cookies, which are removed.
+pub(crate) async fn logout_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    headers: HeaderMap,
+    input: Option<Json<LogoutUser>>,
+) -> Result<impl IntoResponse, AppError> {
+    let cookie = state.cookie_policy();
+    let token = match bearer {
+        Some(TypedHeader(Authorization(bearer))) => Some(bearer.token().to_string()),
+        None => cookie.and_then(|policy| policy.token(&headers)),
+    };
+    if let Some(token) = token {
+        let claims = state.dk.decode(&token)?;
+        revoke_token(&state.pool, &claims).await?;
+    }
+    let refresh_token = input
+        .and_then(|Json(input)| input.refresh_token)
+        .or_else(|| cookie.and_then(|policy| policy.refresh_token(&headers)));
+    if let Some(refresh_token) = refresh_token {
+        state.revoke_refresh_token(user.id, &refresh_token).await?;
+    }
+    clear_auth_cookies(&state, StatusCode::NO_CONTENT)
+}
+
+/// Log out all devices of the user
+#[utoipa::path(
+    post,
+    path = "/api/logout/all",
+    responses(
+        (status = 204, description = "User logged out of all devices")
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+/// Every access token and refresh token issued to the user so far is revoked.
+pub(crate) async fn logout_all_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    revoke_user_tokens(&state.pool, user.id).await?;
+    clear_auth_cookies(&state, StatusCode::NO_CONTENT)
+}
+
+/// List the sessions of the current user
+#[utoipa::path(
+    get,
+    path = "/api/me/sessions",
+    responses(
+        (status = 200, description = "Signed in devices", body = Vec<Session>)
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+/// - There is one session per signin, refreshing the tokens keeps it going.
+/// - The device is told by its user agent and last known ip, `current` marks this device.
+pub(crate) async fn list_sessions_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let sessions = state.list_sessions(&user).await?;
+    Ok(Json(sessions))
+}
+
+/// Log out a session of the current user
+#[utoipa::path(
+    delete,
+    path = "/api/me/sessions/{id}",
+    params(
+        ("id" = String, Path, description = "Session id")
+    ),
+    responses(
+        (status = 204, description = "Session logged out"),
+        (status = 404, description = "Session not found", body = ErrorOutput)
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+/// Both the refresh token and the access tokens of the session stop working.
+pub(crate) async fn revoke_session_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    state.revoke_session(&user, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Take a ticket to connect to notify_server
+#[utoipa::path(
+    post,
+    path = "/api/events/tickets",
+    responses(
+        (status = 201, description = "Ticket issued", body = EventTicket)
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+/// `EventSource` can't set headers: connect to `/events?ticket=...` of notify_server instead of
+/// putting the access token in the url. The ticket is single use and expires within seconds.
+pub(crate) async fn create_event_ticket_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let ticket = state.create_event_ticket(&user).await?;
+    Ok((StatusCode::CREATED, Json(ticket)))
+}
+
+/// Ask for a password reset link by mail
+#[utoipa::path(
+    post,
+    path = "/api/password/forgot",
+    responses(
+        (status = 202, description = "Reset link mailed if the email is known")
+    )
+)]
+/// The response doesn't tell whether the email belongs to a user.
+pub(crate) async fn forgot_password_handler(
+    State(state): State<AppState>,
+    Json(input): Json<ForgotPassword>,
+) -> Result<impl IntoResponse, AppError> {
+    state.send_password_reset(&input).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Set a new password with the token from the reset mail
+#[utoipa::path(
+    post,
+    path = "/api/password/reset",
+    responses(
+        (status = 204, description = "Password changed"),
+        (status = 403, description = "Invalid, expired or used reset token", body = ErrorOutput)
+    )
+)]
+/// Every session of the user is logged out.
+pub(crate) async fn reset_password_handler(
+    State(state): State<AppState>,
+    Json(input): Json<ResetPassword>,
+) -> Result<impl IntoResponse, AppError> {
+    state.reset_password(&input).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Change the password of the current user
+#[utoipa::path(
+    post,
+    path = "/api/me/password",
+    responses(
+        (status = 200, description = "Password changed, new tokens for this device", body = AuthOutput),
+        (status = 403, description = "Wrong old password", body = ErrorOutput),
+        (status = 422, description = "New password rejected by the policy", body = ErrorOutput)
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+/// - Every session of the user is logged out, this device gets a new token pair.
+/// - Wrong old passwords count as failed signins.
+pub(crate) async fn change_password_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(input): Json<ChangePassword>,
+) -> Result<impl IntoResponse, AppError> {
+    state.change_password(&user, &input).await?;
+    revoke_user_tokens(&state.pool, user.id).await?;
+    let output = AuthOutput::new_session(&state, user, &client).await?;
+    output.respond(&state, StatusCode::OK)
+}
+
+/// Verify the email of a user with the token from the verification mail
+#[utoipa::path(
+    post,
+    path = "/api/email/verify",
+    responses(
+        (status = 204, description = "Email verified"),
+        (status = 403, description = "Invalid, expired or used verification token", body = ErrorOutput)
+    )
+)]
+/// A workspace waiting for the verification is joined, refresh the token to switch to it.
+pub(crate) async fn verify_email_handler(
+    State(state): State<AppState>,
+    Json(input): Json<VerifyEmail>,
+) -> Result<impl IntoResponse, AppError> {
+    state.verify_email(&input).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Mail a new verification link to the current user
+#[utoipa::path(
+    post,
+    path = "/api/email/resend",
+    responses(
+        (status = 202, description = "Verification link mailed if the email isn't verified yet")
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+pub(crate) async fn resend_email_verification_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    state.resend_email_verification(user.id).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Public keys used to verify tokens, other services load or poll them
+#[utoipa::path(
+    get,
+    path = "/.well-known/jwks.json",
+    responses(
+        (status = 200, description = "JSON Web Key Set", body = Jwks)
+    )
+)]
+pub(crate) async fn jwks_handler(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.dk.jwks())
+}
+
 impl AuthOutput {
-    pub fn new(token: &str) -> Self {
-        Self {
-            token: token.to_string(),
+    /// Start a new session for a signin and issue its first token pair
+    pub(crate) async fn new_session(
+        state: &AppState,
+        user: User,
+        client: &ClientInfo,
+    ) -> Result<Self, AppError> {
+        let (sid, refresh_token) = state.create_session(user.id, client).await?;
+        let user = User {
+            sid: Some(sid),
+            ..user
+        };
+        Self::try_new(state, user, refresh_token)
+    }
+
+    /// Respond with the token pair, also set in cookies with cookie sessions
+    pub(crate) fn respond(
+        self,
+        state: &AppState,
+        status: StatusCode,
+    ) -> Result<Response, AppError> {
+        let mut res = (status, Json(&self)).into_response();
+        if let Some(policy) = state.cookie_policy() {
+            let auth = &state.config.auth;
+            let cookies = [
+                policy.set_cookie(&policy.name, &self.token, auth.token.duration, true),
+                policy.set_cookie(
+                    &policy.refresh_name,
+                    &self.refresh_token,
+                    auth.refresh_duration,
+                    true,
+                ),
+                policy.set_cookie(
+                    &policy.csrf_name,
+                    &generate_token(),
+                    auth.refresh_duration,
+                    false,
+                ),
+            ];
+            append_cookies(&mut res, cookies)?;
         }
+        Ok(res)
     }
+
+    pub(crate) fn try_new(
+        state: &AppState,
+        user: User,
+        refresh_token: String,
+    ) -> Result<Self, AppError> {
+        let token = state.ek.sign(user)?;
+        Ok(Self {
+            token,
+            refresh_token,
+        })
+    }
+}
+
+fn refresh_token_from_cookie(
+    state: &AppState,
+    method: &Method,
+    headers: &HeaderMap,
+) -> Result<String, AppError> {
+    let policy = state
+        .cookie_policy()
+        .ok_or_else(|| AppError::InvalidRefreshToken("refresh token not found".to_string()))?;
+    if !policy.verify_csrf(method, headers) {
+        return Err(AppError::InvalidCsrfToken);
+    }
+    policy
+        .refresh_token(headers)
+        .ok_or_else(|| AppError::InvalidRefreshToken("refresh token not found".to_string()))
+}
+
+fn clear_auth_cookies(state: &AppState, status: StatusCode) -> Result<Response, AppError> {
+    let mut res = status.into_response();
+    if let Some(policy) = state.cookie_policy() {
+        let cookies = [&policy.name, &policy.refresh_name, &policy.csrf_name]
+            .map(|name| policy.set_cookie(name, "", 0, true));
+        append_cookies(&mut res, cookies)?;
+    }
+    Ok(res)
+}
+
+fn append_cookies(
+    res: &mut Response,
+    cookies: impl IntoIterator<Item = String>,
+) -> Result<(), AppError> {
+    for cookie in cookies {
+        res.headers_mut()
+            .append(SET_COOKIE, HeaderValue::from_str(&cookie)?);
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::get_router;
     use anyhow::Result;
+    use axum::{body::Body, extract::Request};
     use http_body_util::BodyExt;
+    use tower::ServiceExt;
 
     #[tokio::test]
     async fn signup_should_work() -> Result<()> {
@@ -81,11 +522,11 @@ mod tests {
 
         let fullname = "TeamMeng";
         let email = "TeamMeng@123.com";
-        let password = "123456";
+        let password = "12345678";
 
-        let input = CreateUser::new(fullname, "none", email, password);
+        let input = CreateUser::new(fullname, "new-ws", email, password);
 
-        let ret = signup_handler(State(state), Json(input))
+        let ret = signup_handler(State(state.clone()), ClientInfo::default(), Json(input))
             .await?
             .into_response();
 
@@ -94,6 +535,18 @@ mod tests {
         let body = ret.into_body().collect().await?.to_bytes();
         let ret: AuthOutput = serde_json::from_slice(&body)?;
         assert_ne!(ret.token, "");
+        assert_ne!(ret.refresh_token, "");
+
+        // a verification link is mailed
+        let mails = state.read_mails(email);
+        assert_eq!(mails.len(), 1);
+        let input = VerifyEmail {
+            token: mails[0].link_token(),
+        };
+        let ret = verify_email_handler(State(state), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NO_CONTENT);
 
         Ok(())
     }
@@ -104,19 +557,38 @@ mod tests {
 
         let fullname = "TeamTest";
         let email = "Test@123.com";
-        let password = "123456";
+        let password = "12345678";
         let workspace = "acme";
 
         let input = CreateUser::new(fullname, workspace, email, password);
 
-        let ret = signup_handler(State(state), Json(input.clone()))
+        let ret = signup_handler(State(state), ClientInfo::default(), Json(input.clone()))
             .await
             .into_response();
 
         assert_eq!(ret.status(), StatusCode::CONFLICT);
         let body = ret.into_body().collect().await?.to_bytes();
         let ret: ErrorOutput = serde_json::from_slice(&body)?;
-        assert_eq!(ret.error, format!("email already exists: {}", email));
+        assert_eq!(ret.error, "email already exists: test@123.com");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn signup_with_invalid_input_should_422() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let input = CreateUser::new("TeamMeng", "new-ws", "not-an-email", "1");
+        let ret = signup_handler(State(state), ClientInfo::default(), Json(input))
+            .await
+            .into_response();
+
+        assert_eq!(ret.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let ret: ErrorOutput = serde_json::from_slice(&body)?;
+        assert_eq!(ret.error, "invalid input: email, password");
+        assert_eq!(ret.fields[0].field, "email");
+        assert_eq!(ret.fields[0].message, "must be a valid email address");
 
         Ok(())
     }
@@ -129,7 +601,7 @@ mod tests {
         let password = "123456";
 
         let input = SigninUser::new(email, password);
-        let ret = signin_handler(State(state), Json(input))
+        let ret = signin_handler(State(state), ClientInfo::default(), Json(input))
             .await?
             .into_response();
 
@@ -138,6 +610,293 @@ mod tests {
         let body = ret.into_body().collect().await?.to_bytes();
         let ret: AuthOutput = serde_json::from_slice(&body)?;
         assert_ne!(ret.token, "");
+        assert_ne!(ret.refresh_token, "");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refresh_handler_should_rotate_token() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let signin = signin(&state).await?;
+
+        let input = RefreshToken::new(&signin.refresh_token);
+        let ret = refresh_handler(
+            State(state.clone()),
+            Method::POST,
+            HeaderMap::new(),
+            Some(Json(input.clone())),
+        )
+        .await?
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+
+        let body = ret.into_body().collect().await?.to_bytes();
+        let ret: AuthOutput = serde_json::from_slice(&body)?;
+        assert_ne!(ret.token, "");
+        assert_ne!(ret.refresh_token, signin.refresh_token);
+
+        // the old refresh token can't be used again
+        let ret = refresh_handler(
+            State(state),
+            Method::POST,
+            HeaderMap::new(),
+            Some(Json(input)),
+        )
+        .await
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn logout_handler_should_revoke_tokens() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let auth = signin(&state).await?;
+        let other = signin(&state).await?;
+
+        let user = state.verify(&auth.token).await?;
+        let header = TypedHeader(Authorization::bearer(&auth.token)?);
+        let input = LogoutUser {
+            refresh_token: Some(auth.refresh_token.clone()),
+        };
+        let ret = logout_handler(
+            Extension(user),
+            State(state.clone()),
+            Some(header),
+            HeaderMap::new(),
+            Some(Json(input)),
+        )
+        .await?
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::NO_CONTENT);
+
+        // both tokens of this device are revoked
+        assert!(state.verify(&auth.token).await.is_err());
+        assert!(
+            state
+                .rotate_refresh_token(&auth.refresh_token)
+                .await
+                .is_err()
+        );
+
+        // other devices are still logged in
+        assert!(state.verify(&other.token).await.is_ok());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn logout_all_handler_should_revoke_all_tokens() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let auth = signin(&state).await?;
+        let other = signin(&state).await?;
+
+        let user = state.verify(&auth.token).await?;
+        let ret = logout_all_handler(Extension(user), State(state.clone()))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NO_CONTENT);
+
+        for auth in [auth, other] {
+            assert!(state.verify(&auth.token).await.is_err());
+            assert!(
+                state
+                    .rotate_refresh_token(&auth.refresh_token)
+                    .await
+                    .is_err()
+            );
+        }
+
+        // signing in again works
+        let auth = signin(&state).await?;
+        assert!(state.verify(&auth.token).await.is_ok());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn password_reset_handlers_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        // unknown emails get the same answer
+        for email in ["Test@123.com", "TeamMeng@123.com"] {
+            let input = ForgotPassword {
+                email: email.to_string(),
+            };
+            let ret = forgot_password_handler(State(state.clone()), Json(input))
+                .await?
+                .into_response();
+            assert_eq!(ret.status(), StatusCode::ACCEPTED);
+        }
+
+        let input = ResetPassword::new("bad-token", "new-password");
+        let ret = reset_password_handler(State(state), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn change_password_handler_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test_with(|config| {
+            config.auth.signin_limit.delay = 0;
+        })
+        .await?;
+        let auth = signin(&state).await?;
+        let other = signin(&state).await?;
+        let user = state.verify(&auth.token).await?;
+
+        let input = ChangePassword {
+            old_password: "wrong".to_string(),
+            new_password: "new-password".to_string(),
+        };
+        let ret = change_password_handler(
+            Extension(user.clone()),
+            State(state.clone()),
+            ClientInfo::default(),
+            Json(input),
+        )
+        .await
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        let input = ChangePassword {
+            old_password: "123456".to_string(),
+            new_password: "1".to_string(),
+        };
+        let ret = change_password_handler(
+            Extension(user.clone()),
+            State(state.clone()),
+            ClientInfo::default(),
+            Json(input),
+        )
+        .await
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let input = ChangePassword {
+            old_password: "123456".to_string(),
+            new_password: "new-password".to_string(),
+        };
+        let ret = change_password_handler(
+            Extension(user),
+            State(state.clone()),
+            ClientInfo::default(),
+            Json(input),
+        )
+        .await?
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let ret: AuthOutput = serde_json::from_slice(&body)?;
+
+        // other sessions are logged out, this device keeps going with the new tokens
+        for auth in [&auth, &other] {
+            assert!(state.verify(&auth.token).await.is_err());
+            assert!(
+                state
+                    .rotate_refresh_token(&auth.refresh_token)
+                    .await
+                    .is_err()
+            );
+        }
+        assert!(state.verify(&ret.token).await.is_ok());
+
+        let input = SigninUser::new("Test@123.com", "new-password");
+        assert!(state.verify_user(&input).await?.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn signin_with_2fa_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        state.setup_totp(&user).await?;
+        let code = state.current_totp_code(user.id).await?;
+        let recovery_codes = state.enable_totp(user.id, &code).await?;
+
+        let input = SigninUser::new("Test@123.com", "123456");
+        let ret = signin_handler(State(state.clone()), ClientInfo::default(), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::ACCEPTED);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let ret: SigninChallenge = serde_json::from_slice(&body)?;
+
+        let input = VerifySigninChallenge {
+            challenge: ret.challenge,
+            code: recovery_codes[0].clone(),
+        };
+        let ret =
+            signin_two_factor_handler(State(state.clone()), ClientInfo::default(), Json(input))
+                .await?
+                .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let ret: AuthOutput = serde_json::from_slice(&body)?;
+        assert_eq!(state.verify(&ret.token).await?.id, user.id);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wrong_2fa_codes_should_be_throttled() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        state.setup_totp(&user).await?;
+        let code = state.current_totp_code(user.id).await?;
+        let recovery_codes = state.enable_totp(user.id, &code).await?;
+
+        let input = SigninUser::new("Test@123.com", "123456");
+        let ret = signin_handler(State(state.clone()), ClientInfo::default(), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::ACCEPTED);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let SigninChallenge { challenge } = serde_json::from_slice(&body)?;
+
+        let input = VerifySigninChallenge {
+            challenge: challenge.clone(),
+            code: "000000".to_string(),
+        };
+        let ret =
+            signin_two_factor_handler(State(state.clone()), ClientInfo::default(), Json(input))
+                .await
+                .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        // the wrong code counts against the account, even the right one has to wait
+        let input = VerifySigninChallenge {
+            challenge,
+            code: recovery_codes[0].clone(),
+        };
+        let ret =
+            signin_two_factor_handler(State(state.clone()), ClientInfo::default(), Json(input))
+                .await
+                .into_response();
+        assert_eq!(ret.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(ret.headers().contains_key("retry-after"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn jwks_handler_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let ret = jwks_handler(State(state.clone())).await.into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+
+        let body = ret.into_body().collect().await?.to_bytes();
+        let jwks: Jwks = serde_json::from_slice(&body)?;
+        assert_eq!(jwks.keys.len(), 1);
+        assert_eq!(jwks.keys[0].kid, state.ek.key_id());
 
         Ok(())
     }
@@ -150,7 +909,7 @@ mod tests {
         let password = "123456";
 
         let input = SigninUser::new(email, password);
-        let ret = signin_handler(State(state), Json(input))
+        let ret = signin_handler(State(state), ClientInfo::default(), Json(input))
             .await
             .into_response();
 
@@ -162,4 +921,116 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn signin_after_failure_should_be_throttled() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let client = ClientInfo {
+            ip: Some([10, 0, 0, 1].into()),
+            ..Default::default()
+        };
+
+        let input = SigninUser::new("Test@123.com", "wrong");
+        let ret = signin_handler(State(state.clone()), client.clone(), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+
+        // even the right password has to wait
+        let input = SigninUser::new("Test@123.com", "123456");
+        let ret = signin_handler(State(state), client.clone(), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(ret.headers().contains_key("retry-after"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cookie_session_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test_with(|config| {
+            config.auth.cookie.enabled = true;
+        })
+        .await?;
+        let app = get_router(state).await?;
+
+        let req = Request::builder()
+            .method("POST")
+            .uri("/api/signin")
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                r#"{"email": "Test@123.com", "password": "123456"}"#,
+            ))?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let set_cookies: Vec<&str> = res
+            .headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .map(|v| v.to_str())
+            .collect::<Result<_, _>>()?;
+        assert_eq!(set_cookies.len(), 3);
+        assert!(set_cookies[0].starts_with("chat_token="));
+        assert!(set_cookies[0].ends_with("; HttpOnly"));
+        assert!(!set_cookies[2].ends_with("; HttpOnly"));
+        let pairs: Vec<&str> = set_cookies
+            .iter()
+            .filter_map(|v| v.split(';').next())
+            .collect();
+        let cookie = pairs.join("; ");
+        let csrf = pairs[2].trim_start_matches("chat_csrf=");
+
+        let req = |method: &str, uri: &str, csrf: Option<&str>| {
+            let builder = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("Cookie", &cookie);
+            match csrf {
+                Some(csrf) => builder.header("X-CSRF-Token", csrf),
+                None => builder,
+            }
+            .body(Body::empty())
+        };
+
+        let res = app.clone().oneshot(req("GET", "/api/users", None)?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // changes need the csrf token, refreshing too
+        let res = app
+            .clone()
+            .oneshot(req("POST", "/api/refresh", None)?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = app
+            .clone()
+            .oneshot(req("POST", "/api/refresh", Some(csrf))?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get_all(SET_COOKIE).iter().count(), 3);
+
+        let res = app
+            .clone()
+            .oneshot(req("POST", "/api/logout/all", None)?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = app
+            .oneshot(req("POST", "/api/logout/all", Some(csrf))?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        for cookie in res.headers().get_all(SET_COOKIE) {
+            assert!(cookie.to_str()?.contains("Max-Age=0"));
+        }
+
+        Ok(())
+    }
+
+    async fn signin(state: &AppState) -> Result<AuthOutput> {
+        let input = SigninUser::new("Test@123.com", "123456");
+        let ret = signin_handler(State(state.clone()), ClientInfo::default(), Json(input))
+            .await?
+            .into_response();
+        let body = ret.into_body().collect().await?.to_bytes();
+        Ok(serde_json::from_slice(&body)?)
+    }
 }
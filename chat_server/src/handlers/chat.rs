@@ -5,7 +5,7 @@ use axum::{
     http::StatusCode,
     response::IntoResponse,
 };
-use chat_core::{Chat, User};
+use chat_core::{Chat, User, WorkspaceRole};
 
 /// List all chats in the workspace of the user
 #[utoipa::path(
@@ -60,8 +60,29 @@ pub(crate) async fn update_chat_handler() -> impl IntoResponse {
     "update chat"
 }
 
-pub(crate) async fn delete_chat_handler() -> impl IntoResponse {
-    "delete chat"
+/// Delete a chat and its messages
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    responses(
+        (status = 204, description = "Chat deleted"),
+        (status = 403, description = "Not a workspace admin", body = ErrorOutput),
+        (status = 404, description = "Chat not found", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+pub(crate) async fn delete_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.delete_chat(&user, id).await?;
+    Ok(StatusCode::NO_CONTENT)
 }
 
 /// Create a new chat in the workspace of the user
@@ -80,6 +101,11 @@ pub(crate) async fn create_chat_handler(
     State(state): State<AppState>,
     Json(input): Json<CreateChat>,
 ) -> Result<impl IntoResponse, AppError> {
+    if user.role == WorkspaceRole::Guest && input.name.is_some() {
+        return Err(AppError::PermissionDenied(
+            "guests can't create channels".to_string(),
+        ));
+    }
     let chat = state
         .create_chat(&input, user.id as _, user.ws_id as _)
         .await?;
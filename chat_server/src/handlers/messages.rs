@@ -23,6 +23,7 @@ use tracing::{info, warn};
     responses(
         (status = 201, description = "Message created", body = Message),
         (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 403, description = "Email not verified", body = ErrorOutput),
     ),
     security(
         ("token"=[])
@@ -34,6 +35,7 @@ pub(crate) async fn send_message_handler(
     Path(chat_id): Path<u64>,
     Json(input): Json<CreateMessage>,
 ) -> Result<impl IntoResponse, AppError> {
+    state.ensure_email_verified(user.id).await?;
     let message = state.create_message(input, chat_id, user.id as _).await?;
 
     Ok((StatusCode::CREATED, Json(message)).into_response())
@@ -69,7 +71,9 @@ pub(crate) async fn file_handler(
     State(state): State<AppState>,
     Path((ws_id, path)): Path<(i64, String)>,
 ) -> Result<impl IntoResponse, AppError> {
-    if user.ws_id != ws_id {
+    // avatars are stored in the workspace they were uploaded from, and shown in the others
+    let url = format!("/files/{}/{}", ws_id, path);
+    if user.ws_id != ws_id && !state.is_avatar_visible(&user, &url).await? {
         return Err(AppError::NotFound(
             "file doesn't exist or you don't have permission".to_string(),
         ));
@@ -1,9 +1,17 @@
+mod admin;
 mod auth;
 mod chat;
 mod messages;
+mod profile;
+mod sso;
+mod two_factor;
 mod workspace;
 
+pub(crate) use admin::*;
 pub(crate) use auth::*;
 pub(crate) use chat::*;
 pub(crate) use messages::*;
+pub(crate) use profile::*;
+pub(crate) use sso::*;
+pub(crate) use two_factor::*;
 pub(crate) use workspace::*;
@@ -0,0 +1,306 @@
+use crate::{
+    AppError, AppState,
+    error::ErrorOutput,
+    models::{Dnd, Profile, UpdateDnd, UpdateProfile, UpdateStatus},
+};
+use axum::{
+    Extension, Json,
+    extract::{Multipart, State},
+    response::IntoResponse,
+};
+use chat_core::{User, UserStatus};
+
+/// Get the profile of the current user
+#[utoipa::path(
+    get,
+    path = "/api/me",
+    responses(
+        (status = 200, description = "Profile of the user", body = Profile),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+pub(crate) async fn get_profile_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let profile = state.get_profile(&user).await?;
+    Ok(Json(profile))
+}
+
+/// Update the display name, title or time zone of the current user
+#[utoipa::path(
+    patch,
+    path = "/api/me",
+    responses(
+        (status = 200, description = "Profile updated", body = Profile),
+        (status = 422, description = "Invalid input", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+pub(crate) async fn update_profile_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<UpdateProfile>,
+) -> Result<impl IntoResponse, AppError> {
+    let profile = state.update_profile(&user, &input).await?;
+    Ok(Json(profile))
+}
+
+/// Upload the avatar of the current user
+#[utoipa::path(
+    put,
+    path = "/api/me/avatar",
+    request_body(content_type = "multipart/form-data", description = "An image file, png, jpeg or webp"),
+    responses(
+        (status = 200, description = "Avatar updated", body = Profile),
+        (status = 400, description = "Missing or invalid image", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+/// The image is cropped to a square and resized, then stored as a chat file of the workspace.
+pub(crate) async fn update_avatar_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, AppError> {
+    let mut data = None;
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| AppError::ChatFileError(e.to_string()))?
+    {
+        if field.file_name().is_some() {
+            let bytes = field
+                .bytes()
+                .await
+                .map_err(|e| AppError::ChatFileError(e.to_string()))?;
+            data = Some(bytes.to_vec());
+            break;
+        }
+    }
+    let Some(data) = data else {
+        return Err(AppError::ChatFileError("missing avatar file".to_string()));
+    };
+
+    let profile = state.update_avatar(&user, data).await?;
+    Ok(Json(profile))
+}
+
+/// Remove the avatar of the current user
+#[utoipa::path(
+    delete,
+    path = "/api/me/avatar",
+    responses(
+        (status = 200, description = "Avatar removed", body = Profile),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+pub(crate) async fn delete_avatar_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let profile = state.delete_avatar(&user).await?;
+    Ok(Json(profile))
+}
+
+/// Get the status of the current user
+#[utoipa::path(
+    get,
+    path = "/api/me/status",
+    responses(
+        (status = 200, description = "Status of the user, empty if expired", body = UserStatus),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+pub(crate) async fn get_status_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let status = state.get_status(&user).await?;
+    Ok(Json(status))
+}
+
+/// Set the status of the current user
+#[utoipa::path(
+    put,
+    path = "/api/me/status",
+    responses(
+        (status = 200, description = "Status updated", body = UserStatus),
+        (status = 422, description = "Invalid input", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+/// Users sharing a chat with the user get a `StatusChanged` event from notify_server.
+pub(crate) async fn update_status_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<UpdateStatus>,
+) -> Result<impl IntoResponse, AppError> {
+    let status = state.update_status(&user, &input).await?;
+    Ok(Json(status))
+}
+
+/// Clear the status of the current user
+#[utoipa::path(
+    delete,
+    path = "/api/me/status",
+    responses(
+        (status = 200, description = "Status cleared", body = UserStatus),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+pub(crate) async fn clear_status_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let status = state.update_status(&user, &UpdateStatus::default()).await?;
+    Ok(Json(status))
+}
+
+/// List the statuses of the users sharing a chat with the current user
+#[utoipa::path(
+    get,
+    path = "/api/users/statuses",
+    responses(
+        (status = 200, description = "Statuses set and not expired", body = Vec<UserStatus>),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+pub(crate) async fn list_statuses_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let statuses = state.list_peer_statuses(&user).await?;
+    Ok(Json(statuses))
+}
+
+/// Get the do-not-disturb settings of the current user
+#[utoipa::path(
+    get,
+    path = "/api/me/dnd",
+    responses(
+        (status = 200, description = "Do-not-disturb settings", body = Dnd),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+pub(crate) async fn get_dnd_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let dnd = state.get_dnd(&user).await?;
+    Ok(Json(dnd))
+}
+
+/// Replace the do-not-disturb settings of the current user
+#[utoipa::path(
+    put,
+    path = "/api/me/dnd",
+    responses(
+        (status = 200, description = "Do-not-disturb settings updated", body = Dnd),
+        (status = 422, description = "Invalid input", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+/// Windows are in the time zone of the profile. While in do-not-disturb, notify_server holds
+/// back new messages and sends them once it ends.
+pub(crate) async fn update_dnd_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<UpdateDnd>,
+) -> Result<impl IntoResponse, AppError> {
+    let dnd = state.update_dnd(&user, &input).await?;
+    Ok(Json(dnd))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        AppState, get_router,
+        models::{Profile, test_image},
+    };
+    use anyhow::Result;
+    use axum::{body::Body, extract::Request, http::StatusCode};
+    use chat_core::ChatUser;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn profile_handlers_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        let token = state.ek.sign(user)?;
+        let app = get_router(state).await?;
+
+        let req = |method: &str, uri: &str, content_type: &str, body: Vec<u8>| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("Authorization", format!("Bearer {}", token))
+                .header("Content-Type", content_type)
+                .body(Body::from(body))
+        };
+
+        let body = br#"{"title":"CTO","timezone":"Europe/Paris"}"#.to_vec();
+        let res = app
+            .clone()
+            .oneshot(req("PATCH", "/api/me", "application/json", body)?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let boundary = "avatar-boundary";
+        let mut body = format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"me.png\"\r\nContent-Type: image/png\r\n\r\n"
+        )
+        .into_bytes();
+        body.extend(test_image(64, 32));
+        body.extend(format!("\r\n--{boundary}--\r\n").into_bytes());
+        let content_type = format!("multipart/form-data; boundary={boundary}");
+        let res = app
+            .clone()
+            .oneshot(req("PUT", "/api/me/avatar", &content_type, body)?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = app
+            .clone()
+            .oneshot(req("GET", "/api/me", "application/json", vec![])?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await?.to_bytes();
+        let profile: Profile = serde_json::from_slice(&body)?;
+        assert_eq!(profile.title.as_deref(), Some("CTO"));
+        assert_eq!(profile.timezone.as_deref(), Some("Europe/Paris"));
+        let avatar_url = profile.avatar_url.unwrap();
+        assert!(avatar_url.starts_with("/files/1/"));
+
+        let res = app
+            .oneshot(req("GET", "/api/users", "application/json", vec![])?)
+            .await?;
+        let body = res.into_body().collect().await?.to_bytes();
+        let users: Vec<ChatUser> = serde_json::from_slice(&body)?;
+        let me = users.iter().find(|u| u.id == 1).unwrap();
+        assert_eq!(me.avatar_url, Some(avatar_url));
+
+        Ok(())
+    }
+}
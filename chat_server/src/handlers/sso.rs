@@ -0,0 +1,144 @@
+use crate::{
+    AppError, AppState, AuthOutput,
+    error::ErrorOutput,
+    models::{SigninChallenge, SsoCallback},
+};
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Redirect},
+};
+use chat_core::middlewares::ClientInfo;
+
+/// Start an sso login into the workspace
+#[utoipa::path(
+    get,
+    path = "/api/sso/{workspace}/login",
+    params(
+        ("workspace" = String, Path, description = "Workspace name"),
+    ),
+    responses(
+        (status = 303, description = "Redirect to the identity provider of the workspace"),
+        (status = 404, description = "Workspace without sso", body = ErrorOutput),
+    )
+)]
+pub(crate) async fn sso_login_handler(
+    State(state): State<AppState>,
+    Path(workspace): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let url = state.start_sso_login(&workspace).await?;
+    Ok(Redirect::to(&url))
+}
+
+/// Finish an sso login, the identity provider redirects here
+#[utoipa::path(
+    get,
+    path = "/api/sso/callback",
+    params(SsoCallback),
+    responses(
+        (status = 200, description = "User signed in", body = AuthOutput),
+        (status = 202, description = "Login accepted, 2fa code needed", body = SigninChallenge),
+        (status = 403, description = "Invalid login or ID token", body = ErrorOutput),
+    )
+)]
+/// - Users signing in for the first time are created in the workspace, without a password.
+/// - Users with 2fa enabled get a challenge instead of tokens, to be completed at
+///   `/api/signin/2fa`, whatever the identity provider checked.
+pub(crate) async fn sso_callback_handler(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Query(input): Query<SsoCallback>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = state.finish_sso_login(&input).await?;
+    if state.is_totp_enabled(user.id).await? {
+        let challenge = state.create_signin_challenge(&user).await?;
+        let body = Json(SigninChallenge { challenge });
+        return Ok((StatusCode::ACCEPTED, body).into_response());
+    }
+    let output = AuthOutput::new_session(&state, user, &client).await?;
+    output.respond(&state, StatusCode::OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::{UpdateWorkspaceSso, VerifySigninChallenge},
+        oidc::mock::{CLIENT_ID, MockIdp},
+    };
+    use anyhow::Result;
+    use axum::http::header::LOCATION;
+    use chat_core::middlewares::TokenVerify;
+    use http_body_util::BodyExt;
+
+    #[tokio::test]
+    async fn sso_handlers_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let idp = MockIdp::start().await;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.unwrap();
+        let input = UpdateWorkspaceSso {
+            issuer: idp.issuer.clone(),
+            client_id: CLIENT_ID.to_string(),
+            client_secret: Some("secret".to_string()),
+        };
+        state.update_workspace_sso(&owner, &input).await?;
+
+        let ret = sso_login_handler(State(state.clone()), Path("acme".to_string()))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::SEE_OTHER);
+        let url = ret.headers()[LOCATION].to_str()?;
+        assert!(url.starts_with(&format!("{}/authorize?", idp.issuer)));
+
+        let (code, sso_state) = idp.authorize(url, "eve", "Eve@123.com", "Eve Test");
+        let input = SsoCallback {
+            code,
+            state: sso_state,
+        };
+        let ret = sso_callback_handler(State(state.clone()), ClientInfo::default(), Query(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let ret: AuthOutput = serde_json::from_slice(&body)?;
+        let user = state.verify(&ret.token).await?;
+        assert_eq!(user.email, "eve@123.com");
+
+        // the second factor is still asked for
+        state.setup_totp(&user).await?;
+        let code = state.current_totp_code(user.id).await?;
+        let recovery_codes = state.enable_totp(user.id, &code).await?;
+        let ret = sso_login_handler(State(state.clone()), Path("acme".to_string()))
+            .await?
+            .into_response();
+        let url = ret.headers()[LOCATION].to_str()?;
+        let (code, sso_state) = idp.authorize(url, "eve", "Eve@123.com", "Eve Test");
+        let input = SsoCallback {
+            code,
+            state: sso_state,
+        };
+        let ret = sso_callback_handler(State(state.clone()), ClientInfo::default(), Query(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::ACCEPTED);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let ret: SigninChallenge = serde_json::from_slice(&body)?;
+        let input = VerifySigninChallenge {
+            challenge: ret.challenge,
+            code: recovery_codes[0].clone(),
+        };
+        let signed_in = state.verify_signin_challenge(&input).await?;
+        assert_eq!(signed_in.id, user.id);
+        assert_eq!(signed_in.ws_name, "acme");
+
+        // workspaces without sso
+        let ret = sso_login_handler(State(state), Path("foo".to_string()))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+}
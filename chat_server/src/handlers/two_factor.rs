@@ -0,0 +1,139 @@
+use crate::{
+    AppError, AppState,
+    error::ErrorOutput,
+    models::{RecoveryCodes, TotpSetup, TwoFactorCode},
+};
+use axum::{Extension, Json, extract::State, http::StatusCode, response::IntoResponse};
+use chat_core::User;
+
+/// Start a TOTP enrollment
+#[utoipa::path(
+    post,
+    path = "/api/2fa/setup",
+    responses(
+        (status = 200, description = "Secret to add to an authenticator app", body = TotpSetup),
+        (status = 400, description = "2fa already enabled", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+/// 2fa is only enabled once a code from the app is confirmed at `/api/2fa/enable`.
+pub(crate) async fn setup_totp_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let setup = state.setup_totp(&user).await?;
+    Ok((StatusCode::OK, Json(setup)))
+}
+
+/// Enable 2fa with a code from the authenticator app
+#[utoipa::path(
+    post,
+    path = "/api/2fa/enable",
+    responses(
+        (status = 200, description = "2fa enabled", body = RecoveryCodes),
+        (status = 403, description = "Invalid code", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+/// The recovery codes are only shown once.
+pub(crate) async fn enable_totp_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<TwoFactorCode>,
+) -> Result<impl IntoResponse, AppError> {
+    let recovery_codes = state.enable_totp(user.id, &input.code).await?;
+    Ok((StatusCode::OK, Json(RecoveryCodes { recovery_codes })))
+}
+
+/// Disable 2fa
+#[utoipa::path(
+    post,
+    path = "/api/2fa/disable",
+    responses(
+        (status = 204, description = "2fa disabled"),
+        (status = 400, description = "2fa required by the workspace", body = ErrorOutput),
+        (status = 403, description = "Invalid code", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+pub(crate) async fn disable_totp_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<TwoFactorCode>,
+) -> Result<impl IntoResponse, AppError> {
+    state.disable_totp(&user, &input.code).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Replace the recovery codes
+#[utoipa::path(
+    post,
+    path = "/api/2fa/recovery-codes",
+    responses(
+        (status = 200, description = "New recovery codes", body = RecoveryCodes),
+        (status = 403, description = "Invalid code", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+/// The old recovery codes stop working.
+pub(crate) async fn create_recovery_codes_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<TwoFactorCode>,
+) -> Result<impl IntoResponse, AppError> {
+    if !state.verify_second_factor(user.id, &input.code).await? {
+        return Err(AppError::InvalidTwoFactorCode);
+    }
+    let recovery_codes = state.create_recovery_codes(user.id).await?;
+    Ok((StatusCode::OK, Json(RecoveryCodes { recovery_codes })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use http_body_util::BodyExt;
+
+    #[tokio::test]
+    async fn totp_handlers_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+
+        let ret = setup_totp_handler(Extension(user.clone()), State(state.clone()))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let setup: TotpSetup = serde_json::from_slice(&body)?;
+        assert!(!setup.secret.is_empty());
+
+        let input = TwoFactorCode {
+            code: state.current_totp_code(user.id).await?,
+        };
+        let ret = enable_totp_handler(Extension(user.clone()), State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let codes: RecoveryCodes = serde_json::from_slice(&body)?;
+
+        let input = TwoFactorCode {
+            code: codes.recovery_codes[0].clone(),
+        };
+        let ret = disable_totp_handler(Extension(user.clone()), State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NO_CONTENT);
+        assert!(!state.is_totp_enabled(user.id).await?);
+
+        Ok(())
+    }
+}
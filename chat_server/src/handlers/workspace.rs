@@ -1,22 +1,518 @@
-use crate::{AppError, AppState, error::ErrorOutput};
-use axum::{Extension, Json, extract::State, http::StatusCode, response::IntoResponse};
-use chat_core::{ChatUser, User};
+use crate::{
+    AppError, AppState, AuthOutput,
+    error::ErrorOutput,
+    models::{
+        ApiKey, ApiKeyOutput, CreateApiKey, CreateInvite, DeleteWorkspace, Invite, InviteOutput,
+        JoinWorkspace, ListUsers, Membership, TransferOwnership, UpdateAutoJoinDomains,
+        UpdateMemberRole, UpdateTwoFactorPolicy, UpdateWorkspace, UpdateWorkspaceSso,
+    },
+};
+use axum::{
+    Extension, Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chat_core::{ChatUser, User, Workspace};
 
+/// List or search the users of the workspace
 #[utoipa::path(
     get,
     path = "/api/users",
+    params(
+        ListUsers
+    ),
     responses(
-        (status = 200, description = "List of chats", body = Vec<ChatUser>),
+        (status = 200, description = "List of users", body = Vec<ChatUser>),
         (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 422, description = "Invalid last_id", body = ErrorOutput),
     ),
     security(
         ("token"=[])
     )
 )]
+/// - Users are ordered by id, pass the id of the last one as `last_id` for the next page.
+/// - Pages have 50 users unless `limit` asks for another size, up to 100.
+/// - `q` matches the start of names and emails, or words close to it (trigram similarity).
+/// - Deactivated members are listed with `deactivated` set, to show who sent their past messages.
 pub(crate) async fn list_chat_users_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
+    Query(input): Query<ListUsers>,
 ) -> Result<impl IntoResponse, AppError> {
-    let users = state.fetch_all_chat_users(user.ws_id as _).await?;
+    let users = state.list_chat_users(user.ws_id as _, &input).await?;
     Ok((StatusCode::OK, Json(users)).into_response())
 }
+
+/// List the workspaces of the current user
+#[utoipa::path(
+    get,
+    path = "/api/workspaces",
+    responses(
+        (status = 200, description = "Workspaces the user belongs to, with its role there", body = Vec<Membership>),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+pub(crate) async fn list_workspaces_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let workspaces = state.list_memberships(&user).await?;
+    Ok(Json(workspaces))
+}
+
+/// Join another workspace with an invite
+#[utoipa::path(
+    post,
+    path = "/api/workspaces/join",
+    responses(
+        (status = 201, description = "Workspace joined", body = Membership),
+        (status = 403, description = "Invalid invite, or email not verified", body = ErrorOutput),
+        (status = 404, description = "Workspace not found", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+/// The account stays the same, switch to the workspace to use it.
+pub(crate) async fn join_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<JoinWorkspace>,
+) -> Result<impl IntoResponse, AppError> {
+    let ret = state.join_workspace(&user, &input).await?;
+    Ok((StatusCode::CREATED, Json(ret)))
+}
+
+/// Switch to another workspace of the current user
+#[utoipa::path(
+    post,
+    path = "/api/workspaces/{id}/switch",
+    params(
+        ("id" = i64, Path, description = "Workspace id"),
+    ),
+    responses(
+        (status = 200, description = "Tokens for the workspace", body = AuthOutput),
+        (status = 403, description = "Not signed in with a session of the user", body = ErrorOutput),
+        (status = 404, description = "Not a member of the workspace", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+/// - Exchanges the token for one in the workspace, along with a refresh token of the same session.
+/// - Refreshing the session keeps it in the workspace, and new signins land in it.
+/// - Tokens of the other workspaces stay valid while the user is a member of them.
+pub(crate) async fn switch_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<Response, AppError> {
+    let (user, refresh_token) = state.switch_workspace(&user, id).await?;
+    let output = AuthOutput::try_new(&state, user, refresh_token)?;
+    output.respond(&state, StatusCode::OK)
+}
+
+/// Get the workspace of the current user
+#[utoipa::path(
+    get,
+    path = "/api/workspace",
+    responses(
+        (status = 200, description = "The workspace", body = Workspace),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+pub(crate) async fn get_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state.get_workspace(&user).await?;
+    Ok(Json(ws))
+}
+
+/// Rename the workspace
+#[utoipa::path(
+    patch,
+    path = "/api/workspace",
+    responses(
+        (status = 200, description = "Workspace renamed", body = Workspace),
+        (status = 403, description = "Not a workspace admin", body = ErrorOutput),
+        (status = 409, description = "Name already taken", body = ErrorOutput),
+        (status = 422, description = "Invalid input", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+pub(crate) async fn update_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<UpdateWorkspace>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state.rename_workspace(&user, &input).await?;
+    Ok(Json(ws))
+}
+
+/// Delete the workspace with all its data
+#[utoipa::path(
+    delete,
+    path = "/api/workspace",
+    responses(
+        (status = 204, description = "Workspace deleted"),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+        (status = 422, description = "The name doesn't match", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+/// Only the owner can delete it, repeating its name. Chats, messages, files and the accounts of
+/// the members, or of users waiting to join it, are deleted for good, except accounts with a
+/// history in other workspaces, even deactivated or removed there.
+pub(crate) async fn delete_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<DeleteWorkspace>,
+) -> Result<impl IntoResponse, AppError> {
+    state.delete_workspace(&user, &input).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Transfer the ownership of the workspace to another member
+#[utoipa::path(
+    put,
+    path = "/api/workspace/owner",
+    responses(
+        (status = 200, description = "Ownership transferred", body = Workspace),
+        (status = 403, description = "Not the workspace owner, or the member is a bot", body = ErrorOutput),
+        (status = 404, description = "Member not found", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+/// The previous owner stays as an admin.
+pub(crate) async fn transfer_ownership_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<TransferOwnership>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state.transfer_workspace_ownership(&user, &input).await?;
+    Ok(Json(ws))
+}
+
+/// Remove a member from the workspace
+#[utoipa::path(
+    delete,
+    path = "/api/workspace/members/{id}",
+    params(
+        ("id" = i64, Path, description = "Member id"),
+    ),
+    responses(
+        (status = 204, description = "Member removed"),
+        (status = 403, description = "Not allowed to remove this member", body = ErrorOutput),
+        (status = 404, description = "Member not found", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+/// The member leaves its chats and is signed out everywhere, its messages are kept. Only the owner
+/// removes admins, and the owner can't be removed.
+pub(crate) async fn remove_member_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.remove_member(&user, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Deactivate a member of the workspace
+#[utoipa::path(
+    post,
+    path = "/api/workspace/members/{id}/deactivate",
+    params(
+        ("id" = i64, Path, description = "Member id"),
+    ),
+    responses(
+        (status = 204, description = "Member deactivated"),
+        (status = 403, description = "Not allowed to deactivate this member", body = ErrorOutput),
+        (status = 404, description = "Member not found", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+/// - The member is dropped from its chats and its tokens for the workspace stop working.
+/// - Its messages stay, it is listed as deactivated in `/api/users`.
+/// - Without another workspace it is signed out and can't sign in until reactivated.
+pub(crate) async fn deactivate_member_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.deactivate_member(&user, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Reactivate a deactivated member of the workspace
+#[utoipa::path(
+    post,
+    path = "/api/workspace/members/{id}/reactivate",
+    params(
+        ("id" = i64, Path, description = "Member id"),
+    ),
+    responses(
+        (status = 204, description = "Member reactivated"),
+        (status = 403, description = "Not allowed to reactivate this member", body = ErrorOutput),
+        (status = 404, description = "Deactivated member not found", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+/// The member is added back to the chats it was dropped from, and can sign in again.
+pub(crate) async fn reactivate_member_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.reactivate_member(&user, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Require 2fa from all members of the workspace, or stop requiring it
+#[utoipa::path(
+    put,
+    path = "/api/workspace/2fa",
+    responses(
+        (status = 204, description = "Policy updated"),
+        (status = 400, description = "The owner doesn't have 2fa enabled", body = ErrorOutput),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+/// Only the workspace owner can change it. Members without 2fa can only enroll until they enable it.
+pub(crate) async fn update_two_factor_policy_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<UpdateTwoFactorPolicy>,
+) -> Result<impl IntoResponse, AppError> {
+    state.update_two_factor_policy(&user, &input).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Set the OpenID Connect provider members can sign in with
+#[utoipa::path(
+    put,
+    path = "/api/workspace/sso",
+    responses(
+        (status = 204, description = "Provider updated"),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+        (status = 502, description = "Provider discovery failed", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+/// Only the workspace owner can change it. Register `auth.sso_redirect_url` at the provider.
+pub(crate) async fn update_workspace_sso_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<UpdateWorkspaceSso>,
+) -> Result<impl IntoResponse, AppError> {
+    state.update_workspace_sso(&user, &input).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Change the role of a member of the workspace
+#[utoipa::path(
+    put,
+    path = "/api/workspace/members/{id}/role",
+    params(
+        ("id" = i64, Path, description = "Member id"),
+    ),
+    responses(
+        (status = 204, description = "Role updated"),
+        (status = 403, description = "Not allowed to give or take away this role", body = ErrorOutput),
+        (status = 404, description = "Member not found", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+/// Admins manage members and guests, only the owner manages admins. The owner role can't be
+/// given this way.
+pub(crate) async fn update_member_role_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(input): Json<UpdateMemberRole>,
+) -> Result<impl IntoResponse, AppError> {
+    state.update_member_role(&user, id, &input).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Invite people to the workspace
+#[utoipa::path(
+    post,
+    path = "/api/workspace/invites",
+    responses(
+        (status = 201, description = "Invite created, the code is only shown once", body = InviteOutput),
+        (status = 400, description = "Invalid invite", body = ErrorOutput),
+        (status = 403, description = "Not a workspace admin", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+/// - With an email, the invite is mailed and only that email can use it, once.
+/// - Without, the code can be shared, up to `max_uses` times.
+pub(crate) async fn create_invite_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateInvite>,
+) -> Result<impl IntoResponse, AppError> {
+    let ret = state.create_invite(&user, &input).await?;
+    Ok((StatusCode::CREATED, Json(ret)))
+}
+
+/// List the invites of the workspace
+#[utoipa::path(
+    get,
+    path = "/api/workspace/invites",
+    responses(
+        (status = 200, description = "List of invites", body = Vec<Invite>),
+        (status = 403, description = "Not a workspace admin", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+pub(crate) async fn list_invites_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let invites = state.list_invites(&user).await?;
+    Ok((StatusCode::OK, Json(invites)))
+}
+
+/// Revoke an invite
+#[utoipa::path(
+    delete,
+    path = "/api/workspace/invites/{id}",
+    params(
+        ("id" = i64, Path, description = "Invite id"),
+    ),
+    responses(
+        (status = 204, description = "Invite revoked"),
+        (status = 404, description = "Invite not found", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+pub(crate) async fn revoke_invite_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.revoke_invite(&user, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Set the email domains which can join the workspace without an invite
+#[utoipa::path(
+    put,
+    path = "/api/workspace/domains",
+    responses(
+        (status = 204, description = "Domains updated"),
+        (status = 400, description = "Invalid domain", body = ErrorOutput),
+        (status = 403, description = "Not a workspace admin", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+/// Users signing up with one of these domains join once their email is verified.
+pub(crate) async fn update_auto_join_domains_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<UpdateAutoJoinDomains>,
+) -> Result<impl IntoResponse, AppError> {
+    state.update_auto_join_domains(&user, &input).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Create an API key for a bot user
+#[utoipa::path(
+    post,
+    path = "/api/api-keys",
+    responses(
+        (status = 201, description = "API key created, the key is only shown once", body = ApiKeyOutput),
+        (status = 403, description = "Not a workspace admin", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+/// Without `bot_id`, a new bot user named after the key is created.
+pub(crate) async fn create_api_key_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateApiKey>,
+) -> Result<impl IntoResponse, AppError> {
+    let ret = state.create_api_key(&user, &input).await?;
+    Ok((StatusCode::CREATED, Json(ret)))
+}
+
+/// List the API keys of the workspace
+#[utoipa::path(
+    get,
+    path = "/api/api-keys",
+    responses(
+        (status = 200, description = "List of API keys", body = Vec<ApiKey>),
+        (status = 403, description = "Not a workspace admin", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+pub(crate) async fn list_api_keys_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let keys = state.list_api_keys(&user).await?;
+    Ok((StatusCode::OK, Json(keys)))
+}
+
+/// Revoke an API key
+#[utoipa::path(
+    delete,
+    path = "/api/api-keys/{id}",
+    params(
+        ("id" = i64, Path, description = "API key id"),
+    ),
+    responses(
+        (status = 204, description = "API key revoked"),
+        (status = 404, description = "API key not found", body = ErrorOutput),
+    ),
+    security(
+        ("token"=[])
+    )
+)]
+pub(crate) async fn revoke_api_key_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.revoke_api_key(&user, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
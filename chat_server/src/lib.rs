@@ -1,26 +1,38 @@
 mod config;
 mod error;
 mod handlers;
+mod mailer;
 mod middlewares;
 mod models;
+mod oidc;
 mod openapi;
 
-use crate::{handlers::*, middlewares::verify_chat, openapi::OpenApiRouter};
+use crate::{
+    handlers::*,
+    mailer::{Mailer, build_mailer},
+    middlewares::{
+        audit_impersonation, require_admin, verify_api_scope, verify_chat, verify_two_factor,
+    },
+    models::API_KEY_PREFIX,
+    openapi::OpenApiRouter,
+};
 use anyhow::Context;
 use axum::{
     Router,
     http::Method,
-    middleware::from_fn_with_state,
-    routing::{get, post},
+    middleware::{from_fn, from_fn_with_state},
+    routing::{delete, get, post, put},
 };
 use chat_core::{
-    DecodingKey, EncodingKey, User,
-    middlewares::{TokenVerify, set_layers, verify_token},
+    CookiePolicy, DecodingKey, EncodingKey, User, is_token_revoked,
+    middlewares::{ClientInfo, TokenVerify, set_layers, verify_token},
+    touch_session,
 };
 use sqlx::PgPool;
 use std::{fmt, ops::Deref, sync::Arc};
 use tokio::fs;
 use tower_http::cors::{Any, CorsLayer};
+use tracing::warn;
 
 pub use config::AppConfig;
 pub use error::AppError;
@@ -35,6 +47,8 @@ pub struct AppStateInner {
     pub(crate) ek: EncodingKey,
     pub(crate) dk: DecodingKey,
     pub(crate) pool: PgPool,
+    pub(crate) mailer: Box<dyn Mailer>,
+    pub(crate) http: reqwest::Client,
 }
 
 pub async fn get_router(state: AppState) -> Result<Router, AppError> {
@@ -62,17 +76,98 @@ pub async fn get_router(state: AppState) -> Result<Router, AppError> {
         .allow_origin(Any);
 
     let api = Router::new()
+        // routes only open to workspace admins
+        .route("/workspace/2fa", put(update_two_factor_policy_handler))
+        .route("/workspace/sso", put(update_workspace_sso_handler))
+        .route("/workspace/owner", put(transfer_ownership_handler))
+        .route("/workspace/members/{id}", delete(remove_member_handler))
+        .route(
+            "/workspace/members/{id}/deactivate",
+            post(deactivate_member_handler),
+        )
+        .route(
+            "/workspace/members/{id}/reactivate",
+            post(reactivate_member_handler),
+        )
+        .route(
+            "/workspace/members/{id}/role",
+            put(update_member_role_handler),
+        )
+        .route(
+            "/workspace/invites",
+            get(list_invites_handler).post(create_invite_handler),
+        )
+        .route("/workspace/invites/{id}", delete(revoke_invite_handler))
+        .route("/workspace/domains", put(update_auto_join_domains_handler))
+        .route(
+            "/api-keys",
+            get(list_api_keys_handler).post(create_api_key_handler),
+        )
+        .route("/api-keys/{id}", delete(revoke_api_key_handler))
+        .layer(from_fn(require_admin))
+        .route(
+            "/workspace",
+            get(get_workspace_handler)
+                .patch(update_workspace_handler)
+                .delete(delete_workspace_handler),
+        )
+        .route("/workspaces/join", post(join_workspace_handler))
         .route("/users", get(list_chat_users_handler))
+        .route("/users/statuses", get(list_statuses_handler))
         .nest("/chats", chat)
         .route("/upload", post(upload_handler))
+        .route("/events/tickets", post(create_event_ticket_handler))
         .route("/files/{ws_id}/{*path}", get(file_handler))
+        .route(
+            "/me",
+            get(get_profile_handler).patch(update_profile_handler),
+        )
+        .route(
+            "/me/avatar",
+            put(update_avatar_handler).delete(delete_avatar_handler),
+        )
+        .route(
+            "/me/status",
+            get(get_status_handler)
+                .put(update_status_handler)
+                .delete(clear_status_handler),
+        )
+        .route("/me/dnd", get(get_dnd_handler).put(update_dnd_handler))
+        .route("/me/password", post(change_password_handler))
+        .route("/me/sessions", get(list_sessions_handler))
+        .route("/me/sessions/{id}", delete(revoke_session_handler))
+        .route("/2fa/disable", post(disable_totp_handler))
+        .route("/2fa/recovery-codes", post(create_recovery_codes_handler))
+        .route("/admin/users/{id}/impersonate", post(impersonate_handler))
+        .layer(from_fn_with_state(state.clone(), verify_two_factor))
+        // routes still open to members who have to enable 2fa first
+        .route("/workspaces", get(list_workspaces_handler))
+        .route("/workspaces/{id}/switch", post(switch_workspace_handler))
+        .route("/logout", post(logout_handler))
+        .route("/logout/all", post(logout_all_handler))
+        .route("/email/resend", post(resend_email_verification_handler))
+        .route("/2fa/setup", post(setup_totp_handler))
+        .route("/2fa/enable", post(enable_totp_handler))
+        .layer(from_fn(verify_api_scope))
+        .layer(from_fn_with_state(state.clone(), audit_impersonation))
         .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
         // routes doesn't need token verification
         .route("/signin", post(signin_handler))
+        .route("/signin/2fa", post(signin_two_factor_handler))
+        .route("/sso/{workspace}/login", get(sso_login_handler))
+        .route("/sso/callback", get(sso_callback_handler))
         .route("/signup", post(signup_handler))
+        .route("/refresh", post(refresh_handler))
+        .route("/password/forgot", post(forgot_password_handler))
+        .route("/password/reset", post(reset_password_handler))
+        .route("/email/verify", post(verify_email_handler))
         .layer(cors);
 
-    let app = Router::new().openapi().nest("/api", api).with_state(state);
+    let app = Router::new()
+        .openapi()
+        .route("/.well-known/jwks.json", get(jwks_handler))
+        .nest("/api", api)
+        .with_state(state);
 
     Ok(set_layers(app))
 }
@@ -91,14 +186,17 @@ impl AppState {
             .await
             .context("create base_dir failed")?;
         let pool = PgPool::connect(&config.server.db_url).await?;
-        let ek = EncodingKey::load(&config.auth.sk).context("load pk failed")?;
-        let dk = DecodingKey::load(&config.auth.pk).context("load sk failed")?;
+        let ek = config.auth.load_encoding_key().context("load sk failed")?;
+        let dk = config.auth.load_decoding_key().context("load pk failed")?;
+        let mailer = build_mailer(&config.mailer)?;
         Ok(Self {
             inner: Arc::new(AppStateInner {
                 config,
                 ek,
                 dk,
                 pool,
+                mailer,
+                http: reqwest::Client::new(),
             }),
         })
     }
@@ -115,23 +213,69 @@ impl fmt::Debug for AppStateInner {
 impl TokenVerify for AppState {
     type Error = AppError;
 
-    fn verify(&self, token: &str) -> Result<User, Self::Error> {
-        Ok(self.dk.verify(token)?)
+    async fn verify(&self, token: &str) -> Result<User, Self::Error> {
+        if token.starts_with(API_KEY_PREFIX) {
+            return self.verify_api_key(token).await;
+        }
+        let claims = self.dk.decode(token)?;
+        if is_token_revoked(&self.pool, &claims).await? {
+            return Err(AppError::TokenRevoked);
+        }
+        let mut user = claims.custom;
+        // roles can change during the lifetime of a token, and members can leave the workspace
+        user.role = self.find_user_role(user.id, user.ws_id).await?;
+        Ok(user)
+    }
+
+    fn allow_query_token(&self) -> bool {
+        self.config.auth.allow_query_token
+    }
+
+    fn cookie_policy(&self) -> Option<&CookiePolicy> {
+        let policy = &self.config.auth.cookie;
+        policy.enabled.then_some(policy)
+    }
+
+    async fn touch(&self, user: &User, client: &ClientInfo) {
+        let Some(sid) = user.sid else {
+            return;
+        };
+        if let Err(e) = touch_session(&self.pool, sid, client.ip).await {
+            warn!("failed to update session {}: {}", sid, e);
+        }
     }
 }
 
 #[cfg(feature = "test-util")]
 mod test_util {
     use super::*;
+    use crate::config::MailerConfig;
     use sqlx::Executor;
     use sqlx_db_tester::TestPg;
     use std::path::Path;
+    use uuid::Uuid;
 
     impl AppState {
         pub async fn new_for_test() -> Result<(sqlx_db_tester::TestPg, Self), AppError> {
-            let config = AppConfig::load()?;
-            let dk = DecodingKey::load(&config.auth.pk).context("load pk failed")?;
-            let ek = EncodingKey::load(&config.auth.sk).context("load sk failed")?;
+            Self::new_for_test_with(|_| {}).await
+        }
+
+        /// Like `new_for_test`, with some config changed
+        pub async fn new_for_test_with(
+            f: impl FnOnce(&mut AppConfig),
+        ) -> Result<(sqlx_db_tester::TestPg, Self), AppError> {
+            let mut config = AppConfig::load()?;
+            f(&mut config);
+            // every test reads back its own mails
+            let dir = config
+                .server
+                .base_dir
+                .join("mails")
+                .join(Uuid::now_v7().to_string());
+            config.mailer = MailerConfig::File { dir };
+            let mailer = build_mailer(&config.mailer)?;
+            let dk = config.auth.load_decoding_key().context("load pk failed")?;
+            let ek = config.auth.load_encoding_key().context("load sk failed")?;
             let post = config.server.db_url.rfind('/').expect("invalid db_url");
             let server_url = &config.server.db_url[..post];
 
@@ -143,10 +287,20 @@ mod test_util {
                     ek,
                     dk,
                     pool,
+                    mailer,
+                    http: reqwest::Client::new(),
                 }),
             };
             Ok((tdb, state))
         }
+
+        #[cfg(test)]
+        pub fn read_mails(&self, to: &str) -> Vec<crate::mailer::Mail> {
+            match &self.config.mailer {
+                MailerConfig::File { dir } => crate::mailer::FileMailer::read_mails(dir, to),
+                _ => vec![],
+            }
+        }
     }
 
     pub async fn get_test_pool(url: Option<&str>) -> (TestPg, PgPool) {
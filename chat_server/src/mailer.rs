@@ -0,0 +1,161 @@
+use crate::{AppError, config::MailerConfig};
+use async_trait::async_trait;
+use lettre::{
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor, message::Mailbox,
+    transport::smtp::authentication::Credentials,
+};
+use std::path::PathBuf;
+use tokio::fs;
+use tracing::info;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, mail: Mail) -> Result<(), AppError>;
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+/// Write every mail to its own file in `dir`, for tests and local development
+pub struct FileMailer {
+    dir: PathBuf,
+}
+
+/// Create the mailer configured in `mailer`
+pub fn build_mailer(config: &MailerConfig) -> Result<Box<dyn Mailer>, AppError> {
+    let mailer: Box<dyn Mailer> = match config {
+        MailerConfig::Smtp {
+            host,
+            port,
+            username,
+            password,
+            from,
+        } => {
+            let mut builder = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+                .map_err(|e| AppError::MailError(e.to_string()))?
+                .port(*port);
+            if let (Some(username), Some(password)) = (username, password) {
+                builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+            }
+            let from = from
+                .parse()
+                .map_err(|e| AppError::MailError(format!("invalid from address: {}", e)))?;
+            Box::new(SmtpMailer {
+                transport: builder.build(),
+                from,
+            })
+        }
+        MailerConfig::File { dir } => Box::new(FileMailer { dir: dir.clone() }),
+    };
+    Ok(mailer)
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, mail: Mail) -> Result<(), AppError> {
+        let to = mail
+            .to
+            .parse()
+            .map_err(|e| AppError::MailError(format!("invalid address {}: {}", mail.to, e)))?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(mail.subject)
+            .body(mail.body)
+            .map_err(|e| AppError::MailError(e.to_string()))?;
+        self.transport
+            .send(message)
+            .await
+            .map_err(|e| AppError::MailError(e.to_string()))?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, mail: Mail) -> Result<(), AppError> {
+        fs::create_dir_all(&self.dir).await?;
+        let path = self.dir.join(format!("{}.eml", Uuid::now_v7()));
+        let content = format!(
+            "To: {}\nSubject: {}\n\n{}",
+            mail.to, mail.subject, mail.body
+        );
+        fs::write(&path, content).await?;
+        info!("Mail to {} written to {:?}", mail.to, path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Mail {
+    /// The `token` query parameter of the link in the mail
+    pub fn link_token(&self) -> String {
+        let (_, token) = self
+            .body
+            .split_once("token=")
+            .expect("mail should contain a token");
+        token.lines().next().unwrap_or_default().to_string()
+    }
+}
+
+#[cfg(test)]
+impl FileMailer {
+    /// Read back all mails sent to `to`, oldest first
+    pub fn read_mails(dir: &std::path::Path, to: &str) -> Vec<Mail> {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return vec![];
+        };
+        let mut paths: Vec<_> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+        paths.sort();
+
+        paths
+            .into_iter()
+            .filter_map(|path| std::fs::read_to_string(path).ok())
+            .filter_map(|content| {
+                let (headers, body) = content.split_once("\n\n")?;
+                let mut lines = headers.lines();
+                let to = lines.next()?.strip_prefix("To: ")?.to_string();
+                let subject = lines.next()?.strip_prefix("Subject: ")?.to_string();
+                Some(Mail {
+                    to,
+                    subject,
+                    body: body.to_string(),
+                })
+            })
+            .filter(|mail| mail.to.eq_ignore_ascii_case(to))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn file_mailer_should_work() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("chat_mails_{}", Uuid::now_v7()));
+        let mailer = build_mailer(&MailerConfig::File { dir: dir.clone() })?;
+        let mail = Mail {
+            to: "Test@123.com".to_string(),
+            subject: "Hello".to_string(),
+            body: "Hello World!\n".to_string(),
+        };
+        mailer.send(mail.clone()).await?;
+
+        let mails = FileMailer::read_mails(&dir, "Test@123.com");
+        assert_eq!(mails, vec![mail]);
+
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 use anyhow::Result;
 use chat_server::{AppConfig, AppState, get_router};
+use std::net::SocketAddr;
 use tokio::net::TcpListener;
 use tracing::{info, level_filters::LevelFilter};
 use tracing_subscriber::{Layer as _, fmt::Layer, layer::SubscriberExt, util::SubscriberInitExt};
@@ -18,6 +19,8 @@ async fn main() -> Result<()> {
     let listener = TcpListener::bind(&addr).await?;
     info!("Listening on: {}", addr);
 
+    // client ips are needed to throttle failed signins and to show where sessions are
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
     axum::serve(listener, app).await?;
 
     Ok(())
@@ -0,0 +1,90 @@
+use crate::{AppError, models::ApiScope};
+use axum::{
+    extract::Request,
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chat_core::User;
+
+/// Requests authenticated with an API key can only reach the routes its scopes allow
+pub async fn verify_api_scope(req: Request, next: Next) -> Response {
+    let user = req.extensions().get::<User>().unwrap();
+    let Some(scopes) = &user.scopes else {
+        return next.run(req).await;
+    };
+
+    match required_scope(req.method(), req.uri().path()) {
+        Some(scope) if scopes.iter().any(|s| s == scope.as_str()) => next.run(req).await,
+        Some(scope) => {
+            AppError::PermissionDenied(format!("api key doesn't have the {} scope", scope.as_str()))
+                .into_response()
+        }
+        None => {
+            AppError::PermissionDenied("not available with an api key".to_string()).into_response()
+        }
+    }
+}
+
+fn required_scope(method: &Method, path: &str) -> Option<ApiScope> {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (method, segments.as_slice()) {
+        (&Method::GET, ["users"])
+        | (&Method::GET, ["users", "statuses"])
+        | (&Method::GET, ["chats"])
+        | (&Method::GET, ["chats", _])
+        | (&Method::GET, ["chats", _, "messages"])
+        | (&Method::GET, ["files", ..]) => Some(ApiScope::ReadChats),
+        (&Method::POST, ["events", "tickets"]) => Some(ApiScope::ReadChats),
+        (&Method::POST, ["chats", _]) => Some(ApiScope::PostMessages),
+        (&Method::POST, ["upload"]) => Some(ApiScope::UploadFiles),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        AppState, get_router,
+        models::{ApiScope, CreateApiKey},
+    };
+    use anyhow::Result;
+    use axum::{body::Body, extract::Request, http::StatusCode};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn verify_api_scope_middleware_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.unwrap();
+        let input = CreateApiKey::new("ci", &[ApiScope::ReadChats]);
+        let key = state.create_api_key(&owner, &input).await?.key;
+        let app = get_router(state).await?;
+
+        let req = |method: &str, uri: &str, body: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("Authorization", format!("Bearer {}", key))
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+        };
+
+        let res = app.clone().oneshot(req("GET", "/api/users", "")?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // no messages:write scope
+        let body = r#"{"content": "hello", "files": []}"#;
+        let res = app
+            .clone()
+            .oneshot(req("POST", "/api/chats/1", body)?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // never with an api key
+        let res = app.oneshot(req("POST", "/api/logout/all", "")?).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+use crate::{AppError, AppState};
+use axum::{
+    extract::{OriginalUri, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chat_core::User;
+use tracing::warn;
+
+/// Requests made with an impersonation token are logged, and can't touch the credentials or
+/// the sessions of the user
+pub async fn audit_impersonation(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let user = req.extensions().get::<User>().unwrap();
+    let Some(admin_id) = user.impersonated_by else {
+        return next.run(req).await;
+    };
+    let user_id = user.id;
+    let method = req.method().to_string();
+    // the full path, routes nested under /api only see the rest
+    let path = match req.extensions().get::<OriginalUri>() {
+        Some(OriginalUri(uri)) => uri.path().to_string(),
+        None => req.uri().path().to_string(),
+    };
+
+    let res = if is_credential_route(req.uri().path()) {
+        AppError::PermissionDenied("not available while impersonating".to_string()).into_response()
+    } else {
+        next.run(req).await
+    };
+
+    let status = res.status().as_u16();
+    if let Err(e) = state
+        .log_impersonation(admin_id, user_id, &method, &path, status)
+        .await
+    {
+        warn!(
+            "failed to log {} {} of admin {} as user {}: {}",
+            method, path, admin_id, user_id, e
+        );
+    }
+    res
+}
+
+// event tickets don't carry the impersonation, the event stream wouldn't be marked as such
+fn is_credential_route(path: &str) -> bool {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    matches!(
+        segments.as_slice(),
+        ["me", "password"]
+            | ["me", "sessions", ..]
+            | ["logout", "all"]
+            | ["events", "tickets"]
+            | ["2fa", ..]
+            | ["api-keys", ..]
+            | ["admin", ..]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AppState, get_router};
+    use anyhow::Result;
+    use axum::{body::Body, extract::Request, http::StatusCode};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn audit_impersonation_middleware_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        sqlx::query("UPDATE users SET is_system_admin = TRUE WHERE id = 5")
+            .execute(&state.pool)
+            .await?;
+        let admin = state.find_user_by_id(5).await?.unwrap();
+        let token = state.impersonate(&admin, 1).await?.token;
+        let app = get_router(state.clone()).await?;
+
+        let req = |method: &str, uri: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("Authorization", format!("Bearer {}", token))
+                .header("Content-Type", "application/json")
+                .body(Body::from("{}"))
+        };
+
+        let res = app.clone().oneshot(req("GET", "/api/chats")?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = app.oneshot(req("POST", "/api/me/password")?).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let logs: Vec<(String, String, i16)> = sqlx::query_as(
+            "SELECT method, path, status FROM impersonation_logs WHERE user_id = 1 ORDER BY id",
+        )
+        .fetch_all(&state.pool)
+        .await?;
+        assert_eq!(logs.len(), 3);
+        assert_eq!(logs[1], ("GET".to_string(), "/api/chats".to_string(), 200));
+        assert_eq!(
+            logs[2],
+            ("POST".to_string(), "/api/me/password".to_string(), 403)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn impersonation_should_not_reach_sessions_or_event_tickets() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        sqlx::query("UPDATE users SET is_system_admin = TRUE WHERE id = 5")
+            .execute(&state.pool)
+            .await?;
+        let admin = state.find_user_by_id(5).await?.unwrap();
+        let token = state.impersonate(&admin, 1).await?.token;
+        let app = get_router(state.clone()).await?;
+
+        let sid = uuid::Uuid::now_v7();
+        for (method, uri) in [
+            ("POST", "/api/logout/all".to_string()),
+            ("GET", "/api/me/sessions".to_string()),
+            ("DELETE", format!("/api/me/sessions/{}", sid)),
+            ("POST", "/api/events/tickets".to_string()),
+        ] {
+            let req = Request::builder()
+                .method(method)
+                .uri(&uri)
+                .header("Authorization", format!("Bearer {}", token))
+                .header("Content-Type", "application/json")
+                .body(Body::from("{}"))?;
+            let res = app.clone().oneshot(req).await?;
+            assert_eq!(res.status(), StatusCode::FORBIDDEN, "{} {}", method, uri);
+        }
+
+        // the user wasn't signed out
+        let (revoked,): (bool,) =
+            sqlx::query_as("SELECT tokens_revoked_at IS NOT NULL FROM users WHERE id = 1")
+                .fetch_one(&state.pool)
+                .await?;
+        assert!(!revoked);
+
+        Ok(())
+    }
+}
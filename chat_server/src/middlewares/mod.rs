@@ -1,3 +1,11 @@
+mod api_scope;
 mod chat;
+mod impersonation;
+mod role;
+mod two_factor;
 
+pub use api_scope::verify_api_scope;
 pub use chat::verify_chat;
+pub use impersonation::audit_impersonation;
+pub use role::require_admin;
+pub use two_factor::verify_two_factor;
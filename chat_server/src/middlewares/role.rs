@@ -0,0 +1,75 @@
+use crate::models::ensure_role;
+use axum::{
+    extract::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chat_core::{User, WorkspaceRole};
+
+/// Only workspace admins and the owner get through
+pub async fn require_admin(req: Request, next: Next) -> Response {
+    let user = req.extensions().get::<User>().unwrap();
+
+    match ensure_role(user, WorkspaceRole::Admin) {
+        Ok(()) => next.run(req).await,
+        Err(e) => e.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppState;
+    use anyhow::Result;
+    use axum::{
+        Router,
+        body::Body,
+        http::StatusCode,
+        middleware::{from_fn, from_fn_with_state},
+        routing::get,
+    };
+    use chat_core::middlewares::verify_token;
+    use tower::ServiceExt;
+
+    async fn handler(_req: Request) -> impl IntoResponse {
+        (StatusCode::OK, "ok")
+    }
+
+    #[tokio::test]
+    async fn require_admin_middleware_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+
+        let app = Router::new()
+            .route("/admin", get(handler))
+            .layer(from_fn(require_admin))
+            .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
+            .with_state(state.clone());
+
+        for (id, status) in [(1, StatusCode::OK), (2, StatusCode::FORBIDDEN)] {
+            let user = state.find_user_by_id(id).await?.unwrap();
+            let token = state.ek.sign(user)?;
+            let req = Request::builder()
+                .uri("/admin")
+                .header("Authorization", format!("Bearer {}", token))
+                .body(Body::empty())?;
+            let res = app.clone().oneshot(req).await?;
+            assert_eq!(res.status(), status);
+        }
+
+        // a demoted user is refused even with a token issued before
+        let user = state.find_user_by_id(1).await?.unwrap();
+        let token = state.ek.sign(user)?;
+        sqlx::query("UPDATE workspace_members SET role = 'member' WHERE user_id = 1")
+            .execute(&state.pool)
+            .await?;
+        let req = Request::builder()
+            .uri("/admin")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+}
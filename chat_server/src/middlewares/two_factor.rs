@@ -0,0 +1,103 @@
+use crate::{AppError, AppState};
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chat_core::User;
+
+/// Members of a workspace requiring 2fa can only enroll, or log out, until they enabled it
+pub async fn verify_two_factor(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let user = req.extensions().get::<User>().unwrap();
+
+    match state.is_two_factor_missing(user).await {
+        Ok(false) => next.run(req).await,
+        Ok(true) => AppError::TwoFactorRequired.into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ApiScope, CreateApiKey};
+    use anyhow::Result;
+    use axum::{
+        Router, body::Body, http::StatusCode, middleware::from_fn_with_state, routing::get,
+    };
+    use chat_core::middlewares::verify_token;
+    use tower::ServiceExt;
+
+    async fn handler(_req: Request) -> impl IntoResponse {
+        (StatusCode::OK, "ok")
+    }
+
+    #[tokio::test]
+    async fn verify_two_factor_middleware_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let user = state
+            .find_user_by_id(1)
+            .await?
+            .expect("user id: 1 should exists");
+        let token = state.ek.sign(user)?;
+
+        let app = Router::new()
+            .route("/users", get(handler))
+            .layer(from_fn_with_state(state.clone(), verify_two_factor))
+            .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
+            .with_state(state.clone());
+
+        let req = Request::builder()
+            .uri("/users")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(StatusCode::OK, res.status());
+
+        // the workspace requires 2fa, user 1 doesn't have it
+        sqlx::query("UPDATE workspaces SET require_two_factor = TRUE WHERE id = 1")
+            .execute(&state.pool)
+            .await?;
+        let req = Request::builder()
+            .uri("/users")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        assert_eq!(StatusCode::FORBIDDEN, res.status());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn verify_two_factor_middleware_should_let_api_keys_through() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.unwrap();
+        let input = CreateApiKey::new("ci", &[ApiScope::ReadChats]);
+        let key = state.create_api_key(&owner, &input).await?.key;
+
+        sqlx::query("UPDATE workspaces SET require_two_factor = TRUE WHERE id = 1")
+            .execute(&state.pool)
+            .await?;
+
+        let app = Router::new()
+            .route("/users", get(handler))
+            .layer(from_fn_with_state(state.clone(), verify_two_factor))
+            .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
+            .with_state(state.clone());
+
+        let req = Request::builder()
+            .uri("/users")
+            .header("Authorization", format!("Bearer {}", key))
+            .body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        assert_eq!(StatusCode::OK, res.status());
+
+        Ok(())
+    }
+}
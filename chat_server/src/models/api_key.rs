@@ -0,0 +1,294 @@
+use crate::{
+    AppError, AppState,
+    models::{generate_token, hash_token},
+};
+use chat_core::User;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// API keys are told apart from JWTs by this prefix
+pub const API_KEY_PREFIX: &str = "chat_";
+
+/// What an API key is allowed to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
+pub enum ApiScope {
+    /// list chats, users and messages, download files
+    #[serde(rename = "chats:read")]
+    ReadChats,
+    /// send messages
+    #[serde(rename = "messages:write")]
+    PostMessages,
+    /// upload files
+    #[serde(rename = "files:write")]
+    UploadFiles,
+}
+
+/// create an API key for a new bot user, or for an existing one
+#[derive(Debug, Serialize, ToSchema, Deserialize, Clone)]
+pub struct CreateApiKey {
+    /// name of the key, also the name of the bot when a new one is created
+    pub name: String,
+    pub scopes: Vec<ApiScope>,
+    /// bot user to create the key for, a new one is created if not set
+    #[serde(default)]
+    pub bot_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct ApiKey {
+    pub id: i64,
+    pub ws_id: i64,
+    /// bot user acting with the key
+    pub bot_id: i64,
+    pub name: String,
+    pub key_prefix: String,
+    pub scopes: Vec<String>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// a new API key, the key itself is only shown once
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct ApiKeyOutput {
+    pub key: String,
+    pub api_key: ApiKey,
+}
+
+impl ApiScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ReadChats => "chats:read",
+            Self::PostMessages => "messages:write",
+            Self::UploadFiles => "files:write",
+        }
+    }
+}
+
+impl AppState {
+    /// Create an API key in the workspace of the user, only admins can do it
+    pub async fn create_api_key(
+        &self,
+        user: &User,
+        input: &CreateApiKey,
+    ) -> Result<ApiKeyOutput, AppError> {
+        let ws = self.find_administered_workspace(user).await?;
+        if input.scopes.is_empty() {
+            return Err(AppError::ApiKeyError(
+                "an API key needs at least one scope".to_string(),
+            ));
+        }
+        if input.name.is_empty() || input.name.len() > 64 {
+            return Err(AppError::ApiKeyError(
+                "name must have 1 to 64 characters".to_string(),
+            ));
+        }
+
+        let bot_id = match input.bot_id {
+            Some(bot_id) => {
+                let bot: Option<(i64,)> =
+                    sqlx::query_as("SELECT id FROM users WHERE id = $1 AND ws_id = $2 AND is_bot")
+                        .bind(bot_id)
+                        .bind(ws.id)
+                        .fetch_optional(&self.pool)
+                        .await?;
+                bot.ok_or_else(|| AppError::NotFound(format!("bot id: {}", bot_id)))?
+                    .0
+            }
+            None => self.create_bot_user(ws.id, &input.name).await?,
+        };
+
+        let key = format!("{}{}", API_KEY_PREFIX, generate_token());
+        let scopes: Vec<&str> = input.scopes.iter().map(ApiScope::as_str).collect();
+        let api_key = sqlx::query_as(
+            "
+            INSERT INTO api_keys (ws_id, user_id, name, key_prefix, key_hash, scopes, created_by)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id, ws_id, user_id AS bot_id, name, key_prefix, scopes, last_used_at,
+                revoked_at, created_at
+            ",
+        )
+        .bind(ws.id)
+        .bind(bot_id)
+        .bind(&input.name)
+        .bind(&key[..API_KEY_PREFIX.len() + 8])
+        .bind(hash_token(&key))
+        .bind(&scopes)
+        .bind(user.id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(ApiKeyOutput { key, api_key })
+    }
+
+    /// List the API keys of the workspace, only admins can do it
+    pub async fn list_api_keys(&self, user: &User) -> Result<Vec<ApiKey>, AppError> {
+        let ws = self.find_administered_workspace(user).await?;
+        let keys = sqlx::query_as(
+            "
+            SELECT id, ws_id, user_id AS bot_id, name, key_prefix, scopes, last_used_at,
+                revoked_at, created_at
+            FROM api_keys
+            WHERE ws_id = $1
+            ORDER BY id
+            ",
+        )
+        .bind(ws.id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(keys)
+    }
+
+    /// Revoke an API key of the workspace, only admins can do it
+    pub async fn revoke_api_key(&self, user: &User, id: i64) -> Result<(), AppError> {
+        let ws = self.find_administered_workspace(user).await?;
+        let ret = sqlx::query(
+            "
+            UPDATE api_keys
+            SET revoked_at = COALESCE(revoked_at, NOW())
+            WHERE id = $1 AND ws_id = $2
+            ",
+        )
+        .bind(id)
+        .bind(ws.id)
+        .execute(&self.pool)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("api key id: {}", id)));
+        }
+        Ok(())
+    }
+
+    /// The bot user of a valid API key, with the scopes of the key
+    pub async fn verify_api_key(&self, key: &str) -> Result<User, AppError> {
+        let user: Option<User> = sqlx::query_as(
+            "
+            WITH k AS (
+                UPDATE api_keys
+                SET last_used_at = NOW()
+                WHERE key_hash = $1 AND revoked_at IS NULL
+                RETURNING user_id, ws_id, scopes
+            )
+            SELECT u.id, u.ws_id, w.name AS ws_name, u.fullname, u.email, m.role,
+                u.created_at, k.scopes
+            FROM k
+            JOIN users u ON u.id = k.user_id AND u.ws_id = k.ws_id
+            JOIN workspace_members m ON m.user_id = u.id AND m.ws_id = k.ws_id
+                AND m.deactivated_at IS NULL
+            JOIN workspaces w ON w.id = u.ws_id
+            ",
+        )
+        .bind(hash_token(key))
+        .fetch_optional(&self.pool)
+        .await?;
+        user.ok_or(AppError::InvalidApiKey)
+    }
+
+    async fn create_bot_user(&self, ws_id: i64, name: &str) -> Result<i64, AppError> {
+        // bots never receive mail, the address only has to be unique
+        let email = format!("bot-{}@bots.local", Uuid::now_v7().simple());
+        let (id,): (i64,) = sqlx::query_as(
+            "
+            INSERT INTO users (ws_id, fullname, email, is_bot, email_verified_at)
+            VALUES ($1, $2, $3, TRUE, NOW())
+            RETURNING id
+            ",
+        )
+        .bind(ws_id)
+        .bind(name)
+        .bind(email)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+impl CreateApiKey {
+    pub fn new(name: &str, scopes: &[ApiScope]) -> Self {
+        Self {
+            name: name.to_string(),
+            scopes: scopes.to_vec(),
+            bot_id: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn api_key_lifecycle_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.unwrap();
+
+        let input = CreateApiKey::new("ci", &[ApiScope::PostMessages]);
+        let ret = state.create_api_key(&owner, &input).await?;
+        assert!(ret.key.starts_with(API_KEY_PREFIX));
+        assert!(ret.key.starts_with(&ret.api_key.key_prefix));
+        assert_eq!(ret.api_key.scopes, vec!["messages:write"]);
+
+        let bot = state.verify_api_key(&ret.key).await?;
+        assert_eq!(bot.id, ret.api_key.bot_id);
+        assert_eq!(bot.fullname, "ci");
+        assert_eq!(bot.ws_name, "acme");
+        assert_eq!(bot.scopes, Some(vec!["messages:write".to_string()]));
+
+        // a second key for the same bot
+        let input = CreateApiKey {
+            bot_id: Some(bot.id),
+            ..CreateApiKey::new("ci-2", &[ApiScope::ReadChats])
+        };
+        let ret2 = state.create_api_key(&owner, &input).await?;
+        assert_eq!(ret2.api_key.bot_id, bot.id);
+
+        let keys = state.list_api_keys(&owner).await?;
+        assert_eq!(keys.len(), 2);
+        assert!(keys[0].last_used_at.is_some());
+
+        state.revoke_api_key(&owner, ret.api_key.id).await?;
+        let ret = state.verify_api_key(&ret.key).await.unwrap_err();
+        assert_eq!(ret.to_string(), "invalid api key");
+        state.verify_api_key(&ret2.key).await?;
+
+        // bots can't sign in
+        assert!(
+            state
+                .verify_user(&crate::models::SigninUser::new(&bot.email, ""))
+                .await?
+                .is_none()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn only_admins_should_manage_api_keys() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let member = state.find_user_by_id(2).await?.unwrap();
+
+        let input = CreateApiKey::new("ci", &[ApiScope::PostMessages]);
+        let ret = state.create_api_key(&member, &input).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let ret = state.list_api_keys(&member).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        // a human user can't be used as a bot
+        let owner = state.find_user_by_id(1).await?.unwrap();
+        let input = CreateApiKey {
+            bot_id: Some(2),
+            ..input
+        };
+        let ret = state.create_api_key(&owner, &input).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        Ok(())
+    }
+}
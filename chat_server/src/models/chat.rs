@@ -1,5 +1,5 @@
-use crate::{AppError, AppState};
-use chat_core::{Chat, ChatType};
+use crate::{AppError, AppState, models::ensure_role};
+use chat_core::{Chat, ChatType, User, WorkspaceRole};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
@@ -44,9 +44,18 @@ impl AppState {
             ));
         };
 
-        // verify if all members exists
-        let users = self.fetch_chat_user_by_ids(&input.members).await?;
-        if users.len() != len {
+        // verify if all members exists and are active in the workspace
+        let (count,): (i64,) = sqlx::query_as(
+            "
+            SELECT COUNT(*) FROM workspace_members
+            WHERE ws_id = $1 AND user_id = ANY($2) AND deactivated_at IS NULL
+            ",
+        )
+        .bind(ws_id as i64)
+        .bind(&input.members)
+        .fetch_one(&self.pool)
+        .await?;
+        if count as usize != len {
             return Err(AppError::CreateChatError(
                 "some users not exists".to_string(),
             ));
@@ -110,6 +119,26 @@ impl AppState {
         Ok(chat)
     }
 
+    /// Delete a chat of the workspace with its messages, only admins can do it
+    pub async fn delete_chat(&self, user: &User, id: u64) -> Result<(), AppError> {
+        ensure_role(user, WorkspaceRole::Admin)?;
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM messages WHERE chat_id = $1")
+            .bind(id as i64)
+            .execute(&mut *tx)
+            .await?;
+        let ret = sqlx::query("DELETE FROM chats WHERE id = $1 AND ws_id = $2")
+            .bind(id as i64)
+            .bind(user.ws_id)
+            .execute(&mut *tx)
+            .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("chat id: {}", id)));
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
     pub async fn is_chat_member(&self, chat_id: u64, user_id: u64) -> Result<bool, AppError> {
         let is_member = sqlx::query(
             "
@@ -148,6 +177,24 @@ mod tests {
     use super::*;
     use anyhow::Result;
 
+    #[tokio::test]
+    async fn delete_chat_should_need_admin() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let member = state.find_user_by_id(2).await?.unwrap();
+        let owner = state.find_user_by_id(1).await?.unwrap();
+
+        let ret = state.delete_chat(&member, 1).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        state.delete_chat(&owner, 1).await?;
+        assert!(state.get_chat_by_id(1).await?.is_none());
+        let ret = state.delete_chat(&owner, 1).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn create_chat_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
@@ -0,0 +1,209 @@
+use crate::{
+    AppError, AppState,
+    mailer::Mail,
+    models::{generate_token, hash_token},
+};
+use chat_core::User;
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// verify the email of a user with the token from the verification mail
+#[derive(Debug, Serialize, ToSchema, Deserialize, Clone)]
+pub struct VerifyEmail {
+    pub token: String,
+}
+
+impl AppState {
+    /// Mail a verification link to the user
+    pub async fn send_email_verification(&self, user: &User) -> Result<(), AppError> {
+        let token = generate_token();
+        let duration = self.config.auth.email_verification_duration;
+        let expires_at = Utc::now() + Duration::seconds(duration as _);
+
+        sqlx::query(
+            "
+            INSERT INTO email_verification_tokens (user_id, token_hash, expires_at)
+            VALUES ($1, $2, $3)
+            ",
+        )
+        .bind(user.id)
+        .bind(hash_token(&token))
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        let body = format!(
+            "Hi {},\n\n\
+            Open the link below to verify your email, it expires in {} hours:\n\n\
+            {}/verify-email?token={}\n\n\
+            If you didn't sign up, you can ignore this mail.\n",
+            user.fullname,
+            duration / 3600,
+            self.config.server.web_url,
+            token
+        );
+        let mail = Mail {
+            to: user.email.clone(),
+            subject: "Verify your email".to_string(),
+            body,
+        };
+        self.mailer.send(mail).await
+    }
+
+    /// Mail a new verification link, unless the email is verified already
+    pub async fn resend_email_verification(&self, user_id: i64) -> Result<(), AppError> {
+        if self.is_email_verified(user_id).await? {
+            return Ok(());
+        }
+        let user = self
+            .find_user_by_id(user_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("user id: {}", user_id)))?;
+        self.send_email_verification(&user).await
+    }
+
+    /// Consume a verification token, the user joins the workspace it signed up for
+    pub async fn verify_email(&self, input: &VerifyEmail) -> Result<(), AppError> {
+        let user_id: Option<(i64,)> = sqlx::query_as(
+            "
+            UPDATE email_verification_tokens
+            SET used_at = NOW()
+            WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW()
+            RETURNING user_id
+            ",
+        )
+        .bind(hash_token(&input.token))
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let Some((user_id,)) = user_id else {
+            return Err(AppError::InvalidVerificationToken);
+        };
+
+        // the new workspace is picked up by the next token refresh
+        sqlx::query(
+            "
+            UPDATE users
+            SET email_verified_at = COALESCE(email_verified_at, NOW()),
+                ws_id = COALESCE(pending_ws_id, ws_id),
+                pending_ws_id = NULL
+            WHERE id = $1
+            ",
+        )
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query(
+            "
+            UPDATE email_verification_tokens
+            SET used_at = NOW()
+            WHERE user_id = $1 AND used_at IS NULL
+            ",
+        )
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn is_email_verified(&self, user_id: i64) -> Result<bool, AppError> {
+        let verified: Option<(bool,)> = sqlx::query_as(
+            "
+            SELECT email_verified_at IS NOT NULL
+            FROM users
+            WHERE id = $1
+            ",
+        )
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(verified.is_some_and(|(v,)| v))
+    }
+
+    /// Fail with `EmailNotVerified` if unverified users are blocked and the user is one of them
+    pub async fn ensure_email_verified(&self, user_id: i64) -> Result<(), AppError> {
+        if self.config.auth.require_verified_email && !self.is_email_verified(user_id).await? {
+            return Err(AppError::EmailNotVerified);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{CreateInvite, CreateUser};
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn verify_email_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let email = "TeamMeng@123.com";
+        let input = CreateUser::new("TeamMeng", "new-ws", email, "12345678");
+        let user = state.create_user(&input).await?;
+        assert!(!state.is_email_verified(user.id).await?);
+
+        state.send_email_verification(&user).await?;
+        let mails = state.read_mails(email);
+        assert_eq!(mails.len(), 1);
+        assert_eq!(mails[0].subject, "Verify your email");
+
+        let input = VerifyEmail {
+            token: mails[0].link_token(),
+        };
+        state.verify_email(&input).await?;
+        assert!(state.is_email_verified(user.id).await?);
+
+        // the token is single use
+        let ret = state.verify_email(&input).await.unwrap_err();
+        assert_eq!(ret.to_string(), "invalid email verification token");
+
+        // no more mails once verified
+        state.resend_email_verification(user.id).await?;
+        assert_eq!(state.read_mails(email).len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unverified_user_should_join_workspace_after_verification() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test_with(|config| {
+            config.auth.require_verified_email = true;
+        })
+        .await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.unwrap();
+        let invite = state
+            .create_invite(&owner, &CreateInvite::default())
+            .await?;
+        let email = "TeamMeng@123.com";
+        let input = CreateUser {
+            invite: Some(invite.code),
+            ..CreateUser::new("TeamMeng", "acme", email, "12345678")
+        };
+        let user = state.create_user(&input).await?;
+
+        // waiting in workspace 0, invisible to others
+        assert_eq!(user.ws_id, 0);
+        let users = state.fetch_all_chat_users(0).await?;
+        assert!(users.iter().all(|u| u.id != user.id));
+        let ret = state.ensure_email_verified(user.id).await.unwrap_err();
+        assert_eq!(ret.to_string(), "email not verified");
+
+        state.resend_email_verification(user.id).await?;
+        let input = VerifyEmail {
+            token: state.read_mails(email)[0].link_token(),
+        };
+        state.verify_email(&input).await?;
+
+        let user = state.find_user_by_id(user.id).await?.unwrap();
+        assert_eq!(user.ws_id, 1);
+        state.ensure_email_verified(user.id).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+use crate::{
+    AppError, AppState,
+    models::{generate_token, hash_token},
+};
+use chat_core::User;
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// a ticket to connect to `/events` of notify_server, pass it as `?ticket=`
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct EventTicket {
+    /// single use
+    pub ticket: String,
+    /// seconds before the ticket expires
+    pub expires_in: u64,
+}
+
+impl AppState {
+    /// Issue a short-lived single-use ticket for the user to connect to notify_server, in the
+    /// workspace of its token
+    pub async fn create_event_ticket(&self, user: &User) -> Result<EventTicket, AppError> {
+        let ticket = generate_token();
+        let duration = self.config.auth.event_ticket_duration;
+        let expires_at = Utc::now() + Duration::seconds(duration as _);
+
+        // unused tickets are of no use once expired
+        sqlx::query("DELETE FROM event_tickets WHERE expires_at < NOW()")
+            .execute(&self.pool)
+            .await?;
+
+        sqlx::query(
+            "
+            INSERT INTO event_tickets (ticket_hash, user_id, ws_id, session_id, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            ",
+        )
+        .bind(hash_token(&ticket))
+        .bind(user.id)
+        .bind(user.ws_id)
+        .bind(user.sid)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(EventTicket {
+            ticket,
+            expires_in: duration,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use chat_core::redeem_ticket;
+
+    #[tokio::test]
+    async fn event_ticket_should_be_single_use() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+
+        let ret = state.create_event_ticket(&user).await?;
+        assert_eq!(ret.expires_in, 30);
+
+        let redeemed = redeem_ticket(&state.pool, &ret.ticket).await?.unwrap();
+        assert_eq!(redeemed.id, user.id);
+        assert_eq!(redeemed.ws_id, user.ws_id);
+
+        assert!(redeem_ticket(&state.pool, &ret.ticket).await?.is_none());
+        assert!(redeem_ticket(&state.pool, "bad-ticket").await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn event_ticket_should_keep_its_workspace() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        sqlx::query("INSERT INTO workspace_members (ws_id, user_id, role) VALUES (2, 1, 'admin')")
+            .execute(&state.pool)
+            .await?;
+        let ticket = state.create_event_ticket(&user).await?.ticket;
+
+        // another session switched to foo in the meantime
+        sqlx::query("UPDATE users SET ws_id = 2, role = 'admin' WHERE id = 1")
+            .execute(&state.pool)
+            .await?;
+        let redeemed = redeem_ticket(&state.pool, &ticket).await?.unwrap();
+        assert_eq!(redeemed.ws_id, 1);
+        assert_eq!(redeemed.role, user.role);
+
+        // no ticket for a workspace the user was removed from
+        let ticket = state.create_event_ticket(&user).await?.ticket;
+        sqlx::query("DELETE FROM workspace_members WHERE ws_id = 1 AND user_id = 1")
+            .execute(&state.pool)
+            .await?;
+        assert!(redeem_ticket(&state.pool, &ticket).await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn expired_event_ticket_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test_with(|config| {
+            config.auth.event_ticket_duration = 0;
+        })
+        .await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+
+        let ret = state.create_event_ticket(&user).await?;
+        assert!(redeem_ticket(&state.pool, &ret.ticket).await?.is_none());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+use crate::{AppError, AppState};
+use chat_core::{User, WorkspaceRole};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// a token to act as another user, every request made with it is logged
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct ImpersonationOutput {
+    pub token: String,
+    /// seconds before the token expires, it can't be refreshed
+    pub expires_in: u64,
+    /// the user acted as, `impersonatedBy` is the admin
+    pub user: User,
+}
+
+impl AppState {
+    /// Issue a short-lived token for a system admin to act as a user.
+    ///
+    /// Admins, of the system or of a workspace, can't be impersonated.
+    pub async fn impersonate(
+        &self,
+        admin: &User,
+        user_id: i64,
+    ) -> Result<ImpersonationOutput, AppError> {
+        if admin.impersonated_by.is_some() || !self.is_system_admin(admin.id).await? {
+            return Err(AppError::PermissionDenied(
+                "requires a system admin".to_string(),
+            ));
+        }
+        let mut user = self
+            .find_user_by_id(user_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("user id: {}", user_id)))?;
+        if user.role.is_at_least(WorkspaceRole::Admin) || self.is_system_admin(user.id).await? {
+            return Err(AppError::PermissionDenied(
+                "admins can't be impersonated".to_string(),
+            ));
+        }
+        if let Some(ws) = self.find_workspace_by_id(user.ws_id as _).await? {
+            user.ws_name = ws.name;
+        }
+        user.impersonated_by = Some(admin.id);
+
+        let duration = self.config.auth.impersonation_duration;
+        let token = self.ek.sign_with_duration(user.clone(), duration)?;
+        self.log_impersonation(
+            admin.id,
+            user.id,
+            "POST",
+            &format!("/api/admin/users/{}/impersonate", user.id),
+            201,
+        )
+        .await?;
+
+        Ok(ImpersonationOutput {
+            token,
+            expires_in: duration,
+            user,
+        })
+    }
+
+    /// Record a request made by an admin acting as a user
+    pub async fn log_impersonation(
+        &self,
+        admin_id: i64,
+        user_id: i64,
+        method: &str,
+        path: &str,
+        status: u16,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            "
+            INSERT INTO impersonation_logs (admin_id, user_id, method, path, status)
+            VALUES ($1, $2, $3, $4, $5)
+            ",
+        )
+        .bind(admin_id)
+        .bind(user_id)
+        .bind(method)
+        .bind(path.chars().take(256).collect::<String>())
+        .bind(status as i16)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn is_system_admin(&self, user_id: i64) -> Result<bool, AppError> {
+        let ret: Option<(bool,)> =
+            sqlx::query_as("SELECT is_system_admin FROM users WHERE id = $1")
+                .bind(user_id)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(ret.is_some_and(|(v,)| v))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use chat_core::middlewares::TokenVerify;
+
+    #[tokio::test]
+    async fn impersonate_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        sqlx::query("UPDATE users SET is_system_admin = TRUE WHERE id = 5")
+            .execute(&state.pool)
+            .await?;
+        let admin = state.find_user_by_id(5).await?.unwrap();
+
+        let ret = state.impersonate(&admin, 2).await?;
+        assert_eq!(ret.expires_in, 600);
+        let user = state.verify(&ret.token).await?;
+        assert_eq!(user.id, 2);
+        assert_eq!(user.impersonated_by, Some(5));
+        assert_eq!(user.ws_name, "acme");
+
+        // no chaining
+        let ret = state.impersonate(&user, 3).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        // other users can't impersonate
+        let member = state.find_user_by_id(2).await?.unwrap();
+        let ret = state.impersonate(&member, 3).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        // admins can't be impersonated
+        state.update_workspace_owner(1, 1).await?;
+        let ret = state.impersonate(&admin, 1).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        let (count,): (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM impersonation_logs WHERE admin_id = 5")
+                .fetch_one(&state.pool)
+                .await?;
+        assert_eq!(count, 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,433 @@
+use crate::{
+    AppError, AppState,
+    mailer::Mail,
+    models::{generate_token, hash_token, validation::is_valid_email},
+};
+use chat_core::{User, Workspace, WorkspaceRole};
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection};
+use utoipa::ToSchema;
+
+// invites given out for longer are likely forgotten before they are revoked
+const MAX_INVITE_DURATION: u64 = 60 * 60 * 24 * 30;
+
+/// invite people to the workspace of the user
+#[derive(Debug, Default, Serialize, ToSchema, Deserialize, Clone)]
+pub struct CreateInvite {
+    /// bind the invite to this email and mail it, otherwise the code is shareable
+    #[serde(default)]
+    pub email: Option<String>,
+    /// member or guest, member if not set
+    #[serde(default)]
+    pub role: Option<WorkspaceRole>,
+    /// how many users can join with it, unlimited if not set, always 1 for an email
+    #[serde(default)]
+    pub max_uses: Option<i32>,
+    /// lifetime in seconds, up to 30 days, `auth.invite_duration` if not set
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+}
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct Invite {
+    pub id: i64,
+    pub ws_id: i64,
+    pub email: Option<String>,
+    pub role: WorkspaceRole,
+    pub max_uses: Option<i32>,
+    pub uses: i32,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// a new invite, the code is only shown once
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct InviteOutput {
+    pub code: String,
+    pub invite: Invite,
+}
+
+/// email domains whose verified users join the workspace without an invite
+#[derive(Debug, Serialize, ToSchema, Deserialize, Clone)]
+pub struct UpdateAutoJoinDomains {
+    pub domains: Vec<String>,
+}
+
+impl AppState {
+    /// Create an invite to the workspace of the user, only admins can do it
+    pub async fn create_invite(
+        &self,
+        user: &User,
+        input: &CreateInvite,
+    ) -> Result<InviteOutput, AppError> {
+        let ws = self.find_administered_workspace(user).await?;
+        let role = input.role.unwrap_or_default();
+        // promotions are done once the user joined
+        if !matches!(role, WorkspaceRole::Member | WorkspaceRole::Guest) {
+            return Err(AppError::InviteError(
+                "invites can only be for members or guests".to_string(),
+            ));
+        }
+        let email = input.email.as_deref().map(|e| e.trim().to_lowercase());
+        if let Some(email) = &email
+            && !is_valid_email(email)
+        {
+            return Err(AppError::InviteError(format!("invalid email: {}", email)));
+        }
+        let max_uses = match (&email, input.max_uses) {
+            (Some(_), _) => Some(1),
+            (None, Some(n)) if n < 1 => {
+                return Err(AppError::InviteError(
+                    "max_uses must be at least 1".to_string(),
+                ));
+            }
+            (None, n) => n,
+        };
+        let duration = match input.expires_in {
+            Some(secs) if secs == 0 || secs > MAX_INVITE_DURATION => {
+                return Err(AppError::InviteError(format!(
+                    "expires_in must be 1 to {} seconds",
+                    MAX_INVITE_DURATION
+                )));
+            }
+            Some(secs) => secs,
+            None => self.config.auth.invite_duration,
+        };
+        let expires_at = i64::try_from(duration)
+            .ok()
+            .and_then(TimeDelta::try_seconds)
+            .and_then(|delta| Utc::now().checked_add_signed(delta))
+            .ok_or_else(|| AppError::InviteError(format!("invalid duration: {}", duration)))?;
+
+        let code = generate_token();
+        let invite: Invite = sqlx::query_as(
+            "
+            INSERT INTO workspace_invites (ws_id, code_hash, email, role, max_uses, expires_at, created_by)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id, ws_id, email, role, max_uses, uses, expires_at, revoked_at, created_at
+            ",
+        )
+        .bind(ws.id)
+        .bind(hash_token(&code))
+        .bind(&email)
+        .bind(role)
+        .bind(max_uses)
+        .bind(expires_at)
+        .bind(user.id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        if let Some(email) = email {
+            self.send_invite(user, &ws, &email, &code, duration).await?;
+        }
+        Ok(InviteOutput { code, invite })
+    }
+
+    /// List the invites of the workspace, only admins can do it
+    pub async fn list_invites(&self, user: &User) -> Result<Vec<Invite>, AppError> {
+        let ws = self.find_administered_workspace(user).await?;
+        let invites = sqlx::query_as(
+            "
+            SELECT id, ws_id, email, role, max_uses, uses, expires_at, revoked_at, created_at
+            FROM workspace_invites
+            WHERE ws_id = $1
+            ORDER BY id
+            ",
+        )
+        .bind(ws.id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(invites)
+    }
+
+    /// Revoke an invite of the workspace, only admins can do it
+    pub async fn revoke_invite(&self, user: &User, id: i64) -> Result<(), AppError> {
+        let ws = self.find_administered_workspace(user).await?;
+        let ret = sqlx::query(
+            "
+            UPDATE workspace_invites
+            SET revoked_at = COALESCE(revoked_at, NOW())
+            WHERE id = $1 AND ws_id = $2
+            ",
+        )
+        .bind(id)
+        .bind(ws.id)
+        .execute(&self.pool)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("invite id: {}", id)));
+        }
+        Ok(())
+    }
+
+    /// Set the email domains allowed to join the workspace without an invite
+    pub async fn update_auto_join_domains(
+        &self,
+        user: &User,
+        input: &UpdateAutoJoinDomains,
+    ) -> Result<(), AppError> {
+        let ws = self.find_administered_workspace(user).await?;
+        let domains: Vec<String> = input
+            .domains
+            .iter()
+            .map(|d| d.trim().trim_start_matches('@').to_lowercase())
+            .collect();
+        if let Some(domain) = domains
+            .iter()
+            .find(|d| !is_valid_email(&format!("user@{}", d)))
+        {
+            return Err(AppError::InviteError(format!("invalid domain: {}", domain)));
+        }
+
+        sqlx::query("UPDATE workspaces SET auto_join_domains = $1 WHERE id = $2")
+            .bind(&domains)
+            .bind(ws.id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Use an invite to join the workspace, returns the role it grants.
+    ///
+    /// Done in the transaction adding the user to the workspace, so the use is given back when
+    /// that fails.
+    pub(crate) async fn redeem_invite(
+        &self,
+        conn: &mut PgConnection,
+        ws: &Workspace,
+        code: &str,
+        email: &str,
+    ) -> Result<WorkspaceRole, AppError> {
+        let role: Option<(WorkspaceRole,)> = sqlx::query_as(
+            "
+            UPDATE workspace_invites
+            SET uses = uses + 1
+            WHERE code_hash = $1 AND ws_id = $2 AND revoked_at IS NULL AND expires_at > NOW()
+                AND (max_uses IS NULL OR uses < max_uses)
+                AND (email IS NULL OR email = LOWER($3))
+            RETURNING role
+            ",
+        )
+        .bind(hash_token(code))
+        .bind(ws.id)
+        .bind(email)
+        .fetch_optional(conn)
+        .await?;
+        role.map(|(role,)| role).ok_or(AppError::InvalidInvite)
+    }
+
+    /// Whether the domain of the email lets users join the workspace without an invite
+    pub(crate) async fn is_auto_join_email(
+        &self,
+        ws: &Workspace,
+        email: &str,
+    ) -> Result<bool, AppError> {
+        let Some((_, domain)) = email.rsplit_once('@') else {
+            return Ok(false);
+        };
+        let ret: Option<(bool,)> = sqlx::query_as(
+            "SELECT LOWER($2) = ANY(auto_join_domains) FROM workspaces WHERE id = $1",
+        )
+        .bind(ws.id)
+        .bind(domain)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(ret.is_some_and(|(v,)| v))
+    }
+
+    async fn send_invite(
+        &self,
+        user: &User,
+        ws: &Workspace,
+        email: &str,
+        code: &str,
+        duration: u64,
+    ) -> Result<(), AppError> {
+        let body = format!(
+            "Hi,\n\n\
+            {} invited you to join the {} workspace. Open the link below to sign up, \
+            it expires in {} days:\n\n\
+            {}/signup?workspace={}&invite={}\n",
+            user.fullname,
+            ws.name,
+            duration.div_ceil(60 * 60 * 24),
+            self.config.server.web_url,
+            ws.name,
+            code
+        );
+        let mail = Mail {
+            to: email.to_string(),
+            subject: format!("Join {} on chat", ws.name),
+            body,
+        };
+        self.mailer.send(mail).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::CreateUser;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn shareable_invite_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.unwrap();
+
+        // no invite, no way in
+        let input = CreateUser::new("TeamMeng", "acme", "TeamMeng@123.com", "12345678");
+        let ret = state.create_user(&input).await;
+        assert!(matches!(ret, Err(AppError::InviteRequired(_))));
+
+        let input = CreateInvite {
+            role: Some(WorkspaceRole::Guest),
+            max_uses: Some(1),
+            ..Default::default()
+        };
+        let ret = state.create_invite(&owner, &input).await?;
+        assert_eq!(ret.invite.max_uses, Some(1));
+
+        let input = CreateUser {
+            invite: Some(ret.code.clone()),
+            ..CreateUser::new("TeamMeng", "acme", "TeamMeng@123.com", "12345678")
+        };
+        let user = state.create_user(&input).await?;
+        assert_eq!(user.ws_id, 1);
+        assert_eq!(user.role, WorkspaceRole::Guest);
+
+        // used up
+        let input = CreateUser {
+            invite: Some(ret.code),
+            ..CreateUser::new("Eve", "acme", "Eve@123.com", "12345678")
+        };
+        let ret = state.create_user(&input).await;
+        assert!(matches!(ret, Err(AppError::InvalidInvite)));
+
+        let invites = state.list_invites(&owner).await?;
+        assert_eq!(invites.len(), 1);
+        assert_eq!(invites[0].uses, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn email_invite_should_be_bound_to_email() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.unwrap();
+
+        let input = CreateInvite {
+            email: Some("TeamMeng@123.com".to_string()),
+            ..Default::default()
+        };
+        let ret = state.create_invite(&owner, &input).await?;
+        assert_eq!(ret.invite.max_uses, Some(1));
+        let mails = state.read_mails("teammeng@123.com");
+        assert_eq!(mails.len(), 1);
+        assert!(mails[0].body.contains(&ret.code));
+
+        let input = CreateUser {
+            invite: Some(ret.code.clone()),
+            ..CreateUser::new("Eve", "acme", "Eve@123.com", "12345678")
+        };
+        let ret2 = state.create_user(&input).await;
+        assert!(matches!(ret2, Err(AppError::InvalidInvite)));
+
+        state.revoke_invite(&owner, ret.invite.id).await?;
+        let input = CreateUser {
+            invite: Some(ret.code),
+            ..CreateUser::new("TeamMeng", "acme", "TeamMeng@123.com", "12345678")
+        };
+        let ret = state.create_user(&input).await;
+        assert!(matches!(ret, Err(AppError::InvalidInvite)));
+
+        // members can't invite
+        let member = state.find_user_by_id(2).await?.unwrap();
+        let ret = state.create_invite(&member, &CreateInvite::default()).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invite_lifetime_should_be_bounded() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.unwrap();
+
+        for expires_in in [0, MAX_INVITE_DURATION + 1, 1 << 63, u64::MAX] {
+            let input = CreateInvite {
+                expires_in: Some(expires_in),
+                ..Default::default()
+            };
+            let ret = state.create_invite(&owner, &input).await;
+            assert!(matches!(ret, Err(AppError::InviteError(_))));
+        }
+
+        let input = CreateInvite {
+            expires_in: Some(MAX_INVITE_DURATION),
+            ..Default::default()
+        };
+        let ret = state.create_invite(&owner, &input).await?;
+        assert!(ret.invite.expires_at > Utc::now() + TimeDelta::days(29));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_signup_should_not_use_up_invite() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.unwrap();
+        let input = CreateInvite {
+            max_uses: Some(1),
+            ..Default::default()
+        };
+        let code = state.create_invite(&owner, &input).await?.code;
+
+        // the user can't be inserted after the invite is redeemed
+        sqlx::query("ALTER TABLE users ADD CONSTRAINT no_eve CHECK (fullname <> 'Eve')")
+            .execute(&state.pool)
+            .await?;
+        let input = CreateUser {
+            invite: Some(code),
+            ..CreateUser::new("Eve", "acme", "Eve@123.com", "12345678")
+        };
+        assert!(state.create_user(&input).await.is_err());
+        assert_eq!(state.list_invites(&owner).await?[0].uses, 0);
+
+        sqlx::query("ALTER TABLE users DROP CONSTRAINT no_eve")
+            .execute(&state.pool)
+            .await?;
+        let user = state.create_user(&input).await?;
+        assert_eq!(user.ws_id, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn auto_join_domain_should_need_verified_email() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.unwrap();
+        let input = UpdateAutoJoinDomains {
+            domains: vec!["@Acme.org".to_string()],
+        };
+        state.update_auto_join_domains(&owner, &input).await?;
+
+        let input = CreateUser::new("TeamMeng", "acme", "TeamMeng@acme.org", "12345678");
+        let user = state.create_user(&input).await?;
+        // waiting in workspace 0 until verified
+        assert_eq!(user.ws_id, 0);
+
+        let input = CreateUser::new("Eve", "acme", "Eve@evil.org", "12345678");
+        let ret = state.create_user(&input).await;
+        assert!(matches!(ret, Err(AppError::InviteRequired(_))));
+
+        Ok(())
+    }
+}
@@ -1,14 +1,52 @@
+mod api_key;
 mod chat;
+mod email_verification;
+mod event_ticket;
 mod file;
+mod impersonation;
+mod invite;
 mod message;
+mod password_reset;
+mod profile;
+mod refresh_token;
+mod session;
+mod signin_limit;
+mod sso;
+mod status;
+mod two_factor;
 mod user;
+mod validation;
 mod workspace;
 
+pub use api_key::{API_KEY_PREFIX, ApiKey, ApiKeyOutput, ApiScope, CreateApiKey};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
 pub use chat::CreateChat;
+pub(crate) use chat_core::hash_token;
+pub use email_verification::VerifyEmail;
+pub use event_ticket::EventTicket;
+pub use impersonation::ImpersonationOutput;
+pub use invite::{CreateInvite, Invite, InviteOutput, UpdateAutoJoinDomains};
 pub use message::{CreateMessage, ListMessages};
+pub use password_reset::{ForgotPassword, ResetPassword};
+#[cfg(test)]
+pub(crate) use profile::test_image;
+pub use profile::{Profile, UpdateProfile};
+pub use refresh_token::{LogoutUser, RefreshToken};
 use serde::{Deserialize, Serialize};
-pub use user::{CreateUser, SigninUser};
+pub use session::Session;
+pub use sso::{SsoCallback, UpdateWorkspaceSso};
+pub use status::{Dnd, DndWindow, UpdateDnd, UpdateStatus};
+pub use two_factor::{
+    RecoveryCodes, SigninChallenge, TotpSetup, TwoFactorCode, UpdateTwoFactorPolicy,
+    VerifySigninChallenge,
+};
+pub use user::{ChangePassword, CreateUser, ListUsers, SigninUser};
 use utoipa::ToSchema;
+pub(crate) use workspace::ensure_role;
+pub use workspace::{
+    DeleteWorkspace, JoinWorkspace, Membership, TransferOwnership, UpdateMemberRole,
+    UpdateWorkspace,
+};
 
 #[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
 pub struct ChatFile {
@@ -16,3 +54,10 @@ pub struct ChatFile {
     pub ext: String, // extract ext from filename or mine type
     pub hash: String,
 }
+
+/// Generate a random opaque token, e.g. for refresh, password reset or email verification
+pub(crate) fn generate_token() -> String {
+    let mut buf = [0u8; 32];
+    OsRng.fill_bytes(&mut buf);
+    hex::encode(buf)
+}
@@ -0,0 +1,185 @@
+use crate::{
+    AppError, AppState,
+    mailer::Mail,
+    models::{generate_token, hash_token},
+};
+use chat_core::revoke_user_tokens;
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// ask for a password reset link
+#[derive(Debug, Serialize, ToSchema, Deserialize, Clone)]
+pub struct ForgotPassword {
+    pub email: String,
+}
+
+/// set a new password with the token from the reset mail
+#[derive(Debug, Serialize, ToSchema, Deserialize, Clone)]
+pub struct ResetPassword {
+    pub token: String,
+    pub password: String,
+}
+
+impl AppState {
+    /// Mail a password reset link if a user with the email exists
+    pub async fn send_password_reset(&self, input: &ForgotPassword) -> Result<(), AppError> {
+        let Some(user) = self.find_user_by_email(&input.email).await? else {
+            return Ok(());
+        };
+
+        let token = generate_token();
+        let duration = self.config.auth.password_reset_duration;
+        let expires_at = Utc::now() + Duration::seconds(duration as _);
+
+        sqlx::query(
+            "
+            INSERT INTO password_reset_tokens (user_id, token_hash, expires_at)
+            VALUES ($1, $2, $3)
+            ",
+        )
+        .bind(user.id)
+        .bind(hash_token(&token))
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        let body = format!(
+            "Hi {},\n\n\
+            Open the link below to reset your password, it expires in {} minutes:\n\n\
+            {}/reset-password?token={}\n\n\
+            If you didn't ask for a password reset, you can ignore this mail.\n",
+            user.fullname,
+            duration / 60,
+            self.config.server.web_url,
+            token
+        );
+        let mail = Mail {
+            to: user.email,
+            subject: "Reset your password".to_string(),
+            body,
+        };
+        self.mailer.send(mail).await
+    }
+
+    /// Consume a reset token and set the new password, all sessions of the user are revoked
+    pub async fn reset_password(&self, input: &ResetPassword) -> Result<(), AppError> {
+        self.validate_password("password", &input.password)?;
+        let user_id: Option<(i64,)> = sqlx::query_as(
+            "
+            UPDATE password_reset_tokens
+            SET used_at = NOW()
+            WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW()
+            RETURNING user_id
+            ",
+        )
+        .bind(hash_token(&input.token))
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let Some((user_id,)) = user_id else {
+            return Err(AppError::InvalidResetToken);
+        };
+
+        self.update_password(user_id, &input.password).await?;
+
+        // other reset links mailed before are no longer needed
+        sqlx::query(
+            "
+            UPDATE password_reset_tokens
+            SET used_at = NOW()
+            WHERE user_id = $1 AND used_at IS NULL
+            ",
+        )
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+
+        revoke_user_tokens(&self.pool, user_id).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl ResetPassword {
+    pub fn new(token: &str, password: &str) -> Self {
+        Self {
+            token: token.to_string(),
+            password: password.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::SigninUser;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn reset_password_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let email = "Test@123.com";
+
+        let input = ForgotPassword {
+            email: email.to_string(),
+        };
+        state.send_password_reset(&input).await?;
+        let mails = state.read_mails(email);
+        assert_eq!(mails.len(), 1);
+        assert_eq!(mails[0].subject, "Reset your password");
+        let token = mails[0].link_token();
+
+        let input = ResetPassword::new(&token, "new-password");
+        state.reset_password(&input).await?;
+
+        let user = state
+            .verify_user(&SigninUser::new(email, "new-password"))
+            .await?;
+        assert!(user.is_some());
+        let user = state.verify_user(&SigninUser::new(email, "123456")).await?;
+        assert!(user.is_none());
+
+        // the token is single use
+        let ret = state.reset_password(&input).await.unwrap_err();
+        assert_eq!(ret.to_string(), "invalid password reset token");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn forgot_password_for_unknown_email_should_not_send_mail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let email = "TeamMeng@123.com";
+
+        let input = ForgotPassword {
+            email: email.to_string(),
+        };
+        state.send_password_reset(&input).await?;
+        assert!(state.read_mails(email).is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn expired_reset_token_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let email = "Test@123.com";
+
+        let input = ForgotPassword {
+            email: email.to_string(),
+        };
+        state.send_password_reset(&input).await?;
+        let token = state.read_mails(email)[0].link_token();
+
+        sqlx::query("UPDATE password_reset_tokens SET expires_at = NOW() - INTERVAL '1 minute'")
+            .execute(&state.pool)
+            .await?;
+
+        let input = ResetPassword::new(&token, "new-password");
+        let ret = state.reset_password(&input).await.unwrap_err();
+        assert_eq!(ret.to_string(), "invalid password reset token");
+
+        Ok(())
+    }
+}
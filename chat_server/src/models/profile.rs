@@ -0,0 +1,326 @@
+use crate::{
+    AppError, AppState,
+    models::{
+        ChatFile,
+        validation::{FULLNAME_MAX_LEN, Validator},
+    },
+};
+use chat_core::User;
+use chrono::{DateTime, Utc};
+use image::{ImageFormat, ImageReader, Limits, imageops::FilterType};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::io::Cursor;
+use tokio::fs;
+use utoipa::ToSchema;
+
+/// avatars are stored as square png images of this size
+pub(crate) const AVATAR_SIZE: u32 = 256;
+/// larger images are rejected before being decoded
+const AVATAR_MAX_DIMENSION: u32 = 4096;
+const TITLE_MAX_LEN: usize = 64;
+const TIMEZONE_MAX_LEN: usize = 64;
+
+/// The profile of the current user, `fullname` is the name shown to others
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Profile {
+    pub id: i64,
+    pub ws_id: i64,
+    pub fullname: String,
+    pub email: String,
+    pub title: Option<String>,
+    /// IANA time zone, e.g. `Europe/Paris`
+    pub timezone: Option<String>,
+    /// chat file of the avatar
+    pub avatar_url: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Fields left out are unchanged, empty `title` or `timezone` clear them
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct UpdateProfile {
+    #[serde(default)]
+    pub fullname: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub timezone: Option<String>,
+}
+
+impl AppState {
+    pub async fn get_profile(&self, user: &User) -> Result<Profile, AppError> {
+        sqlx::query_as(
+            "
+            SELECT id, ws_id, fullname, email, title, timezone, avatar_url, created_at
+            FROM users
+            WHERE id = $1
+            ",
+        )
+        .bind(user.id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("user id: {}", user.id)))
+    }
+
+    pub async fn update_profile(
+        &self,
+        user: &User,
+        input: &UpdateProfile,
+    ) -> Result<Profile, AppError> {
+        let input = input.normalize();
+        let is_known_timezone = match input.timezone.as_deref().filter(|v| !v.is_empty()) {
+            Some(timezone) => self.is_known_timezone(timezone).await?,
+            None => true,
+        };
+        input.validate(is_known_timezone)?;
+
+        sqlx::query(
+            "
+            UPDATE users
+            SET fullname = COALESCE($2, fullname),
+                title = CASE WHEN $3::TEXT IS NULL THEN title ELSE NULLIF($3, '') END,
+                timezone = CASE WHEN $4::TEXT IS NULL THEN timezone ELSE NULLIF($4, '') END
+            WHERE id = $1
+            ",
+        )
+        .bind(user.id)
+        .bind(&input.fullname)
+        .bind(&input.title)
+        .bind(&input.timezone)
+        .execute(&self.pool)
+        .await?;
+
+        self.get_profile(user).await
+    }
+
+    /// Resize the image to a square avatar and store it as a chat file of the workspace, the
+    /// other workspaces of the user see it as well
+    pub async fn update_avatar(&self, user: &User, data: Vec<u8>) -> Result<Profile, AppError> {
+        let data = tokio::task::spawn_blocking(move || resize_avatar(&data))
+            .await
+            .map_err(|e| AppError::ChatFileError(format!("failed to resize avatar: {}", e)))??;
+
+        let file = ChatFile::new(user.ws_id as _, "avatar.png", &data);
+        let path = file.path(&self.config.server.base_dir);
+        if !path.exists() {
+            fs::create_dir_all(path.parent().expect("file path parent should exists")).await?;
+            fs::write(path, &data).await?;
+        }
+
+        self.set_avatar_url(user, Some(file.url())).await
+    }
+
+    /// Remove the avatar of the user, the file is kept as messages may refer to it
+    pub async fn delete_avatar(&self, user: &User) -> Result<Profile, AppError> {
+        self.set_avatar_url(user, None).await
+    }
+
+    /// Whether the file is the avatar of a user of the workspace of the user, wherever the
+    /// avatar was uploaded
+    pub async fn is_avatar_visible(&self, user: &User, url: &str) -> Result<bool, AppError> {
+        let (visible,): (bool,) = sqlx::query_as(
+            "
+            SELECT EXISTS (
+                SELECT 1
+                FROM users u JOIN workspace_members m ON m.user_id = u.id
+                WHERE u.avatar_url = $1 AND m.ws_id = $2
+            )
+            ",
+        )
+        .bind(url)
+        .bind(user.ws_id)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(visible)
+    }
+
+    /// Times are converted to the zone in queries, postgres has to know it
+    async fn is_known_timezone(&self, timezone: &str) -> Result<bool, AppError> {
+        let (exists,): (bool,) =
+            sqlx::query_as("SELECT EXISTS (SELECT 1 FROM pg_timezone_names WHERE name = $1)")
+                .bind(timezone)
+                .fetch_one(&self.pool)
+                .await?;
+        Ok(exists)
+    }
+
+    async fn set_avatar_url(&self, user: &User, url: Option<String>) -> Result<Profile, AppError> {
+        sqlx::query("UPDATE users SET avatar_url = $2 WHERE id = $1")
+            .bind(user.id)
+            .bind(url)
+            .execute(&self.pool)
+            .await?;
+        self.get_profile(user).await
+    }
+}
+
+impl UpdateProfile {
+    fn normalize(&self) -> Self {
+        let trim = |v: &Option<String>| v.as_ref().map(|v| v.trim().to_string());
+        Self {
+            fullname: trim(&self.fullname),
+            title: trim(&self.title),
+            timezone: trim(&self.timezone),
+        }
+    }
+
+    fn validate(&self, is_known_timezone: bool) -> Result<(), AppError> {
+        let mut validator = Validator::default();
+        if let Some(fullname) = &self.fullname {
+            validator.length("fullname", fullname, 1, FULLNAME_MAX_LEN);
+        }
+        if let Some(title) = &self.title {
+            validator.length("title", title, 0, TITLE_MAX_LEN);
+        }
+        if let Some(timezone) = &self.timezone {
+            validator
+                .length("timezone", timezone, 0, TIMEZONE_MAX_LEN)
+                .check(
+                    "timezone",
+                    is_known_timezone,
+                    "must be an IANA time zone, e.g. Europe/Paris",
+                );
+        }
+        validator.finish()
+    }
+}
+
+/// Crop the image to a square around its center and scale it to `AVATAR_SIZE`, as png
+fn resize_avatar(data: &[u8]) -> Result<Vec<u8>, AppError> {
+    let invalid = |e: image::ImageError| AppError::ChatFileError(format!("invalid avatar: {}", e));
+    let mut reader = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .map_err(AppError::IoError)?;
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(AVATAR_MAX_DIMENSION);
+    limits.max_image_height = Some(AVATAR_MAX_DIMENSION);
+    reader.limits(limits);
+
+    let image = reader.decode().map_err(invalid)?.resize_to_fill(
+        AVATAR_SIZE,
+        AVATAR_SIZE,
+        FilterType::Triangle,
+    );
+    let mut buf = Cursor::new(Vec::new());
+    image
+        .write_to(&mut buf, ImageFormat::Png)
+        .map_err(invalid)?;
+    Ok(buf.into_inner())
+}
+
+#[cfg(test)]
+pub(crate) fn test_image(width: u32, height: u32) -> Vec<u8> {
+    let image = image::RgbImage::from_pixel(width, height, image::Rgb([200, 100, 50]));
+    let mut buf = Cursor::new(Vec::new());
+    image
+        .write_to(&mut buf, ImageFormat::Png)
+        .expect("png should be encoded");
+    buf.into_inner()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn update_profile_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+
+        let input = UpdateProfile {
+            fullname: Some(" Tyr Chen ".to_string()),
+            title: Some("Engineer".to_string()),
+            timezone: Some("Asia/Shanghai".to_string()),
+        };
+        let profile = state.update_profile(&user, &input).await?;
+        assert_eq!(profile.fullname, "Tyr Chen");
+        assert_eq!(profile.title.as_deref(), Some("Engineer"));
+        assert_eq!(profile.timezone.as_deref(), Some("Asia/Shanghai"));
+
+        // missing fields are kept, empty ones cleared
+        let input = UpdateProfile {
+            title: Some("".to_string()),
+            ..Default::default()
+        };
+        let profile = state.update_profile(&user, &input).await?;
+        assert_eq!(profile.fullname, "Tyr Chen");
+        assert_eq!(profile.title, None);
+        assert_eq!(profile.timezone.as_deref(), Some("Asia/Shanghai"));
+
+        let input = UpdateProfile {
+            fullname: Some(" ".to_string()),
+            timezone: Some("Mars Time".to_string()),
+            ..Default::default()
+        };
+        let ret = state.update_profile(&user, &input).await;
+        let Err(AppError::InvalidInput(fields)) = ret else {
+            panic!("expected invalid input");
+        };
+        let fields: Vec<_> = fields.iter().map(|f| f.field.as_str()).collect();
+        assert_eq!(fields, ["fullname", "timezone"]);
+
+        let input = UpdateProfile {
+            timezone: Some("Mars/Olympus_Mons".to_string()),
+            ..Default::default()
+        };
+        let ret = state.update_profile(&user, &input).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_avatar_should_resize_image() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+
+        let profile = state.update_avatar(&user, test_image(400, 300)).await?;
+        let url = profile.avatar_url.unwrap();
+        let file: ChatFile = url.parse()?;
+        assert_eq!(file.ws_id, 1);
+        let image = image::open(file.path(&state.config.server.base_dir))?;
+        assert_eq!((image.width(), image.height()), (AVATAR_SIZE, AVATAR_SIZE));
+
+        let users = state.fetch_all_chat_users(1).await?;
+        let chat_user = users.iter().find(|u| u.id == 1).unwrap();
+        assert_eq!(chat_user.avatar_url.as_deref(), Some(url.as_str()));
+
+        let ret = state.update_avatar(&user, b"not an image".to_vec()).await;
+        assert!(matches!(ret, Err(AppError::ChatFileError(_))));
+
+        let profile = state.delete_avatar(&user).await?;
+        assert_eq!(profile.avatar_url, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn avatar_should_be_visible_in_other_workspaces() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        let url = state
+            .update_avatar(&user, test_image(100, 100))
+            .await?
+            .avatar_url
+            .unwrap();
+        let (peer_id,): (i64,) = sqlx::query_as(
+            "INSERT INTO users (ws_id, fullname, email) VALUES (2, 'Foo', 'foo@123.com') RETURNING id",
+        )
+        .fetch_one(&state.pool)
+        .await?;
+        let peer = state.find_user_by_id(peer_id).await?.unwrap();
+        assert!(!state.is_avatar_visible(&peer, &url).await?);
+
+        // once user 1 joins foo, its avatar uploaded in acme shows there
+        sqlx::query("INSERT INTO workspace_members (ws_id, user_id) VALUES (2, 1)")
+            .execute(&state.pool)
+            .await?;
+        assert!(state.is_avatar_visible(&peer, &url).await?);
+        let file = ChatFile::new(1, "notes.txt", b"not an avatar");
+        assert!(!state.is_avatar_visible(&peer, &file.url()).await?);
+
+        Ok(())
+    }
+}
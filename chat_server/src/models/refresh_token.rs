@@ -0,0 +1,273 @@
+use crate::{
+    AppError, AppState,
+    models::{generate_token, hash_token},
+};
+use chat_core::User;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// exchange a refresh token for a new access and refresh token pair
+#[derive(Debug, Serialize, ToSchema, Deserialize, Clone)]
+pub struct RefreshToken {
+    pub refresh_token: String,
+}
+
+/// log out the current device, optionally revoking its refresh token as well
+#[derive(Debug, Default, Serialize, ToSchema, Deserialize, Clone)]
+pub struct LogoutUser {
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Debug, FromRow)]
+struct RefreshTokenRecord {
+    id: i64,
+    user_id: i64,
+    family_id: Uuid,
+    expires_at: DateTime<Utc>,
+    rotated_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+impl AppState {
+    /// Issue a refresh token for the user, starting a new family if none is given
+    pub async fn create_refresh_token(
+        &self,
+        user_id: i64,
+        family_id: Option<Uuid>,
+    ) -> Result<String, AppError> {
+        let token = generate_token();
+        let family_id = family_id.unwrap_or_else(Uuid::now_v7);
+        let expires_at = Utc::now() + Duration::seconds(self.config.auth.refresh_duration as _);
+
+        sqlx::query(
+            "
+            INSERT INTO refresh_tokens (user_id, family_id, token_hash, expires_at)
+            VALUES ($1, $2, $3, $4)
+            ",
+        )
+        .bind(user_id)
+        .bind(family_id)
+        .bind(hash_token(&token))
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(token)
+    }
+
+    /// Rotate a refresh token: the old one is consumed and a new one in the same family is issued.
+    ///
+    /// Presenting a token which was already rotated means it leaked, so the whole family is revoked.
+    pub async fn rotate_refresh_token(&self, token: &str) -> Result<(User, String), AppError> {
+        let record: Option<RefreshTokenRecord> = sqlx::query_as(
+            "
+            SELECT id, user_id, family_id, expires_at, rotated_at, revoked_at
+            FROM refresh_tokens
+            WHERE token_hash = $1
+            ",
+        )
+        .bind(hash_token(token))
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let Some(record) = record else {
+            return Err(AppError::InvalidRefreshToken(
+                "refresh token not found".to_string(),
+            ));
+        };
+
+        if record.revoked_at.is_some() {
+            return Err(AppError::InvalidRefreshToken(
+                "refresh token revoked".to_string(),
+            ));
+        }
+
+        if record.rotated_at.is_some() {
+            self.revoke_refresh_token_family(record.family_id).await?;
+            return Err(AppError::InvalidRefreshToken(
+                "refresh token reuse detected".to_string(),
+            ));
+        }
+
+        if record.expires_at <= Utc::now() {
+            return Err(AppError::InvalidRefreshToken(
+                "refresh token expired".to_string(),
+            ));
+        }
+
+        // another request may have rotated the token in the meantime
+        let ret = sqlx::query(
+            "
+            UPDATE refresh_tokens
+            SET rotated_at = NOW()
+            WHERE id = $1 AND rotated_at IS NULL AND revoked_at IS NULL
+            ",
+        )
+        .bind(record.id)
+        .execute(&self.pool)
+        .await?;
+
+        if ret.rows_affected() == 0 {
+            self.revoke_refresh_token_family(record.family_id).await?;
+            return Err(AppError::InvalidRefreshToken(
+                "refresh token reuse detected".to_string(),
+            ));
+        }
+
+        // sessions stay in the workspace they switched to, while the user is a member of it
+        let (ws_id,): (Option<i64>,) = sqlx::query_as("SELECT ws_id FROM sessions WHERE id = $1")
+            .bind(record.family_id)
+            .fetch_optional(&self.pool)
+            .await?
+            .unwrap_or((None,));
+        let member = match ws_id {
+            Some(ws_id) => self.find_member(record.user_id, ws_id).await?,
+            None => None,
+        };
+        let mut user = match member {
+            Some(user) => user,
+            None => {
+                let mut user = self
+                    .find_user_by_id(record.user_id)
+                    .await?
+                    .ok_or_else(|| AppError::NotFound(format!("user id: {}", record.user_id)))?;
+                let ws = self.find_user_workspace(&user).await?;
+                user.ws_name = ws.name;
+                user
+            }
+        };
+        user.sid = Some(record.family_id);
+
+        let token = self
+            .create_refresh_token(record.user_id, Some(record.family_id))
+            .await?;
+
+        Ok((user, token))
+    }
+
+    /// Revoke the family of a refresh token owned by the user
+    pub async fn revoke_refresh_token(&self, user_id: i64, token: &str) -> Result<(), AppError> {
+        let family_id: Option<(Uuid,)> = sqlx::query_as(
+            "SELECT family_id FROM refresh_tokens WHERE token_hash = $1 AND user_id = $2",
+        )
+        .bind(hash_token(token))
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        match family_id {
+            Some((family_id,)) => self.revoke_refresh_token_family(family_id).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Revoke every refresh token of a family, ending its session
+    pub async fn revoke_refresh_token_family(&self, family_id: Uuid) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "
+            UPDATE refresh_tokens
+            SET revoked_at = NOW()
+            WHERE family_id = $1 AND revoked_at IS NULL
+            ",
+        )
+        .bind(family_id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("UPDATE sessions SET revoked_at = NOW() WHERE id = $1 AND revoked_at IS NULL")
+            .bind(family_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl RefreshToken {
+    pub fn new(refresh_token: &str) -> Self {
+        Self {
+            refresh_token: refresh_token.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn rotate_refresh_token_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let token = state.create_refresh_token(1, None).await?;
+
+        let (user, new_token) = state.rotate_refresh_token(&token).await?;
+        assert_eq!(user.id, 1);
+        assert_eq!(user.ws_name, "acme");
+        assert_ne!(token, new_token);
+
+        // the new token can be rotated again
+        let (user, _) = state.rotate_refresh_token(&new_token).await?;
+        assert_eq!(user.id, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reused_refresh_token_should_revoke_family() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let token = state.create_refresh_token(1, None).await?;
+        let (_, new_token) = state.rotate_refresh_token(&token).await?;
+
+        // reuse of the old token
+        let ret = state.rotate_refresh_token(&token).await.unwrap_err();
+        assert_eq!(
+            ret.to_string(),
+            "invalid refresh token: refresh token reuse detected"
+        );
+
+        // the whole family is revoked, including the latest token
+        let ret = state.rotate_refresh_token(&new_token).await.unwrap_err();
+        assert_eq!(
+            ret.to_string(),
+            "invalid refresh token: refresh token revoked"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn revoke_refresh_token_should_only_revoke_own_token() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let token = state.create_refresh_token(1, None).await?;
+
+        // another user can't revoke it
+        state.revoke_refresh_token(2, &token).await?;
+        let (_, token) = state.rotate_refresh_token(&token).await?;
+
+        state.revoke_refresh_token(1, &token).await?;
+        let ret = state.rotate_refresh_token(&token).await.unwrap_err();
+        assert_eq!(
+            ret.to_string(),
+            "invalid refresh token: refresh token revoked"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unknown_refresh_token_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let ret = state.rotate_refresh_token("bad-token").await.unwrap_err();
+        assert_eq!(
+            ret.to_string(),
+            "invalid refresh token: refresh token not found"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+use crate::{AppError, AppState};
+use chat_core::{User, middlewares::ClientInfo};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// A signed in device of the user
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct Session {
+    #[schema(value_type = String)]
+    pub id: Uuid,
+    pub user_agent: Option<String>,
+    /// last known ip
+    pub ip: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    /// whether this is the session of the token used for the request
+    pub current: bool,
+}
+
+impl AppState {
+    /// Start a session for a new signin, returns its id and first refresh token
+    pub async fn create_session(
+        &self,
+        user_id: i64,
+        client: &ClientInfo,
+    ) -> Result<(Uuid, String), AppError> {
+        let id = Uuid::now_v7();
+        sqlx::query(
+            "
+            INSERT INTO sessions (id, user_id, user_agent, ip)
+            VALUES ($1, $2, $3, $4)
+            ",
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(&client.user_agent)
+        .bind(client.ip.map(|ip| ip.to_string()))
+        .execute(&self.pool)
+        .await?;
+
+        let refresh_token = self.create_refresh_token(user_id, Some(id)).await?;
+        Ok((id, refresh_token))
+    }
+
+    /// List the sessions of the user which can still be refreshed, most recently seen first
+    pub async fn list_sessions(&self, user: &User) -> Result<Vec<Session>, AppError> {
+        let sessions = sqlx::query_as(
+            "
+            SELECT id, user_agent, ip, created_at, last_seen_at, COALESCE(id = $2, FALSE) AS current
+            FROM sessions s
+            WHERE user_id = $1 AND revoked_at IS NULL AND EXISTS (
+                SELECT 1 FROM refresh_tokens
+                WHERE family_id = s.id AND rotated_at IS NULL AND revoked_at IS NULL
+                    AND expires_at > NOW()
+            )
+            ORDER BY last_seen_at DESC
+            ",
+        )
+        .bind(user.id)
+        .bind(user.sid)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(sessions)
+    }
+
+    /// Log out a session of the user: its refresh tokens and access tokens stop working
+    pub async fn revoke_session(&self, user: &User, id: Uuid) -> Result<(), AppError> {
+        let ret = sqlx::query(
+            "
+            UPDATE sessions
+            SET revoked_at = NOW()
+            WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+            ",
+        )
+        .bind(id)
+        .bind(user.id)
+        .execute(&self.pool)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("session id: {}", id)));
+        }
+        self.revoke_refresh_token_family(id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AuthOutput;
+    use anyhow::Result;
+    use chat_core::middlewares::TokenVerify;
+
+    #[tokio::test]
+    async fn sessions_should_be_listed_and_revoked() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        let client = ClientInfo {
+            user_agent: Some("curl/8.0".to_string()),
+            ip: Some([10, 0, 0, 1].into()),
+        };
+        let (sid, refresh_token) = state.create_session(user.id, &client).await?;
+        let auth = AuthOutput::try_new(
+            &state,
+            User {
+                sid: Some(sid),
+                ..user.clone()
+            },
+            refresh_token,
+        )?;
+        let (other, _) = state
+            .create_session(user.id, &ClientInfo::default())
+            .await?;
+
+        let user = state.verify(&auth.token).await?;
+        assert_eq!(user.sid, Some(sid));
+        let sessions = state.list_sessions(&user).await?;
+        assert_eq!(sessions.len(), 2);
+        let current = sessions.iter().find(|s| s.current).unwrap();
+        assert_eq!(current.id, sid);
+        assert_eq!(current.user_agent.as_deref(), Some("curl/8.0"));
+        assert_eq!(current.ip.as_deref(), Some("10.0.0.1"));
+
+        // rotating keeps the session
+        let (refreshed, _) = state.rotate_refresh_token(&auth.refresh_token).await?;
+        assert_eq!(refreshed.sid, Some(sid));
+        assert_eq!(state.list_sessions(&user).await?.len(), 2);
+
+        state.revoke_session(&user, other).await?;
+        let sessions = state.list_sessions(&user).await?;
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].id, sid);
+
+        // access tokens of a revoked session are rejected
+        state.revoke_session(&user, sid).await?;
+        assert!(state.verify(&auth.token).await.is_err());
+
+        // sessions of other users can't be revoked
+        let (sid, _) = state.create_session(2, &ClientInfo::default()).await?;
+        let ret = state.revoke_session(&user, sid).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,249 @@
+use crate::{AppError, AppState, config::SigninLimitConfig};
+use chrono::{DateTime, Duration, Utc};
+use sqlx::FromRow;
+use std::net::IpAddr;
+use tracing::warn;
+
+const ACCOUNT_SCOPE: &str = "account";
+const IP_SCOPE: &str = "ip";
+
+#[derive(Debug, FromRow)]
+struct SigninFailures {
+    failures: i32,
+    last_failed_at: DateTime<Utc>,
+    locked_until: Option<DateTime<Utc>>,
+}
+
+impl AppState {
+    /// Fail with the seconds to wait when the account or the ip is locked or still throttled
+    pub async fn check_signin_allowed(
+        &self,
+        email: &str,
+        ip: Option<IpAddr>,
+    ) -> Result<(), AppError> {
+        let rows: Vec<SigninFailures> = sqlx::query_as(
+            "
+            SELECT failures, last_failed_at, locked_until
+            FROM signin_failures
+            WHERE (scope = $1 AND subject = $2) OR (scope = $3 AND subject = $4)
+            ",
+        )
+        .bind(ACCOUNT_SCOPE)
+        .bind(email.to_lowercase())
+        .bind(IP_SCOPE)
+        .bind(ip.map(|ip| ip.to_string()))
+        .fetch_all(&self.pool)
+        .await?;
+
+        let now = Utc::now();
+        let limit = &self.config.auth.signin_limit;
+        match rows.iter().filter_map(|row| row.retry_at(limit)).max() {
+            Some(retry_at) if retry_at > now => {
+                let secs = (retry_at - now).num_seconds().max(1);
+                Err(AppError::TooManySigninAttempts(secs as _))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Count a failed signin against the account and the ip, locking them past the threshold
+    pub async fn record_signin_failure(
+        &self,
+        email: &str,
+        ip: Option<IpAddr>,
+    ) -> Result<(), AppError> {
+        let limit = &self.config.auth.signin_limit;
+        let ip = ip.map(|ip| ip.to_string());
+        let account = email.to_lowercase();
+        self.count_signin_failure(ACCOUNT_SCOPE, &account, limit.max_failures, ip.as_deref())
+            .await?;
+        if let Some(ip) = ip.as_deref() {
+            self.count_signin_failure(IP_SCOPE, ip, limit.max_ip_failures, Some(ip))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Forget the failures of the account after a successful signin.
+    ///
+    /// The ip counter is kept, otherwise signing into an own account would reset it.
+    pub async fn reset_signin_failures(&self, email: &str) -> Result<(), AppError> {
+        sqlx::query("DELETE FROM signin_failures WHERE scope = $1 AND subject = $2")
+            .bind(ACCOUNT_SCOPE)
+            .bind(email.to_lowercase())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn count_signin_failure(
+        &self,
+        scope: &str,
+        subject: &str,
+        max_failures: u32,
+        ip: Option<&str>,
+    ) -> Result<(), AppError> {
+        let lockout_duration = self.config.auth.signin_limit.lockout_duration;
+        let mut tx = self.pool.begin().await?;
+        // failures older than a lockout are forgotten
+        let (failures,): (i32,) = sqlx::query_as(
+            "
+            INSERT INTO signin_failures (scope, subject, failures, last_failed_at)
+            VALUES ($1, $2, 1, NOW())
+            ON CONFLICT (scope, subject) DO UPDATE
+            SET failures = CASE
+                    WHEN signin_failures.last_failed_at < NOW() - make_interval(secs => $3) THEN 1
+                    ELSE signin_failures.failures + 1
+                END,
+                last_failed_at = NOW()
+            RETURNING failures
+            ",
+        )
+        .bind(scope)
+        .bind(subject)
+        .bind(lockout_duration as f64)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if failures as u32 >= max_failures {
+            let locked_until = Utc::now() + Duration::seconds(lockout_duration as _);
+            sqlx::query(
+                "
+                UPDATE signin_failures
+                SET failures = 0, locked_until = $3
+                WHERE scope = $1 AND subject = $2
+                ",
+            )
+            .bind(scope)
+            .bind(subject)
+            .bind(locked_until)
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query(
+                "
+                INSERT INTO signin_lockouts (scope, subject, ip, failures, locked_until)
+                VALUES ($1, $2, $3, $4, $5)
+                ",
+            )
+            .bind(scope)
+            .bind(subject)
+            .bind(ip)
+            .bind(failures)
+            .bind(locked_until)
+            .execute(&mut *tx)
+            .await?;
+            warn!(
+                "signin locked for {} {} until {} after {} failures",
+                scope, subject, locked_until, failures
+            );
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+impl SigninFailures {
+    /// When the next attempt is allowed, the wait doubles with each failure
+    fn retry_at(&self, limit: &SigninLimitConfig) -> Option<DateTime<Utc>> {
+        if let Some(locked_until) = self.locked_until.filter(|t| *t > Utc::now()) {
+            return Some(locked_until);
+        }
+        if self.failures <= 0 || limit.delay == 0 {
+            return None;
+        }
+        let factor = 2u64.saturating_pow(self.failures as u32 - 1);
+        let delay = limit.delay.saturating_mul(factor).min(limit.max_delay);
+        Some(self.last_failed_at + Duration::seconds(delay as _))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn signin_failures_should_lock_account() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test_with(|config| {
+            config.auth.signin_limit.delay = 0;
+            config.auth.signin_limit.max_failures = 3;
+        })
+        .await?;
+        let ip: IpAddr = "10.0.0.1".parse()?;
+
+        for _ in 0..2 {
+            state.check_signin_allowed("Test@123.com", Some(ip)).await?;
+            state
+                .record_signin_failure("test@123.com", Some(ip))
+                .await?;
+        }
+        // a success resets the account counter
+        state.reset_signin_failures("Test@123.com").await?;
+        for _ in 0..3 {
+            state.check_signin_allowed("Test@123.com", Some(ip)).await?;
+            state
+                .record_signin_failure("Test@123.com", Some(ip))
+                .await?;
+        }
+
+        let ret = state.check_signin_allowed("Test@123.com", None).await;
+        assert!(matches!(ret, Err(AppError::TooManySigninAttempts(secs)) if secs > 800));
+        // other accounts are still open
+        state
+            .check_signin_allowed("Alice@123.com", Some(ip))
+            .await?;
+
+        let (scope, subject, ip): (String, String, Option<String>) =
+            sqlx::query_as("SELECT scope, subject, ip FROM signin_lockouts")
+                .fetch_one(&state.pool)
+                .await?;
+        assert_eq!(scope, "account");
+        assert_eq!(subject, "test@123.com");
+        assert_eq!(ip.as_deref(), Some("10.0.0.1"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn signin_failures_should_lock_ip() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test_with(|config| {
+            config.auth.signin_limit.delay = 0;
+            config.auth.signin_limit.max_ip_failures = 3;
+        })
+        .await?;
+        let ip: IpAddr = "10.0.0.1".parse()?;
+
+        for email in ["Test@123.com", "Alice@123.com", "Bob@123.com"] {
+            state.record_signin_failure(email, Some(ip)).await?;
+        }
+        let ret = state
+            .check_signin_allowed("Charlie@123.com", Some(ip))
+            .await;
+        assert!(matches!(ret, Err(AppError::TooManySigninAttempts(_))));
+        state.check_signin_allowed("Charlie@123.com", None).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn signin_failures_should_delay_next_attempt() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test_with(|config| {
+            config.auth.signin_limit.delay = 10;
+            config.auth.signin_limit.max_delay = 15;
+        })
+        .await?;
+
+        state.record_signin_failure("Test@123.com", None).await?;
+        let ret = state.check_signin_allowed("Test@123.com", None).await;
+        assert!(matches!(ret, Err(AppError::TooManySigninAttempts(secs)) if secs <= 10));
+
+        // the wait doubles, up to max_delay
+        state.record_signin_failure("Test@123.com", None).await?;
+        let ret = state.check_signin_allowed("Test@123.com", None).await;
+        assert!(
+            matches!(ret, Err(AppError::TooManySigninAttempts(secs)) if secs > 10 && secs <= 15)
+        );
+
+        Ok(())
+    }
+}
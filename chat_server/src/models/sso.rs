@@ -0,0 +1,374 @@
+use crate::{
+    AppError, AppState,
+    models::{
+        generate_token, hash_token,
+        validation::{EMAIL_MAX_LEN, FULLNAME_MAX_LEN},
+    },
+    oidc::{self, IdToken},
+};
+use chat_core::{User, Workspace};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::{IntoParams, ToSchema};
+
+/// OpenID Connect provider of the workspace
+#[derive(Debug, Serialize, ToSchema, Deserialize, Clone)]
+pub struct UpdateWorkspaceSso {
+    /// issuer url, the provider metadata is discovered from it
+    pub issuer: String,
+    pub client_id: String,
+    /// only for confidential clients
+    #[serde(default)]
+    pub client_secret: Option<String>,
+}
+
+/// parameters the identity provider sends the browser back with
+#[derive(Debug, Serialize, Deserialize, IntoParams, Clone)]
+pub struct SsoCallback {
+    pub code: String,
+    pub state: String,
+}
+
+#[derive(Debug, FromRow)]
+struct WorkspaceSso {
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+}
+
+#[derive(Debug, FromRow)]
+struct SsoLogin {
+    ws_id: i64,
+    nonce: String,
+    code_verifier: String,
+}
+
+impl AppState {
+    /// Set the OpenID provider of the workspace, only the owner can do it
+    pub async fn update_workspace_sso(
+        &self,
+        user: &User,
+        input: &UpdateWorkspaceSso,
+    ) -> Result<(), AppError> {
+        let ws = self.find_owned_workspace(user).await?;
+        // fail early on a wrong issuer
+        oidc::discover(&self.http, &input.issuer).await?;
+
+        sqlx::query(
+            "
+            INSERT INTO workspace_sso (ws_id, issuer, client_id, client_secret)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (ws_id) DO UPDATE
+            SET issuer = EXCLUDED.issuer,
+                client_id = EXCLUDED.client_id,
+                client_secret = EXCLUDED.client_secret
+            ",
+        )
+        .bind(ws.id)
+        .bind(&input.issuer)
+        .bind(&input.client_id)
+        .bind(&input.client_secret)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Start an sso login into the workspace, returns the provider url to send the browser to
+    pub async fn start_sso_login(&self, workspace: &str) -> Result<String, AppError> {
+        let ws = self
+            .find_workspace_by_name(workspace)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("workspace: {}", workspace)))?;
+        let sso = self.find_workspace_sso(&ws).await?;
+        let metadata = oidc::discover(&self.http, &sso.issuer).await?;
+
+        let state = generate_token();
+        let nonce = generate_token();
+        let code_verifier = generate_token();
+        let expires_at = Utc::now() + Duration::seconds(self.config.auth.sso_login_duration as _);
+
+        sqlx::query(
+            "
+            INSERT INTO sso_logins (ws_id, state_hash, nonce, code_verifier, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            ",
+        )
+        .bind(ws.id)
+        .bind(hash_token(&state))
+        .bind(&nonce)
+        .bind(&code_verifier)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        oidc::authorization_url(
+            &metadata,
+            &sso.client_id,
+            &self.config.auth.sso_redirect_url,
+            &state,
+            &nonce,
+            &code_verifier,
+        )
+    }
+
+    /// Finish an sso login: exchange the code, validate the ID token and provision the user
+    pub async fn finish_sso_login(&self, input: &SsoCallback) -> Result<User, AppError> {
+        let login: Option<SsoLogin> = sqlx::query_as(
+            "
+            UPDATE sso_logins
+            SET used_at = NOW()
+            WHERE state_hash = $1 AND used_at IS NULL AND expires_at > NOW()
+            RETURNING ws_id, nonce, code_verifier
+            ",
+        )
+        .bind(hash_token(&input.state))
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(login) = login else {
+            return Err(AppError::SsoError("invalid or expired login".to_string()));
+        };
+
+        let ws = self
+            .find_workspace_by_id(login.ws_id as _)
+            .await?
+            .expect("workspace should exists");
+        let sso = self.find_workspace_sso(&ws).await?;
+        let metadata = oidc::discover(&self.http, &sso.issuer).await?;
+        let token = oidc::exchange_code(
+            &self.http,
+            &metadata,
+            &sso.client_id,
+            sso.client_secret.as_deref(),
+            &self.config.auth.sso_redirect_url,
+            &input.code,
+            &login.code_verifier,
+        )
+        .await?;
+        let id_token =
+            oidc::validate_id_token(&self.http, &metadata, &sso.client_id, &login.nonce, &token)
+                .await?;
+
+        self.provision_sso_user(&ws, &sso.issuer, &id_token).await
+    }
+
+    /// Find the member linked to the identity, link it by verified email, or create the user
+    async fn provision_sso_user(
+        &self,
+        ws: &Workspace,
+        issuer: &str,
+        id_token: &IdToken,
+    ) -> Result<User, AppError> {
+        let linked: Option<(i64,)> = sqlx::query_as(
+            "
+            SELECT user_id
+            FROM user_identities
+            WHERE issuer = $1 AND subject = $2
+            ",
+        )
+        .bind(issuer)
+        .bind(&id_token.subject)
+        .fetch_optional(&self.pool)
+        .await?;
+        if let Some((user_id,)) = linked {
+            // the identity may be linked to a user who left the workspace, or to another one
+            // sharing the same provider
+            return self.find_member(user_id, ws.id).await?.ok_or_else(|| {
+                AppError::SsoError(format!(
+                    "the identity isn't linked to a member of {}",
+                    ws.name
+                ))
+            });
+        }
+
+        // emails differing only by case are the same, as for signups
+        let Some(email) = id_token
+            .email
+            .as_deref()
+            .filter(|_| id_token.email_verified)
+            .map(|email| email.trim().to_lowercase())
+        else {
+            return Err(AppError::SsoError(
+                "the identity provider didn't return a verified email".to_string(),
+            ));
+        };
+        let email = email.as_str();
+        if email.chars().count() > EMAIL_MAX_LEN {
+            return Err(AppError::SsoError(format!(
+                "the email returned by the identity provider is longer than {} characters",
+                EMAIL_MAX_LEN
+            )));
+        }
+
+        let user: User = match self.find_user_by_email(email).await? {
+            Some(user) if self.find_member(user.id, ws.id).await?.is_some() => user,
+            Some(_) => {
+                return Err(AppError::SsoError(format!(
+                    "{} is already used in another workspace",
+                    email
+                )));
+            }
+            None => {
+                // providers don't bound the name, keep what fits
+                let fullname: String = id_token
+                    .name
+                    .as_deref()
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .unwrap_or(email)
+                    .chars()
+                    .take(FULLNAME_MAX_LEN)
+                    .collect();
+                sqlx::query_as(
+                    "
+                    INSERT INTO users (ws_id, fullname, email, email_verified_at)
+                    VALUES ($1, $2, $3, NOW())
+                    RETURNING id, ws_id, fullname, email, role, created_at
+                    ",
+                )
+                .bind(ws.id)
+                .bind(&fullname)
+                .bind(email)
+                .fetch_one(&self.pool)
+                .await?
+            }
+        };
+
+        sqlx::query(
+            "
+            INSERT INTO user_identities (user_id, issuer, subject)
+            VALUES ($1, $2, $3)
+            ",
+        )
+        .bind(user.id)
+        .bind(issuer)
+        .bind(&id_token.subject)
+        .execute(&self.pool)
+        .await?;
+
+        self.find_member(user.id, ws.id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("user id: {}", user.id)))
+    }
+
+    async fn find_workspace_sso(&self, ws: &Workspace) -> Result<WorkspaceSso, AppError> {
+        let sso: Option<WorkspaceSso> = sqlx::query_as(
+            "
+            SELECT issuer, client_id, client_secret
+            FROM workspace_sso
+            WHERE ws_id = $1
+            ",
+        )
+        .bind(ws.id)
+        .fetch_optional(&self.pool)
+        .await?;
+        sso.ok_or_else(|| AppError::NotFound(format!("sso for workspace: {}", ws.name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oidc::mock::{CLIENT_ID, MockIdp};
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn sso_login_should_provision_user() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let idp = MockIdp::start().await;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.unwrap();
+
+        let input = UpdateWorkspaceSso {
+            issuer: idp.issuer.clone(),
+            client_id: CLIENT_ID.to_string(),
+            client_secret: None,
+        };
+        let member = state.find_user_by_id(2).await?.unwrap();
+        let ret = state.update_workspace_sso(&member, &input).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        state.update_workspace_sso(&owner, &input).await?;
+
+        // new user, created without password
+        let url = state.start_sso_login("acme").await?;
+        let (code, sso_state) = idp.authorize(&url, "eve", "Eve@123.com", "Eve Test");
+        let input = SsoCallback {
+            code,
+            state: sso_state,
+        };
+        let user = state.finish_sso_login(&input).await?;
+        assert_eq!(user.email, "eve@123.com");
+        assert_eq!(user.fullname, "Eve Test");
+        assert_eq!(user.ws_id, 1);
+        assert_eq!(user.ws_name, "acme");
+        assert!(state.is_email_verified(user.id).await?);
+
+        // the state is single use
+        let ret = state.finish_sso_login(&input).await;
+        assert!(matches!(ret, Err(AppError::SsoError(_))));
+
+        // the same identity signs in as the same user
+        let url = state.start_sso_login("acme").await?;
+        let (code, sso_state) = idp.authorize(&url, "eve", "Eve@123.com", "Eve Test");
+        let input = SsoCallback {
+            code,
+            state: sso_state,
+        };
+        assert_eq!(state.finish_sso_login(&input).await?.id, user.id);
+
+        // existing users of the workspace are linked by email
+        let url = state.start_sso_login("acme").await?;
+        let (code, sso_state) = idp.authorize(&url, "alice", "Alice@123.com", "Alice");
+        let input = SsoCallback {
+            code,
+            state: sso_state,
+        };
+        assert_eq!(state.finish_sso_login(&input).await?.id, 2);
+
+        // long names from the provider are cut to fit
+        let url = state.start_sso_login("acme").await?;
+        let (code, sso_state) = idp.authorize(&url, "frank", "Frank@123.com", &"F".repeat(100));
+        let input = SsoCallback {
+            code,
+            state: sso_state,
+        };
+        let user = state.finish_sso_login(&input).await?;
+        assert_eq!(user.fullname, "F".repeat(FULLNAME_MAX_LEN));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sso_login_should_not_cross_workspaces() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let idp = MockIdp::start().await;
+        for ws_id in [1, 2] {
+            sqlx::query("INSERT INTO workspace_sso (ws_id, issuer, client_id) VALUES ($1, $2, $3)")
+                .bind(ws_id)
+                .bind(&idp.issuer)
+                .bind(CLIENT_ID)
+                .execute(&state.pool)
+                .await?;
+        }
+
+        let url = state.start_sso_login("acme").await?;
+        let (code, sso_state) = idp.authorize(&url, "eve", "Eve@123.com", "Eve Test");
+        let input = SsoCallback {
+            code,
+            state: sso_state,
+        };
+        state.finish_sso_login(&input).await?;
+
+        // the identity is linked to a member of acme only
+        let url = state.start_sso_login("foo").await?;
+        let (code, sso_state) = idp.authorize(&url, "eve", "Eve@123.com", "Eve Test");
+        let input = SsoCallback {
+            code,
+            state: sso_state,
+        };
+        let ret = state.finish_sso_login(&input).await;
+        assert!(matches!(ret, Err(AppError::SsoError(_))));
+
+        Ok(())
+    }
+}
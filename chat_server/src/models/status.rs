@@ -0,0 +1,354 @@
+use crate::{AppError, AppState, models::validation::Validator};
+use chat_core::{User, UserStatus, users_in_dnd};
+use chrono::{DateTime, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+const STATUS_EMOJI_MAX_LEN: usize = 32;
+const STATUS_TEXT_MAX_LEN: usize = 128;
+const DND_MAX_WINDOWS: usize = 28;
+
+/// Set the status of the current user, an empty status clears it
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct UpdateStatus {
+    #[serde(default)]
+    pub emoji: Option<String>,
+    #[serde(default)]
+    pub text: Option<String>,
+    /// clear the status then, it is kept until changed otherwise
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// A weekly do-not-disturb window, in the time zone of the user profile (UTC if unset)
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct DndWindow {
+    /// iso day of week the window starts on, 1 for monday
+    pub weekday: i16,
+    #[schema(value_type = String, example = "22:00:00")]
+    pub start_time: NaiveTime,
+    /// before `start_time` for windows ending the next day
+    #[schema(value_type = String, example = "08:00:00")]
+    pub end_time: NaiveTime,
+}
+
+/// Replace the do-not-disturb settings of the current user
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct UpdateDnd {
+    #[serde(default)]
+    pub windows: Vec<DndWindow>,
+    /// do-not-disturb until then, whatever the schedule
+    #[serde(default)]
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// Do-not-disturb settings, notifications are held back by notify_server while active
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct Dnd {
+    pub windows: Vec<DndWindow>,
+    pub until: Option<DateTime<Utc>>,
+    /// whether the user is in do-not-disturb right now
+    pub active: bool,
+}
+
+impl AppState {
+    /// The status of the user, expired ones are empty
+    pub async fn get_status(&self, user: &User) -> Result<UserStatus, AppError> {
+        let status: Option<UserStatus> = sqlx::query_as(
+            "
+            SELECT id AS user_id, status_emoji AS emoji, status_text AS text,
+                status_expires_at AS expires_at
+            FROM users
+            WHERE id = $1 AND (status_expires_at IS NULL OR status_expires_at > NOW())
+            ",
+        )
+        .bind(user.id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(status.unwrap_or(UserStatus {
+            user_id: user.id,
+            ..Default::default()
+        }))
+    }
+
+    /// Set the status of the user, the users it shares a chat with are notified
+    pub async fn update_status(
+        &self,
+        user: &User,
+        input: &UpdateStatus,
+    ) -> Result<UserStatus, AppError> {
+        let input = input.normalize();
+        input.validate()?;
+
+        sqlx::query(
+            "
+            UPDATE users
+            SET status_emoji = $2, status_text = $3, status_expires_at = $4
+            WHERE id = $1
+            ",
+        )
+        .bind(user.id)
+        .bind(&input.emoji)
+        .bind(&input.text)
+        .bind(input.expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        self.get_status(user).await
+    }
+
+    /// Statuses of the users sharing a chat with the user, empty ones are left out
+    pub async fn list_peer_statuses(&self, user: &User) -> Result<Vec<UserStatus>, AppError> {
+        let statuses = sqlx::query_as(
+            "
+            SELECT id AS user_id, status_emoji AS emoji, status_text AS text,
+                status_expires_at AS expires_at
+            FROM users
+            WHERE id <> $1 AND id IN (
+                SELECT DISTINCT unnest(members) FROM chats WHERE $1 = ANY(members)
+            )
+                AND (status_emoji IS NOT NULL OR status_text IS NOT NULL)
+                AND (status_expires_at IS NULL OR status_expires_at > NOW())
+            ORDER BY id
+            ",
+        )
+        .bind(user.id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(statuses)
+    }
+
+    pub async fn get_dnd(&self, user: &User) -> Result<Dnd, AppError> {
+        let windows = sqlx::query_as(
+            "
+            SELECT weekday, start_time, end_time
+            FROM dnd_windows
+            WHERE user_id = $1
+            ORDER BY weekday, start_time
+            ",
+        )
+        .bind(user.id)
+        .fetch_all(&self.pool)
+        .await?;
+        let (until,): (Option<DateTime<Utc>>,) =
+            sqlx::query_as("SELECT dnd_until FROM users WHERE id = $1")
+                .bind(user.id)
+                .fetch_one(&self.pool)
+                .await?;
+        let active = !users_in_dnd(&self.pool, &[user.id]).await?.is_empty();
+
+        Ok(Dnd {
+            windows,
+            until: until.filter(|until| *until > Utc::now()),
+            active,
+        })
+    }
+
+    /// Replace the do-not-disturb schedule of the user
+    pub async fn update_dnd(&self, user: &User, input: &UpdateDnd) -> Result<Dnd, AppError> {
+        input.validate()?;
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM dnd_windows WHERE user_id = $1")
+            .bind(user.id)
+            .execute(&mut *tx)
+            .await?;
+        for window in &input.windows {
+            sqlx::query(
+                "
+                INSERT INTO dnd_windows (user_id, weekday, start_time, end_time)
+                VALUES ($1, $2, $3, $4)
+                ",
+            )
+            .bind(user.id)
+            .bind(window.weekday)
+            .bind(window.start_time)
+            .bind(window.end_time)
+            .execute(&mut *tx)
+            .await?;
+        }
+        sqlx::query("UPDATE users SET dnd_until = $2 WHERE id = $1")
+            .bind(user.id)
+            .bind(input.until)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        self.get_dnd(user).await
+    }
+}
+
+impl UpdateStatus {
+    /// Trim the fields, empty ones are unset
+    fn normalize(&self) -> Self {
+        let trim = |v: &Option<String>| {
+            v.as_deref()
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(str::to_string)
+        };
+        let emoji = trim(&self.emoji);
+        let text = trim(&self.text);
+        // an empty status doesn't expire
+        let expires_at = self
+            .expires_at
+            .filter(|_| emoji.is_some() || text.is_some());
+        Self {
+            emoji,
+            text,
+            expires_at,
+        }
+    }
+
+    fn validate(&self) -> Result<(), AppError> {
+        let mut validator = Validator::default();
+        if let Some(emoji) = &self.emoji {
+            validator.length("emoji", emoji, 1, STATUS_EMOJI_MAX_LEN);
+        }
+        if let Some(text) = &self.text {
+            validator.length("text", text, 1, STATUS_TEXT_MAX_LEN);
+        }
+        validator
+            .check(
+                "expires_at",
+                self.expires_at.is_none_or(|v| v > Utc::now()),
+                "must be in the future",
+            )
+            .finish()
+    }
+}
+
+impl UpdateDnd {
+    fn validate(&self) -> Result<(), AppError> {
+        let mut validator = Validator::default();
+        validator.check(
+            "windows",
+            self.windows.len() <= DND_MAX_WINDOWS,
+            format!("must have at most {} windows", DND_MAX_WINDOWS),
+        );
+        for (i, window) in self.windows.iter().enumerate() {
+            validator
+                .check(
+                    &format!("windows[{}].weekday", i),
+                    (1..=7).contains(&window.weekday),
+                    "must be 1 (monday) to 7 (sunday)",
+                )
+                .check(
+                    &format!("windows[{}].end_time", i),
+                    window.start_time != window.end_time,
+                    "must differ from start_time",
+                );
+        }
+        validator.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::UpdateProfile;
+    use anyhow::Result;
+    use chrono::{Datelike, Duration, Timelike};
+
+    #[tokio::test]
+    async fn update_status_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+
+        let input = UpdateStatus {
+            emoji: Some("🌴".to_string()),
+            text: Some(" On vacation ".to_string()),
+            expires_at: Some(Utc::now() + Duration::days(1)),
+        };
+        let status = state.update_status(&user, &input).await?;
+        assert_eq!(status.user_id, 1);
+        assert_eq!(status.emoji.as_deref(), Some("🌴"));
+        assert_eq!(status.text.as_deref(), Some("On vacation"));
+
+        // users sharing a chat see it
+        let peer = state.find_user_by_id(2).await?.unwrap();
+        let statuses = state.list_peer_statuses(&peer).await?;
+        assert_eq!(statuses, vec![status]);
+
+        let input = UpdateStatus {
+            text: Some("Lunch".to_string()),
+            expires_at: Some(Utc::now() - Duration::minutes(1)),
+            ..Default::default()
+        };
+        let ret = state.update_status(&user, &input).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+
+        // expired statuses are empty
+        sqlx::query("UPDATE users SET status_expires_at = NOW() WHERE id = 1")
+            .execute(&state.pool)
+            .await?;
+        let status = state.get_status(&user).await?;
+        assert_eq!(status.text, None);
+        assert!(state.list_peer_statuses(&peer).await?.is_empty());
+
+        let status = state.update_status(&user, &UpdateStatus::default()).await?;
+        assert_eq!(status.emoji, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dnd_windows_should_follow_user_timezone() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        let input = UpdateProfile {
+            timezone: Some("Asia/Tokyo".to_string()),
+            ..Default::default()
+        };
+        state.update_profile(&user, &input).await?;
+
+        // an overnight window starting an hour ago in Tokyo
+        let local = Utc::now() + Duration::hours(9) - Duration::hours(1);
+        let start_time = NaiveTime::from_hms_opt(local.hour(), 0, 0).unwrap();
+        let input = UpdateDnd {
+            windows: vec![DndWindow {
+                weekday: local.weekday().number_from_monday() as i16,
+                start_time,
+                end_time: start_time - Duration::minutes(1),
+            }],
+            until: None,
+        };
+        let dnd = state.update_dnd(&user, &input).await?;
+        assert_eq!(dnd.windows, input.windows);
+        assert!(dnd.active);
+
+        // the same window in UTC is over
+        let input = UpdateProfile {
+            timezone: Some("".to_string()),
+            ..Default::default()
+        };
+        state.update_profile(&user, &input).await?;
+        let dnd = state.get_dnd(&user).await?;
+        assert!(!dnd.active);
+
+        let input = UpdateDnd {
+            windows: vec![],
+            until: Some(Utc::now() + Duration::hours(1)),
+        };
+        let dnd = state.update_dnd(&user, &input).await?;
+        assert!(dnd.windows.is_empty());
+        assert!(dnd.active);
+
+        let input = UpdateDnd {
+            windows: vec![DndWindow {
+                weekday: 8,
+                start_time,
+                end_time: start_time,
+            }],
+            until: None,
+        };
+        let ret = state.update_dnd(&user, &input).await;
+        let Err(AppError::InvalidInput(fields)) = ret else {
+            panic!("expected invalid input");
+        };
+        assert_eq!(fields.len(), 2);
+
+        Ok(())
+    }
+}
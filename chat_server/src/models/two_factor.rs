@@ -0,0 +1,618 @@
+use crate::{
+    AppError, AppState,
+    models::{generate_token, hash_token},
+};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chat_core::User;
+use chrono::{Duration, Utc};
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+const TOTP_ISSUER: &str = "Chat";
+const TOTP_DIGITS: u32 = 6;
+const TOTP_PERIOD: i64 = 30;
+// accept codes from the previous and next time step, to allow for clock drift
+const TOTP_SKEW: i64 = 1;
+const RECOVERY_CODES: usize = 10;
+// a challenge is burnt after that many wrong codes
+const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+
+/// secret of a new TOTP enrollment, to be added to an authenticator app
+#[derive(Debug, Serialize, ToSchema, Deserialize, Clone)]
+pub struct TotpSetup {
+    /// base32 encoded secret
+    pub secret: String,
+    /// `otpauth://` URI, usually shown as a QR code
+    pub otpauth_uri: String,
+}
+
+/// a code from the authenticator app, or a recovery code
+#[derive(Debug, Serialize, ToSchema, Deserialize, Clone)]
+pub struct TwoFactorCode {
+    pub code: String,
+}
+
+/// one-time codes to sign in when the authenticator app is lost
+#[derive(Debug, Serialize, ToSchema, Deserialize, Clone)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+/// returned by signin instead of tokens when the user has 2fa enabled
+#[derive(Debug, Serialize, ToSchema, Deserialize, Clone)]
+pub struct SigninChallenge {
+    /// short-lived challenge, to be sent with a code to `/api/signin/2fa`
+    pub challenge: String,
+}
+
+/// second signin step
+#[derive(Debug, Serialize, ToSchema, Deserialize, Clone)]
+pub struct VerifySigninChallenge {
+    pub challenge: String,
+    pub code: String,
+}
+
+/// require 2fa from all members of the workspace, or stop requiring it
+#[derive(Debug, Serialize, ToSchema, Deserialize, Clone)]
+pub struct UpdateTwoFactorPolicy {
+    pub required: bool,
+}
+
+#[derive(Debug, FromRow)]
+struct TotpRecord {
+    secret: String,
+    last_used_step: i64,
+}
+
+impl AppState {
+    /// Start a TOTP enrollment, replacing a previous one which wasn't enabled
+    pub async fn setup_totp(&self, user: &User) -> Result<TotpSetup, AppError> {
+        if self.is_totp_enabled(user.id).await? {
+            return Err(AppError::TwoFactorError("2fa already enabled".to_string()));
+        }
+
+        let mut buf = [0u8; 20];
+        OsRng.fill_bytes(&mut buf);
+        let secret = BASE32_NOPAD.encode(&buf);
+
+        sqlx::query(
+            "
+            INSERT INTO user_totp (user_id, secret)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id) DO UPDATE
+            SET secret = EXCLUDED.secret, last_used_step = 0, created_at = NOW()
+            ",
+        )
+        .bind(user.id)
+        .bind(&secret)
+        .execute(&self.pool)
+        .await?;
+
+        let otpauth_uri = format!(
+            "otpauth://totp/{issuer}:{}?secret={}&issuer={issuer}&algorithm=SHA1&digits={}&period={}",
+            user.email,
+            secret,
+            TOTP_DIGITS,
+            TOTP_PERIOD,
+            issuer = TOTP_ISSUER,
+        );
+        Ok(TotpSetup {
+            secret,
+            otpauth_uri,
+        })
+    }
+
+    /// Enable 2fa once the user proved the authenticator app works, returns fresh recovery codes
+    pub async fn enable_totp(&self, user_id: i64, code: &str) -> Result<Vec<String>, AppError> {
+        let Some(record) = self.find_totp(user_id, false).await? else {
+            return Err(AppError::TwoFactorError("2fa not set up".to_string()));
+        };
+        if !self.accept_totp_code(user_id, &record, code).await? {
+            return Err(AppError::InvalidTwoFactorCode);
+        }
+
+        sqlx::query("UPDATE user_totp SET enabled_at = NOW() WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        self.create_recovery_codes(user_id).await
+    }
+
+    /// Turn 2fa off, with a valid code, unless the workspace requires it
+    pub async fn disable_totp(&self, user: &User, code: &str) -> Result<(), AppError> {
+        if self.is_two_factor_required(user.ws_id).await? {
+            return Err(AppError::TwoFactorError(
+                "2fa is required by the workspace".to_string(),
+            ));
+        }
+        if !self.verify_second_factor(user.id, code).await? {
+            return Err(AppError::InvalidTwoFactorCode);
+        }
+
+        sqlx::query("DELETE FROM user_totp WHERE user_id = $1")
+            .bind(user.id)
+            .execute(&self.pool)
+            .await?;
+        sqlx::query("DELETE FROM totp_recovery_codes WHERE user_id = $1")
+            .bind(user.id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Replace the recovery codes of the user, the old ones stop working
+    pub async fn create_recovery_codes(&self, user_id: i64) -> Result<Vec<String>, AppError> {
+        let codes: Vec<String> = (0..RECOVERY_CODES)
+            .map(|_| {
+                let code = &generate_token()[..10];
+                format!("{}-{}", &code[..5], &code[5..])
+            })
+            .collect();
+        let hashes: Vec<String> = codes.iter().map(|code| hash_token(code)).collect();
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM totp_recovery_codes WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "
+            INSERT INTO totp_recovery_codes (user_id, code_hash)
+            SELECT $1, UNNEST($2::CHAR(64)[])
+            ",
+        )
+        .bind(user_id)
+        .bind(&hashes)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(codes)
+    }
+
+    pub async fn is_totp_enabled(&self, user_id: i64) -> Result<bool, AppError> {
+        Ok(self.find_totp(user_id, true).await?.is_some())
+    }
+
+    /// Check a TOTP code or consume a recovery code
+    pub async fn verify_second_factor(&self, user_id: i64, code: &str) -> Result<bool, AppError> {
+        let Some(record) = self.find_totp(user_id, true).await? else {
+            return Ok(false);
+        };
+        if self.accept_totp_code(user_id, &record, code).await? {
+            return Ok(true);
+        }
+
+        let code = code.trim().to_lowercase();
+        let ret = sqlx::query(
+            "
+            UPDATE totp_recovery_codes
+            SET used_at = NOW()
+            WHERE id = (
+                SELECT id FROM totp_recovery_codes
+                WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+                LIMIT 1
+            )
+            ",
+        )
+        .bind(user_id)
+        .bind(hash_token(&code))
+        .execute(&self.pool)
+        .await?;
+        Ok(ret.rows_affected() > 0)
+    }
+
+    /// Hand out a challenge for the second signin step
+    pub async fn create_signin_challenge(&self, user: &User) -> Result<String, AppError> {
+        let challenge = generate_token();
+        let expires_at =
+            Utc::now() + Duration::seconds(self.config.auth.signin_challenge_duration as _);
+
+        sqlx::query(
+            "
+            INSERT INTO signin_challenges (user_id, ws_id, token_hash, expires_at)
+            VALUES ($1, $2, $3, $4)
+            ",
+        )
+        .bind(user.id)
+        .bind(user.ws_id)
+        .bind(hash_token(&challenge))
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(challenge)
+    }
+
+    /// Email of the user an open challenge was handed to, to throttle its codes like passwords
+    pub async fn find_signin_challenge_email(
+        &self,
+        challenge: &str,
+    ) -> Result<Option<String>, AppError> {
+        let email: Option<(String,)> = sqlx::query_as(
+            "
+            SELECT u.email
+            FROM signin_challenges c JOIN users u ON u.id = c.user_id
+            WHERE c.token_hash = $1 AND c.used_at IS NULL AND c.expires_at > NOW()
+            ",
+        )
+        .bind(hash_token(challenge))
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(email.map(|(email,)| email))
+    }
+
+    /// Complete a signin with the challenge and a second factor
+    pub async fn verify_signin_challenge(
+        &self,
+        input: &VerifySigninChallenge,
+    ) -> Result<User, AppError> {
+        // every try counts, so codes can't be guessed with a single challenge
+        let challenge: Option<(i64, i64, i64)> = sqlx::query_as(
+            "
+            UPDATE signin_challenges
+            SET attempts = attempts + 1
+            WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW() AND attempts < $2
+            RETURNING id, user_id, ws_id
+            ",
+        )
+        .bind(hash_token(&input.challenge))
+        .bind(MAX_CHALLENGE_ATTEMPTS)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let Some((id, user_id, ws_id)) = challenge else {
+            return Err(AppError::InvalidSigninChallenge);
+        };
+        if !self.verify_second_factor(user_id, &input.code).await? {
+            return Err(AppError::InvalidTwoFactorCode);
+        }
+
+        let ret = sqlx::query(
+            "UPDATE signin_challenges SET used_at = NOW() WHERE id = $1 AND used_at IS NULL",
+        )
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::InvalidSigninChallenge);
+        }
+
+        let mut user = self
+            .find_user_by_id(user_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("user id: {}", user_id)))?;
+        // sso logins sign into the workspace of the identity provider, not the one of the user
+        if user.ws_id != ws_id {
+            return self
+                .find_member(user_id, ws_id)
+                .await?
+                .ok_or_else(|| AppError::NotFound(format!("workspace id: {}", ws_id)));
+        }
+        let ws = self.find_user_workspace(&user).await?;
+        user.ws_name = ws.name;
+        Ok(user)
+    }
+
+    pub async fn is_two_factor_required(&self, ws_id: i64) -> Result<bool, AppError> {
+        let required: Option<(bool,)> =
+            sqlx::query_as("SELECT require_two_factor FROM workspaces WHERE id = $1")
+                .bind(ws_id)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(required.is_some_and(|(v,)| v))
+    }
+
+    /// The workspace of the user requires 2fa, but the user hasn't enabled it yet
+    pub async fn is_two_factor_missing(&self, user: &User) -> Result<bool, AppError> {
+        // bots act with API keys, they can't enroll
+        if user.scopes.is_some() {
+            return Ok(false);
+        }
+        Ok(
+            self.is_two_factor_required(user.ws_id).await?
+                && !self.is_totp_enabled(user.id).await?,
+        )
+    }
+
+    /// Only the workspace owner can change the 2fa policy, and needs 2fa to require it
+    pub async fn update_two_factor_policy(
+        &self,
+        user: &User,
+        input: &UpdateTwoFactorPolicy,
+    ) -> Result<(), AppError> {
+        let ws = self.find_owned_workspace(user).await?;
+        if input.required && !self.is_totp_enabled(user.id).await? {
+            return Err(AppError::TwoFactorError(
+                "enable 2fa before requiring it from members".to_string(),
+            ));
+        }
+
+        sqlx::query("UPDATE workspaces SET require_two_factor = $1 WHERE id = $2")
+            .bind(input.required)
+            .bind(ws.id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn find_totp(&self, user_id: i64, enabled: bool) -> Result<Option<TotpRecord>, AppError> {
+        let record = sqlx::query_as(
+            "
+            SELECT secret, last_used_step
+            FROM user_totp
+            WHERE user_id = $1 AND (enabled_at IS NOT NULL) = $2
+            ",
+        )
+        .bind(user_id)
+        .bind(enabled)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(record)
+    }
+
+    // a code is accepted once, so a code seen by someone else can't be replayed
+    async fn accept_totp_code(
+        &self,
+        user_id: i64,
+        record: &TotpRecord,
+        code: &str,
+    ) -> Result<bool, AppError> {
+        let Some(step) = match_totp_code(&record.secret, code, Utc::now().timestamp())? else {
+            return Ok(false);
+        };
+        if step <= record.last_used_step {
+            return Ok(false);
+        }
+
+        let ret = sqlx::query(
+            "
+            UPDATE user_totp
+            SET last_used_step = $1
+            WHERE user_id = $2 AND last_used_step < $1
+            ",
+        )
+        .bind(step)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(ret.rows_affected() > 0)
+    }
+}
+
+/// Find the time step within the allowed skew for which `code` is valid
+fn match_totp_code(secret: &str, code: &str, now: i64) -> Result<Option<i64>, AppError> {
+    let code = code.trim();
+    if code.len() != TOTP_DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return Ok(None);
+    }
+    let key = BASE32_NOPAD
+        .decode(secret.as_bytes())
+        .map_err(|e| AppError::TwoFactorError(format!("invalid secret: {}", e)))?;
+
+    let current = now / TOTP_PERIOD;
+    let step = (current - TOTP_SKEW..=current + TOTP_SKEW)
+        .find(|step| totp_code(&key, *step as u64) == code);
+    Ok(step)
+}
+
+// RFC 4226 HOTP on the RFC 6238 time step
+fn totp_code(key: &[u8], step: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("hmac accepts any key length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    format!(
+        "{:0width$}",
+        value % 10u32.pow(TOTP_DIGITS),
+        width = TOTP_DIGITS as usize
+    )
+}
+
+#[cfg(test)]
+impl AppState {
+    /// The code an authenticator app would show right now
+    pub async fn current_totp_code(&self, user_id: i64) -> Result<String, AppError> {
+        let (secret,): (String,) =
+            sqlx::query_as("SELECT secret FROM user_totp WHERE user_id = $1")
+                .bind(user_id)
+                .fetch_one(&self.pool)
+                .await?;
+        let key = BASE32_NOPAD.decode(secret.as_bytes()).unwrap();
+        Ok(totp_code(
+            &key,
+            (Utc::now().timestamp() / TOTP_PERIOD) as u64,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::UpdateMemberRole;
+    use anyhow::Result;
+    use chat_core::WorkspaceRole;
+
+    #[test]
+    fn totp_code_should_match_rfc6238() {
+        // test vectors from RFC 6238 appendix B, truncated to 6 digits
+        let key = b"12345678901234567890";
+        let vectors = [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+        ];
+        for (time, code) in vectors {
+            assert_eq!(totp_code(key, (time / TOTP_PERIOD) as u64), code);
+        }
+
+        let secret = BASE32_NOPAD.encode(key);
+        let ret = match_totp_code(&secret, "287082", 59 + TOTP_PERIOD).unwrap();
+        assert_eq!(ret, Some(1));
+        let ret = match_totp_code(&secret, "287082", 59 + 3 * TOTP_PERIOD).unwrap();
+        assert_eq!(ret, None);
+    }
+
+    #[tokio::test]
+    async fn totp_enrollment_and_signin_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+
+        let setup = state.setup_totp(&user).await?;
+        assert!(
+            setup
+                .otpauth_uri
+                .starts_with("otpauth://totp/Chat:Test@123.com?secret=")
+        );
+        assert!(!state.is_totp_enabled(user.id).await?);
+
+        let ret = state.enable_totp(user.id, "000000").await;
+        assert!(ret.is_err());
+        let code = state.current_totp_code(user.id).await?;
+        let recovery_codes = state.enable_totp(user.id, &code).await?;
+        assert_eq!(recovery_codes.len(), RECOVERY_CODES);
+        assert!(state.is_totp_enabled(user.id).await?);
+
+        // the code used for enrollment can't be replayed
+        let challenge = state.create_signin_challenge(&user).await?;
+        let input = VerifySigninChallenge {
+            challenge: challenge.clone(),
+            code,
+        };
+        let ret = state.verify_signin_challenge(&input).await.unwrap_err();
+        assert_eq!(ret.to_string(), "invalid two-factor code");
+
+        // recovery codes work once
+        let input = VerifySigninChallenge {
+            challenge,
+            code: recovery_codes[0].clone(),
+        };
+        let signed_in = state.verify_signin_challenge(&input).await?;
+        assert_eq!(signed_in.id, user.id);
+        assert_eq!(signed_in.ws_name, "acme");
+
+        let ret = state.verify_signin_challenge(&input).await.unwrap_err();
+        assert_eq!(ret.to_string(), "invalid signin challenge");
+        let challenge = state.create_signin_challenge(&user).await?;
+        let input = VerifySigninChallenge {
+            challenge,
+            code: recovery_codes[0].clone(),
+        };
+        let ret = state.verify_signin_challenge(&input).await.unwrap_err();
+        assert_eq!(ret.to_string(), "invalid two-factor code");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn signin_challenge_should_keep_its_workspace() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        sqlx::query("INSERT INTO workspace_members (ws_id, user_id) VALUES (2, 1)")
+            .execute(&state.pool)
+            .await?;
+        let user = state.find_member(1, 2).await?.unwrap();
+        state.setup_totp(&user).await?;
+        let code = state.current_totp_code(user.id).await?;
+        let recovery_codes = state.enable_totp(user.id, &code).await?;
+
+        let challenge = state.create_signin_challenge(&user).await?;
+        let input = VerifySigninChallenge {
+            challenge,
+            code: recovery_codes[0].clone(),
+        };
+        let signed_in = state.verify_signin_challenge(&input).await?;
+        assert_eq!(signed_in.ws_id, 2);
+        assert_eq!(signed_in.ws_name, "foo");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn signin_challenge_should_be_burnt_after_too_many_attempts() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        state.setup_totp(&user).await?;
+        let code = state.current_totp_code(user.id).await?;
+        let recovery_codes = state.enable_totp(user.id, &code).await?;
+
+        let challenge = state.create_signin_challenge(&user).await?;
+        for _ in 0..MAX_CHALLENGE_ATTEMPTS {
+            let input = VerifySigninChallenge {
+                challenge: challenge.clone(),
+                code: "bad-code".to_string(),
+            };
+            assert!(state.verify_signin_challenge(&input).await.is_err());
+        }
+
+        let input = VerifySigninChallenge {
+            challenge,
+            code: recovery_codes[0].clone(),
+        };
+        let ret = state.verify_signin_challenge(&input).await.unwrap_err();
+        assert_eq!(ret.to_string(), "invalid signin challenge");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn workspace_two_factor_policy_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.unwrap();
+        let member = state.find_user_by_id(2).await?.unwrap();
+        let input = UpdateTwoFactorPolicy { required: true };
+
+        let ret = state.update_two_factor_policy(&member, &input).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        // nor can admins
+        let role = UpdateMemberRole {
+            role: WorkspaceRole::Admin,
+        };
+        state.update_member_role(&owner, 3, &role).await?;
+        let admin = state.find_user_by_id(3).await?.unwrap();
+        assert_eq!(admin.role, WorkspaceRole::Admin);
+        let ret = state.update_two_factor_policy(&admin, &input).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        // the owner must have 2fa first
+        let ret = state.update_two_factor_policy(&owner, &input).await;
+        assert!(matches!(ret, Err(AppError::TwoFactorError(_))));
+
+        state.setup_totp(&owner).await?;
+        let code = state.current_totp_code(owner.id).await?;
+        state.enable_totp(owner.id, &code).await?;
+        state.update_two_factor_policy(&owner, &input).await?;
+
+        assert!(!state.is_two_factor_missing(&owner).await?);
+        assert!(state.is_two_factor_missing(&member).await?);
+
+        // members can't turn it off anymore, even with a valid code
+        state.setup_totp(&member).await?;
+        let code = state.current_totp_code(member.id).await?;
+        let recovery_codes = state.enable_totp(member.id, &code).await?;
+        assert!(!state.is_two_factor_missing(&member).await?);
+        let ret = state.disable_totp(&member, &recovery_codes[0]).await;
+        assert!(matches!(ret, Err(AppError::TwoFactorError(_))));
+
+        // the same code works once the workspace stops requiring 2fa
+        let input = UpdateTwoFactorPolicy { required: false };
+        state.update_two_factor_policy(&owner, &input).await?;
+        state.disable_totp(&member, &recovery_codes[0]).await?;
+        assert!(!state.is_totp_enabled(member.id).await?);
+
+        Ok(())
+    }
+}
@@ -1,12 +1,22 @@
-use crate::{AppError, AppState};
+use crate::{
+    AppError, AppState,
+    config::{Argon2Config, PasswordPolicy},
+    error::FieldError,
+    models::validation::{FULLNAME_MAX_LEN, Validator, WORKSPACE_MAX_LEN},
+};
 use argon2::{
-    Argon2,
+    Algorithm, Argon2, Params,
     password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString, rand_core::OsRng},
 };
-use chat_core::{ChatUser, User};
+use chat_core::{ChatUser, User, WorkspaceRole};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::mem;
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
+
+/// users listed when the page size isn't given, and the most a page can have
+const DEFAULT_PAGE_SIZE: u64 = 50;
+const MAX_PAGE_SIZE: u64 = 100;
 
 /// create a user with email and password
 #[derive(Debug, Serialize, ToSchema, Deserialize, Clone)]
@@ -19,6 +29,38 @@ pub struct CreateUser {
     pub workspace: String,
     /// Password of the user
     pub password: String,
+    /// Invite code, needed to join an existing workspace unless the email domain is allowed
+    #[serde(default)]
+    pub invite: Option<String>,
+}
+
+/// change the password of the current user
+#[derive(Debug, Serialize, ToSchema, Deserialize, Clone)]
+pub struct ChangePassword {
+    pub old_password: String,
+    pub new_password: String,
+}
+
+/// A page of the users of the workspace, ordered by id
+#[derive(Debug, Clone, Default, IntoParams, ToSchema, Serialize, Deserialize)]
+pub struct ListUsers {
+    /// id of the last user of the previous page
+    #[serde(default)]
+    pub last_id: Option<u64>,
+    /// up to 100 users, 50 if 0
+    #[serde(default)]
+    pub limit: u64,
+    /// prefix or approximate name or email
+    #[serde(default)]
+    pub q: Option<String>,
+    /// only the active users, or only the deactivated ones
+    #[serde(default)]
+    pub active: Option<bool>,
+    #[serde(default)]
+    pub role: Option<WorkspaceRole>,
+    /// only the users active since then
+    #[serde(default)]
+    pub active_since: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Serialize, ToSchema, Deserialize, Clone)]
@@ -32,9 +74,9 @@ impl AppState {
     pub async fn find_user_by_email(&self, email: &str) -> Result<Option<User>, AppError> {
         let ret = sqlx::query_as(
             "
-            SELECT id, ws_id, fullname, email, created_at
+            SELECT id, ws_id, fullname, email, role, created_at
             FROM users
-            WHERE email = $1
+            WHERE LOWER(email) = LOWER($1)
             ",
         )
         .bind(email)
@@ -48,7 +90,7 @@ impl AppState {
     pub async fn find_user_by_id(&self, id: i64) -> Result<Option<User>, AppError> {
         let user = sqlx::query_as(
             "
-            SELECT id, ws_id, fullname, email, created_at
+            SELECT id, ws_id, fullname, email, role, created_at
             FROM users
             WHERE id = $1
             ",
@@ -62,35 +104,67 @@ impl AppState {
 
     /// create a new user
     pub async fn create_user(&self, input: &CreateUser) -> Result<User, AppError> {
+        let input = &input.normalize();
+        input.validate(&self.config.auth.password_policy)?;
         // check if email exists
         if self.find_user_by_email(&input.email).await?.is_some() {
             return Err(AppError::EmailAleardyExists(input.email.clone()));
         }
+        let password_hash = hash_password(&input.password, &self.config.auth.argon2)?;
+
+        // the invite is only used up once the user is created
+        let mut tx = self.pool.begin().await?;
         // check if workspace exists, if not create one
-        let ws = match self.find_workspace_by_name(&input.workspace).await? {
-            Some(ws) => ws,
-            None => self.create_workspace(&input.workspace, 0).await?,
+        let (ws, pending_ws_id, role) = match self.find_workspace_by_name(&input.workspace).await? {
+            Some(ws) => {
+                let (role, by_domain) = match &input.invite {
+                    Some(code) => (
+                        self.redeem_invite(&mut tx, &ws, code, &input.email).await?,
+                        false,
+                    ),
+                    None if self.is_auto_join_email(&ws, &input.email).await? => {
+                        (WorkspaceRole::Member, true)
+                    }
+                    None => return Err(AppError::InviteRequired(ws.name)),
+                };
+                if self.config.auth.require_verified_email || by_domain {
+                    // the user waits in workspace 0 until the email is verified
+                    let none = self
+                        .find_workspace_by_id(0)
+                        .await?
+                        .expect("workspace 0 should exists");
+                    (none, Some(ws.id), role)
+                } else {
+                    (ws, None, role)
+                }
+            }
+            None => (
+                self.create_workspace(&input.workspace, 0).await?,
+                None,
+                WorkspaceRole::Member,
+            ),
         };
 
-        let password_hash = hash_password(&input.password)?;
-
         let mut user: User = sqlx::query_as(
             "
-            INSERT INTO users (ws_id, fullname, email, password_hash)
-            VALUES ($1, $2, $3, $4)
-            RETURNING id, ws_id, fullname, email, created_at
+            INSERT INTO users (ws_id, pending_ws_id, fullname, email, password_hash, role)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, ws_id, fullname, email, role, created_at
             ",
         )
         .bind(ws.id)
+        .bind(pending_ws_id)
         .bind(&input.fullname)
         .bind(&input.email)
         .bind(password_hash)
-        .fetch_one(&self.pool)
+        .bind(role)
+        .fetch_one(&mut *tx)
         .await?;
+        tx.commit().await?;
 
         user.ws_name = ws.name.clone();
 
-        if ws.owner_id == 0 {
+        if ws.owner_id == 0 && pending_ws_id.is_none() {
             self.update_workspace_owner(user.id as _, ws.id as _)
                 .await?;
         }
@@ -102,9 +176,9 @@ impl AppState {
     pub async fn verify_user(&self, input: &SigninUser) -> Result<Option<User>, AppError> {
         let user: Option<User> = sqlx::query_as(
             "
-            SELECT id, ws_id, fullname, email, password_hash, created_at
+            SELECT id, ws_id, fullname, email, role, password_hash, created_at
             FROM users
-            WHERE email = $1
+            WHERE LOWER(email) = LOWER($1)
             ",
         )
         .bind(&input.email)
@@ -113,14 +187,21 @@ impl AppState {
 
         match user {
             Some(mut user) => {
-                let password_hash = mem::take(&mut user.password_hash);
-                let is_valid =
-                    verify_password(&input.password, &password_hash.unwrap_or_default())?;
+                // users signing in with sso only have no password
+                let Some(password_hash) = mem::take(&mut user.password_hash) else {
+                    return Ok(None);
+                };
+                let is_valid = verify_password(&input.password, &password_hash)?;
                 if is_valid {
-                    let ws = self
-                        .find_workspace_by_id(user.ws_id as _)
-                        .await?
-                        .expect("workspace should exists");
+                    // upgrade hashes made with an older cost while the password is at hand
+                    if needs_rehash(&password_hash, &self.config.auth.argon2)? {
+                        self.update_password(user.id, &input.password).await?;
+                    }
+                    // other workspaces take over when deactivated, this is the last one
+                    if self.is_member_deactivated(user.id, user.ws_id).await? {
+                        return Err(AppError::UserDeactivated);
+                    }
+                    let ws = self.find_user_workspace(&user).await?;
                     user.ws_name = ws.name;
                     Ok(Some(user))
                 } else {
@@ -131,10 +212,48 @@ impl AppState {
         }
     }
 
+    /// Store a new password for the user
+    pub async fn update_password(&self, user_id: i64, password: &str) -> Result<(), AppError> {
+        let password_hash = hash_password(password, &self.config.auth.argon2)?;
+        sqlx::query(
+            "
+            UPDATE users
+            SET password_hash = $1
+            WHERE id = $2
+            ",
+        )
+        .bind(password_hash)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Change the password after checking the old one.
+    ///
+    /// Wrong old passwords count as failed signins, so a stolen token can't be used to guess it.
+    pub async fn change_password(
+        &self,
+        user: &User,
+        input: &ChangePassword,
+    ) -> Result<(), AppError> {
+        self.check_signin_allowed(&user.email, None).await?;
+        let input_signin = SigninUser {
+            email: user.email.clone(),
+            password: input.old_password.clone(),
+        };
+        if self.verify_user(&input_signin).await?.is_none() {
+            self.record_signin_failure(&user.email, None).await?;
+            return Err(AppError::WrongPassword);
+        }
+        self.validate_password("new_password", &input.new_password)?;
+        self.update_password(user.id, &input.new_password).await
+    }
+
     pub async fn fetch_chat_user_by_ids(&self, ids: &[i64]) -> Result<Vec<ChatUser>, AppError> {
         let users = sqlx::query_as(
             "
-            SELECT id, fullname, email
+            SELECT id, fullname, email, avatar_url
             FROM users
             WHERE id = ANY($1)
             ",
@@ -146,24 +265,105 @@ impl AppState {
     }
 
     pub async fn fetch_all_chat_users(&self, ws_id: u64) -> Result<Vec<ChatUser>, AppError> {
+        self.query_chat_users(ws_id, &ListUsers::default(), i64::MAX)
+            .await
+    }
+
+    /// Search the users of the workspace, deactivated ones included unless filtered out.
+    ///
+    /// `q` matches the start of the name or email, or words close to it in either of them.
+    pub async fn list_chat_users(
+        &self,
+        ws_id: u64,
+        input: &ListUsers,
+    ) -> Result<Vec<ChatUser>, AppError> {
+        let limit = match input.limit {
+            0 => DEFAULT_PAGE_SIZE,
+            _ => input.limit.min(MAX_PAGE_SIZE),
+        };
+        self.query_chat_users(ws_id, input, limit as _).await
+    }
+
+    async fn query_chat_users(
+        &self,
+        ws_id: u64,
+        input: &ListUsers,
+        limit: i64,
+    ) -> Result<Vec<ChatUser>, AppError> {
+        let last_id = i64::try_from(input.last_id.unwrap_or(0)).map_err(|_| {
+            AppError::InvalidInput(vec![FieldError::new("last_id", "must be the id of a user")])
+        })?;
+        let q = input.q.as_deref().map(str::trim).filter(|q| !q.is_empty());
+        let prefix = q.map(|q| format!("{}%", escape_like(q)));
+
         let users = sqlx::query_as(
             "
-            SELECT id, fullname, email
-            FROM users
-            WHERE ws_id = $1
+            SELECT u.id, u.fullname, u.email, u.avatar_url,
+                m.deactivated_at IS NOT NULL AS deactivated
+            FROM workspace_members m
+            JOIN users u ON u.id = m.user_id
+            LEFT JOIN user_presence p ON p.user_id = u.id
+            WHERE m.ws_id = $1 AND u.id > $2
+                AND ($3::TEXT IS NULL
+                    OR u.fullname ILIKE $4 OR u.email ILIKE $4
+                    OR $3 <% u.fullname OR $3 <% u.email)
+                AND ($5::BOOLEAN IS NULL OR (m.deactivated_at IS NULL) = $5)
+                AND ($6::workspace_role IS NULL OR m.role = $6)
+                AND ($7::TIMESTAMPTZ IS NULL OR p.last_active_at >= $7)
+            ORDER BY u.id
+            LIMIT $8
             ",
         )
         .bind(ws_id as i64)
+        .bind(last_id)
+        .bind(q)
+        .bind(prefix)
+        .bind(input.active)
+        .bind(input.role)
+        .bind(input.active_since)
+        .bind(limit)
         .fetch_all(&self.pool)
         .await?;
         Ok(users)
     }
 }
 
-fn hash_password(password: &str) -> Result<String, AppError> {
+impl CreateUser {
+    /// Trim the names and lower-case the email, emails differing only by case are the same
+    fn normalize(&self) -> Self {
+        Self {
+            fullname: self.fullname.trim().to_string(),
+            email: self.email.trim().to_lowercase(),
+            workspace: self.workspace.trim().to_string(),
+            password: self.password.clone(),
+            invite: self.invite.as_deref().map(|v| v.trim().to_string()),
+        }
+    }
+
+    fn validate(&self, policy: &PasswordPolicy) -> Result<(), AppError> {
+        Validator::default()
+            .length("fullname", &self.fullname, 1, FULLNAME_MAX_LEN)
+            .email("email", &self.email)
+            .length("workspace", &self.workspace, 1, WORKSPACE_MAX_LEN)
+            .password("password", &self.password, policy)
+            .finish()
+    }
+}
+
+/// Match `%`, `_` and `\` literally in a LIKE pattern
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+fn hash_password(password: &str, config: &Argon2Config) -> Result<String, AppError> {
     let salt = SaltString::generate(&mut OsRng);
 
-    let argon2 = Argon2::default();
+    let argon2 = config
+        .hasher()
+        .map_err(argon2::password_hash::Error::from)?;
 
     let password_hash = argon2
         .hash_password(password.as_bytes(), &salt)?
@@ -183,6 +383,19 @@ fn verify_password(password: &str, password_hash: &str) -> Result<bool, AppError
     Ok(is_valid)
 }
 
+/// Whether the hash was made with another algorithm or cost than the configured ones
+fn needs_rehash(password_hash: &str, config: &Argon2Config) -> Result<bool, AppError> {
+    let password_hash = PasswordHash::new(password_hash)?;
+    let params = Params::try_from(&password_hash)?;
+    let expected = config
+        .params()
+        .map_err(argon2::password_hash::Error::from)?;
+    Ok(password_hash.algorithm != Algorithm::Argon2id.ident()
+        || params.m_cost() != expected.m_cost()
+        || params.t_cost() != expected.t_cost()
+        || params.p_cost() != expected.p_cost())
+}
+
 #[cfg(test)]
 impl CreateUser {
     pub fn new(fullname: &str, workspace: &str, email: &str, password: &str) -> Self {
@@ -191,6 +404,7 @@ impl CreateUser {
             workspace: workspace.to_string(),
             email: email.to_string(),
             password: password.to_string(),
+            invite: None,
         }
     }
 }
@@ -208,28 +422,31 @@ impl SigninUser {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::UpdateMemberRole;
     use anyhow::Result;
 
     #[tokio::test]
     async fn create_user_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
         let fullname = "TeamMeng";
-        let workspace = "none";
+        let workspace = "new-ws";
         let email = "TeamMeng@123.com";
-        let password = "123456";
+        let password = "12345678";
 
         // create user success
         let input = CreateUser::new(fullname, workspace, email, password);
         let user = state.create_user(&input).await?;
 
         assert_eq!(fullname, &user.fullname);
-        assert_eq!(email, &user.email);
+        assert_eq!(user.email, "teammeng@123.com");
         assert!(user.id > 0);
 
-        // failed to create user
+        // failed to create user, emails are compared case-insensitively
         let ret = state.create_user(&input).await;
-
-        assert!(ret.is_err());
+        assert!(matches!(ret, Err(AppError::EmailAleardyExists(_))));
+        let input = CreateUser::new(fullname, workspace, " TEAMMENG@123.com", password);
+        let ret = state.create_user(&input).await;
+        assert!(matches!(ret, Err(AppError::EmailAleardyExists(_))));
 
         Ok(())
     }
@@ -263,14 +480,148 @@ mod tests {
         let fullname = "TeamTest";
         let workspace = "acme";
         let email = "Test@123.com";
-        let password = "123456";
+        let password = "12345678";
         let input = CreateUser::new(fullname, workspace, email, password);
         let ret = state.create_user(&input).await;
 
         if let Err(AppError::EmailAleardyExists(email)) = ret {
-            assert_eq!(email, input.email);
+            assert_eq!(email, input.email.to_lowercase());
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_user_with_invalid_input_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let fullname = "x".repeat(65);
+        let workspace = "w".repeat(33);
+        let input = CreateUser::new(&fullname, &workspace, "test@", "123");
+        let ret = state.create_user(&input).await.unwrap_err();
+
+        let AppError::InvalidInput(fields) = ret else {
+            panic!("expect InvalidInput");
+        };
+        let fields: Vec<_> = fields.iter().map(|f| f.field.as_str()).collect();
+        assert_eq!(fields, ["fullname", "email", "workspace", "password"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn verify_user_should_rehash_outdated_password() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test_with(|config| {
+            config.auth.argon2.time_cost = 3;
+        })
+        .await?;
+        let input = SigninUser::new("Test@123.com", "123456");
+        assert!(state.verify_user(&input).await?.is_some());
+
+        let (password_hash,): (String,) =
+            sqlx::query_as("SELECT password_hash FROM users WHERE id = 1")
+                .fetch_one(&state.pool)
+                .await?;
+        assert!(password_hash.contains("t=3"));
+        assert!(state.verify_user(&input).await?.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_chat_users_should_search_and_paginate() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let ids = |users: Vec<ChatUser>| users.iter().map(|u| u.id).collect::<Vec<_>>();
+
+        let input = ListUsers {
+            limit: 2,
+            ..Default::default()
+        };
+        assert_eq!(ids(state.list_chat_users(1, &input).await?), [1, 2]);
+        let input = ListUsers {
+            last_id: Some(2),
+            ..input
+        };
+        assert_eq!(ids(state.list_chat_users(1, &input).await?), [3, 4]);
+        let input = ListUsers {
+            last_id: Some(u64::MAX),
+            ..input
+        };
+        assert!(matches!(
+            state.list_chat_users(1, &input).await,
+            Err(AppError::InvalidInput(_))
+        ));
+
+        // prefix of a name or email, or a close word
+        for (q, expected) in [
+            ("ali", vec![2]),
+            ("BOB@", vec![3]),
+            ("charly", vec![4]),
+            ("test", vec![1, 2, 3, 4, 5]),
+            ("100%", vec![]),
+        ] {
+            let input = ListUsers {
+                q: Some(q.to_string()),
+                ..Default::default()
+            };
+            assert_eq!(
+                ids(state.list_chat_users(1, &input).await?),
+                expected,
+                "q: {}",
+                q
+            );
         }
 
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.unwrap();
+        let input = UpdateMemberRole {
+            role: WorkspaceRole::Guest,
+        };
+        state.update_member_role(&owner, 3, &input).await?;
+        state.deactivate_member(&owner, 4).await?;
+        sqlx::query("INSERT INTO user_presence (user_id, last_active_at) VALUES (5, NOW())")
+            .execute(&state.pool)
+            .await?;
+
+        let input = ListUsers {
+            role: Some(WorkspaceRole::Guest),
+            ..Default::default()
+        };
+        assert_eq!(ids(state.list_chat_users(1, &input).await?), [3]);
+        let input = ListUsers {
+            active: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(ids(state.list_chat_users(1, &input).await?), [4]);
+        let input = ListUsers {
+            active_since: Some(Utc::now() - chrono::Duration::hours(1)),
+            ..Default::default()
+        };
+        assert_eq!(ids(state.list_chat_users(1, &input).await?), [5]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_chat_users_should_bound_pages() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        sqlx::query(
+            "
+            INSERT INTO users (ws_id, fullname, email)
+            SELECT 1, 'user' || i, 'user' || i || '@acme.org' FROM generate_series(1, 120) i
+            ",
+        )
+        .execute(&state.pool)
+        .await?;
+
+        let users = state.list_chat_users(1, &ListUsers::default()).await?;
+        assert_eq!(users.len(), 50);
+        let input = ListUsers {
+            limit: 1000,
+            ..Default::default()
+        };
+        assert_eq!(state.list_chat_users(1, &input).await?.len(), 100);
+        assert_eq!(state.fetch_all_chat_users(1).await?.len(), 125);
+
         Ok(())
     }
 
@@ -300,10 +651,18 @@ mod tests {
     fn hash_and_verify_password_should_work() -> Result<()> {
         let password = "123456";
 
-        let password_hash = hash_password(password)?;
+        let config = Argon2Config::default();
+        let password_hash = hash_password(password, &config)?;
 
         let is_valid = verify_password(password, &password_hash)?;
         assert!(is_valid);
+        assert!(!needs_rehash(&password_hash, &config)?);
+
+        let config = Argon2Config {
+            time_cost: 3,
+            ..config
+        };
+        assert!(needs_rehash(&password_hash, &config)?);
 
         Ok(())
     }
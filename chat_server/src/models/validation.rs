@@ -0,0 +1,167 @@
+use crate::{AppError, AppState, config::PasswordPolicy, error::FieldError};
+
+/// column sizes of the users and workspaces tables
+pub(crate) const FULLNAME_MAX_LEN: usize = 64;
+pub(crate) const EMAIL_MAX_LEN: usize = 64;
+pub(crate) const WORKSPACE_MAX_LEN: usize = 32;
+
+/// Collects every invalid field of a request, to report them all at once
+#[derive(Debug, Default)]
+pub(crate) struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn check(&mut self, field: &str, is_valid: bool, message: impl Into<String>) -> &mut Self {
+        if !is_valid {
+            self.errors.push(FieldError::new(field, message));
+        }
+        self
+    }
+
+    /// length in characters, as counted by postgres
+    pub fn length(&mut self, field: &str, value: &str, min: usize, max: usize) -> &mut Self {
+        let len = value.chars().count();
+        self.check(
+            field,
+            (min..=max).contains(&len),
+            format!("must have {} to {} characters", min, max),
+        )
+    }
+
+    pub fn email(&mut self, field: &str, value: &str) -> &mut Self {
+        self.length(field, value, 3, EMAIL_MAX_LEN);
+        self.check(
+            field,
+            is_valid_email(value),
+            "must be a valid email address",
+        )
+    }
+
+    pub fn password(&mut self, field: &str, value: &str, policy: &PasswordPolicy) -> &mut Self {
+        self.length(field, value, policy.min_length, policy.max_length);
+        let has = |f: fn(char) -> bool| value.chars().any(f);
+        self.check(
+            field,
+            !policy.require_lowercase || has(char::is_lowercase),
+            "must contain a lowercase letter",
+        );
+        self.check(
+            field,
+            !policy.require_uppercase || has(char::is_uppercase),
+            "must contain an uppercase letter",
+        );
+        self.check(
+            field,
+            !policy.require_digit || has(|c| c.is_ascii_digit()),
+            "must contain a digit",
+        );
+        self.check(
+            field,
+            !policy.require_symbol || has(|c| !c.is_alphanumeric()),
+            "must contain a symbol",
+        )
+    }
+
+    pub fn finish(&mut self) -> Result<(), AppError> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(AppError::InvalidInput(std::mem::take(&mut self.errors)))
+        }
+    }
+}
+
+impl AppState {
+    /// Check a new password against the configured policy
+    pub fn validate_password(&self, field: &str, password: &str) -> Result<(), AppError> {
+        Validator::default()
+            .password(field, password, &self.config.auth.password_policy)
+            .finish()
+    }
+}
+
+/// A practical subset of RFC 5322: dot-atom local part and a dns domain name
+pub(crate) fn is_valid_email(email: &str) -> bool {
+    let Some((local, domain)) = email.rsplit_once('@') else {
+        return false;
+    };
+    let is_atom = |s: &str| {
+        !s.is_empty()
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+/=?^_`{|}~-".contains(c))
+    };
+    let is_label = |s: &str| {
+        (1..=63).contains(&s.len())
+            && !s.starts_with('-')
+            && !s.ends_with('-')
+            && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+    local.len() <= 64
+        && local.split('.').all(is_atom)
+        && domain.split('.').count() >= 2
+        && domain.split('.').all(is_label)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_valid_email_should_work() {
+        for email in ["test@123.com", "a.b+c@x.example.org", "o'neil@mail-1.io"] {
+            assert!(is_valid_email(email), "{}", email);
+        }
+        for email in [
+            "",
+            "test",
+            "@123.com",
+            "test@",
+            "test@localhost",
+            "te st@123.com",
+            "a..b@123.com",
+            ".a@123.com",
+            "a@-x.com",
+            "a@x..com",
+            "a@b@c.com",
+        ] {
+            assert!(!is_valid_email(email), "{}", email);
+        }
+    }
+
+    #[test]
+    fn password_policy_should_work() {
+        let policy = PasswordPolicy {
+            min_length: 8,
+            max_length: 16,
+            require_lowercase: true,
+            require_uppercase: true,
+            require_digit: true,
+            require_symbol: true,
+        };
+        let mut v = Validator::default();
+        assert!(
+            v.password("password", "Passw0rd!", &policy)
+                .finish()
+                .is_ok()
+        );
+
+        let ret = v
+            .password("password", "pass", &policy)
+            .finish()
+            .unwrap_err();
+        let AppError::InvalidInput(fields) = ret else {
+            panic!("expect InvalidInput");
+        };
+        let messages: Vec<_> = fields.iter().map(|f| f.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "must have 8 to 16 characters",
+                "must contain an uppercase letter",
+                "must contain a digit",
+                "must contain a symbol",
+            ]
+        );
+    }
+}
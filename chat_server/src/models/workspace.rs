@@ -1,5 +1,58 @@
-use crate::{AppError, AppState};
-use chat_core::{ChatUser, Workspace};
+use crate::{
+    AppError, AppState,
+    error::FieldError,
+    models::validation::{Validator, WORKSPACE_MAX_LEN},
+};
+use chat_core::{ChatUser, User, Workspace, WorkspaceRole, revoke_user_tokens};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection};
+use tokio::fs;
+use tracing::warn;
+use utoipa::ToSchema;
+
+/// new role of a member, ownership can't be given this way
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct UpdateMemberRole {
+    pub role: WorkspaceRole,
+}
+
+/// rename the workspace
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct UpdateWorkspace {
+    pub name: String,
+}
+
+/// give the workspace to another member, the current owner becomes an admin
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct TransferOwnership {
+    pub user_id: i64,
+}
+
+/// delete the workspace, its name has to be repeated
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct DeleteWorkspace {
+    pub name: String,
+}
+
+/// A workspace the user belongs to, with its role there
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Membership {
+    /// id of the workspace
+    pub id: i64,
+    pub name: String,
+    pub owner_id: i64,
+    pub role: WorkspaceRole,
+    pub joined_at: DateTime<Utc>,
+}
+
+/// join another workspace with an invite, keeping the same account
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct JoinWorkspace {
+    pub workspace: String,
+    pub invite: String,
+}
 
 impl AppState {
     pub async fn create_workspace(&self, name: &str, user_id: u64) -> Result<Workspace, AppError> {
@@ -31,6 +84,7 @@ impl AppState {
         Ok(ws)
     }
 
+    /// Make the user the owner of the workspace, the previous owner becomes an admin
     pub async fn update_workspace_owner(
         &self,
         owner_id: u64,
@@ -38,12 +92,25 @@ impl AppState {
     ) -> Result<Workspace, AppError> {
         let ws = sqlx::query_as(
             "
-            UPDATE workspaces
-            SET owner_id = $1
-            WHERE id = $2 AND EXISTS (
-                SELECT 1 FROM users WHERE id = $1 AND ws_id = $2
+            WITH ws AS (
+                UPDATE workspaces
+                SET owner_id = $1
+                WHERE id = $2 AND EXISTS (
+                    SELECT 1 FROM workspace_members WHERE user_id = $1 AND ws_id = $2
+                )
+                RETURNING id, name, owner_id, created_at
+            ), roles AS (
+                UPDATE workspace_members
+                SET role = CASE WHEN user_id = $1 THEN 'owner' ELSE 'admin' END::workspace_role
+                WHERE ws_id = $2 AND (user_id = $1 OR role = 'owner') AND EXISTS (SELECT 1 FROM ws)
+                RETURNING user_id, role
+            ), users AS (
+                UPDATE users u
+                SET role = r.role
+                FROM roles r
+                WHERE u.id = r.user_id AND u.ws_id = $2
             )
-            RETURNING id, name, owner_id, created_at
+            SELECT id, name, owner_id, created_at FROM ws
             ",
         )
         .bind(owner_id as i64)
@@ -68,13 +135,569 @@ impl AppState {
         Ok(ws)
     }
 
+    /// The workspace of the user, if the user owns it
+    pub async fn find_owned_workspace(&self, user: &User) -> Result<Workspace, AppError> {
+        ensure_role(user, WorkspaceRole::Owner)?;
+        self.find_user_workspace(user).await
+    }
+
+    /// The workspace of the user, if the user is an admin or the owner of it
+    pub async fn find_administered_workspace(&self, user: &User) -> Result<Workspace, AppError> {
+        ensure_role(user, WorkspaceRole::Admin)?;
+        self.find_user_workspace(user).await
+    }
+
+    /// Current role of the user in the workspace, roles in tokens may be outdated.
+    ///
+    /// Fails if the user isn't an active member of it anymore. Users waiting in workspace 0 keep the role
+    /// they will have once they join.
+    pub async fn find_user_role(
+        &self,
+        user_id: i64,
+        ws_id: i64,
+    ) -> Result<WorkspaceRole, AppError> {
+        let role: Option<(WorkspaceRole,)> = if ws_id == 0 {
+            sqlx::query_as("SELECT role FROM users WHERE id = $1 AND ws_id = 0")
+                .bind(user_id)
+                .fetch_optional(&self.pool)
+                .await?
+        } else {
+            sqlx::query_as(
+                "
+                SELECT role FROM workspace_members
+                WHERE user_id = $1 AND ws_id = $2 AND deactivated_at IS NULL
+                ",
+            )
+            .bind(user_id)
+            .bind(ws_id)
+            .fetch_optional(&self.pool)
+            .await?
+        };
+        role.map(|(role,)| role)
+            .ok_or_else(|| AppError::NotFound(format!("member id: {}", user_id)))
+    }
+
+    /// The user as an active member of the workspace, with its role there
+    pub async fn find_member(&self, user_id: i64, ws_id: i64) -> Result<Option<User>, AppError> {
+        let user = sqlx::query_as(
+            "
+            SELECT u.id, m.ws_id, w.name AS ws_name, u.fullname, u.email, m.role, u.created_at
+            FROM workspace_members m
+            JOIN users u ON u.id = m.user_id
+            JOIN workspaces w ON w.id = m.ws_id
+            WHERE m.user_id = $1 AND m.ws_id = $2 AND m.deactivated_at IS NULL
+            ",
+        )
+        .bind(user_id)
+        .bind(ws_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(user)
+    }
+
+    /// Whether the user was deactivated in the workspace
+    pub(crate) async fn is_member_deactivated(
+        &self,
+        user_id: i64,
+        ws_id: i64,
+    ) -> Result<bool, AppError> {
+        let (deactivated,): (bool,) = sqlx::query_as(
+            "
+            SELECT EXISTS (
+                SELECT 1 FROM workspace_members
+                WHERE user_id = $1 AND ws_id = $2 AND deactivated_at IS NOT NULL
+            )
+            ",
+        )
+        .bind(user_id)
+        .bind(ws_id)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(deactivated)
+    }
+
+    /// The workspaces the user is an active member of, in the order it joined them
+    pub async fn list_memberships(&self, user: &User) -> Result<Vec<Membership>, AppError> {
+        let workspaces = sqlx::query_as(
+            "
+            SELECT w.id, w.name, w.owner_id, m.role, m.created_at AS joined_at
+            FROM workspace_members m
+            JOIN workspaces w ON w.id = m.ws_id
+            WHERE m.user_id = $1 AND m.deactivated_at IS NULL
+            ORDER BY m.created_at, w.id
+            ",
+        )
+        .bind(user.id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(workspaces)
+    }
+
+    /// Join another workspace with an invite, the account stays the same
+    pub async fn join_workspace(
+        &self,
+        user: &User,
+        input: &JoinWorkspace,
+    ) -> Result<Membership, AppError> {
+        ensure_own_account(user)?;
+        self.ensure_email_verified(user.id).await?;
+        let ws = self
+            .find_workspace_by_name(input.workspace.trim())
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("workspace: {}", input.workspace)))?;
+        // the invite is kept for someone else when the user is already a member
+        if self.is_member_deactivated(user.id, ws.id).await? {
+            return Err(AppError::UserDeactivated);
+        }
+        if self.find_member(user.id, ws.id).await?.is_none() {
+            let mut tx = self.pool.begin().await?;
+            let role = self
+                .redeem_invite(&mut tx, &ws, input.invite.trim(), &user.email)
+                .await?;
+            sqlx::query(
+                "
+                INSERT INTO workspace_members (ws_id, user_id, role)
+                VALUES ($1, $2, $3)
+                ON CONFLICT DO NOTHING
+                ",
+            )
+            .bind(ws.id)
+            .bind(user.id)
+            .bind(role)
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+        }
+
+        self.list_memberships(user)
+            .await?
+            .into_iter()
+            .find(|m| m.id == ws.id)
+            .ok_or_else(|| AppError::NotFound(format!("workspace: {}", ws.name)))
+    }
+
+    /// Make another workspace of the user the active one of its session, returns the user in
+    /// that workspace and a new refresh token of the session.
+    ///
+    /// Refreshing the session keeps it in that workspace, new signins land in it as well.
+    pub async fn switch_workspace(
+        &self,
+        user: &User,
+        ws_id: i64,
+    ) -> Result<(User, String), AppError> {
+        ensure_own_account(user)?;
+        let Some(sid) = user.sid else {
+            return Err(AppError::PermissionDenied(
+                "switching workspaces needs a signin session".to_string(),
+            ));
+        };
+        let mut member = self
+            .find_member(user.id, ws_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("workspace id: {}", ws_id)))?;
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("UPDATE users SET ws_id = $2, role = $3 WHERE id = $1")
+            .bind(user.id)
+            .bind(ws_id)
+            .bind(member.role)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("UPDATE sessions SET ws_id = $3 WHERE id = $1 AND user_id = $2")
+            .bind(sid)
+            .bind(user.id)
+            .bind(ws_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        member.sid = Some(sid);
+        let refresh_token = self.create_refresh_token(user.id, Some(sid)).await?;
+        Ok((member, refresh_token))
+    }
+
+    /// Change the role of a member.
+    ///
+    /// Admins manage members and guests, only the owner grants or takes away the admin role.
+    pub async fn update_member_role(
+        &self,
+        user: &User,
+        member_id: i64,
+        input: &UpdateMemberRole,
+    ) -> Result<(), AppError> {
+        let ws = self.find_administered_workspace(user).await?;
+        let member = self
+            .find_member(member_id, ws.id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("member id: {}", member_id)))?;
+
+        if input.role == WorkspaceRole::Owner || member.role == WorkspaceRole::Owner {
+            return Err(AppError::PermissionDenied(
+                "ownership can only be transferred by the owner".to_string(),
+            ));
+        }
+        if (input.role == WorkspaceRole::Admin || member.role == WorkspaceRole::Admin)
+            && user.role != WorkspaceRole::Owner
+        {
+            return Err(AppError::PermissionDenied(
+                "only the workspace owner can manage admins".to_string(),
+            ));
+        }
+
+        sqlx::query(
+            "
+            WITH m AS (
+                UPDATE workspace_members
+                SET role = $3
+                WHERE ws_id = $1 AND user_id = $2
+            )
+            UPDATE users
+            SET role = $3
+            WHERE id = $2 AND ws_id = $1
+            ",
+        )
+        .bind(ws.id)
+        .bind(member.id)
+        .bind(input.role)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// The workspace of the user, whatever its role
+    pub async fn get_workspace(&self, user: &User) -> Result<Workspace, AppError> {
+        self.find_user_workspace(user).await
+    }
+
+    /// Rename the workspace of the user, an admin
+    pub async fn rename_workspace(
+        &self,
+        user: &User,
+        input: &UpdateWorkspace,
+    ) -> Result<Workspace, AppError> {
+        let ws = self.find_administered_workspace(user).await?;
+        let name = input.name.trim();
+        Validator::default()
+            .length("name", name, 1, WORKSPACE_MAX_LEN)
+            .finish()?;
+        if name == ws.name {
+            return Ok(ws);
+        }
+        if self.find_workspace_by_name(name).await?.is_some() {
+            return Err(AppError::WorkspaceAlreadyExists(name.to_string()));
+        }
+
+        let ws = sqlx::query_as(
+            "
+            UPDATE workspaces
+            SET name = $2
+            WHERE id = $1
+            RETURNING id, name, owner_id, created_at
+            ",
+        )
+        .bind(ws.id)
+        .bind(name)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(ws)
+    }
+
+    /// Give the workspace of the user, its owner, to another member who isn't a bot
+    pub async fn transfer_workspace_ownership(
+        &self,
+        user: &User,
+        input: &TransferOwnership,
+    ) -> Result<Workspace, AppError> {
+        let ws = self.find_owned_workspace(user).await?;
+        let is_bot: Option<(bool,)> = sqlx::query_as(
+            "
+            SELECT u.is_bot
+            FROM users u
+            JOIN workspace_members m ON m.user_id = u.id
+            WHERE u.id = $1 AND m.ws_id = $2 AND m.deactivated_at IS NULL
+            ",
+        )
+        .bind(input.user_id)
+        .bind(ws.id)
+        .fetch_optional(&self.pool)
+        .await?;
+        match is_bot {
+            None => Err(AppError::NotFound(format!("member id: {}", input.user_id))),
+            Some((true,)) => Err(AppError::PermissionDenied(
+                "bots can't own a workspace".to_string(),
+            )),
+            Some((false,)) => {
+                self.update_workspace_owner(input.user_id as _, ws.id as _)
+                    .await
+            }
+        }
+    }
+
+    /// Remove a member from the workspace of the user, an admin.
+    ///
+    /// The member leaves its chats, its messages stay. It keeps its other workspaces, without any
+    /// it is signed out and its account waits in workspace 0 like pending signups. Only the owner
+    /// removes admins, the owner can't be removed.
+    pub async fn remove_member(&self, user: &User, member_id: i64) -> Result<(), AppError> {
+        let ws = self.find_administered_workspace(user).await?;
+        let member = self
+            .find_member(member_id, ws.id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("member id: {}", member_id)))?;
+        ensure_can_offboard(user, member.id, member.role)?;
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "
+            UPDATE chats
+            SET members = array_remove(members, $1)
+            WHERE ws_id = $2 AND $1 = ANY(members)
+            ",
+        )
+        .bind(member.id)
+        .bind(ws.id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM workspace_members WHERE ws_id = $1 AND user_id = $2")
+            .bind(ws.id)
+            .bind(member.id)
+            .execute(&mut *tx)
+            .await?;
+        move_to_other_workspace(&mut tx, ws.id, Some(member.id)).await?;
+        sqlx::query("UPDATE users SET ws_id = 0, role = 'member' WHERE id = $1 AND ws_id = $2")
+            .bind(member.id)
+            .bind(ws.id)
+            .execute(&mut *tx)
+            .await?;
+        let (ws_id,): (i64,) = sqlx::query_as("SELECT ws_id FROM users WHERE id = $1")
+            .bind(member.id)
+            .fetch_one(&mut *tx)
+            .await?;
+        sqlx::query(
+            "
+            UPDATE api_keys
+            SET revoked_at = NOW()
+            WHERE user_id = $1 AND ws_id = $2 AND revoked_at IS NULL
+            ",
+        )
+        .bind(member.id)
+        .bind(ws.id)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        if ws_id == 0 {
+            revoke_user_tokens(&self.pool, member.id).await?;
+        }
+        Ok(())
+    }
+
+    /// Deactivate a member of the workspace of the user, an admin.
+    ///
+    /// The member is dropped from its chats and its tokens for the workspace stop working, its
+    /// account and messages are kept. Without another workspace it is signed out.
+    pub async fn deactivate_member(&self, user: &User, member_id: i64) -> Result<(), AppError> {
+        let ws = self.find_administered_workspace(user).await?;
+        let member = self
+            .find_member(member_id, ws.id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("member id: {}", member_id)))?;
+        ensure_can_offboard(user, member.id, member.role)?;
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "
+            UPDATE workspace_members
+            SET deactivated_at = NOW(),
+                left_chat_ids = ARRAY(SELECT id FROM chats WHERE ws_id = $1 AND $2 = ANY(members))
+            WHERE ws_id = $1 AND user_id = $2
+            ",
+        )
+        .bind(ws.id)
+        .bind(member.id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            "
+            UPDATE chats
+            SET members = array_remove(members, $2)
+            WHERE ws_id = $1 AND $2 = ANY(members)
+            ",
+        )
+        .bind(ws.id)
+        .bind(member.id)
+        .execute(&mut *tx)
+        .await?;
+        move_to_other_workspace(&mut tx, ws.id, Some(member.id)).await?;
+        let (ws_id,): (i64,) = sqlx::query_as("SELECT ws_id FROM users WHERE id = $1")
+            .bind(member.id)
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        if ws_id == ws.id {
+            revoke_user_tokens(&self.pool, member.id).await?;
+        }
+        Ok(())
+    }
+
+    /// Reactivate a deactivated member, it is added back to the chats it was dropped from
+    pub async fn reactivate_member(&self, user: &User, member_id: i64) -> Result<(), AppError> {
+        let ws = self.find_administered_workspace(user).await?;
+        let role: Option<(WorkspaceRole,)> = sqlx::query_as(
+            "
+            SELECT role FROM workspace_members
+            WHERE ws_id = $1 AND user_id = $2 AND deactivated_at IS NOT NULL
+            ",
+        )
+        .bind(ws.id)
+        .bind(member_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some((role,)) = role else {
+            return Err(AppError::NotFound(format!(
+                "deactivated member id: {}",
+                member_id
+            )));
+        };
+        ensure_can_offboard(user, member_id, role)?;
+
+        let mut tx = self.pool.begin().await?;
+        let (chat_ids,): (Vec<i64>,) = sqlx::query_as(
+            "
+            WITH old AS (
+                SELECT left_chat_ids FROM workspace_members
+                WHERE ws_id = $1 AND user_id = $2
+                FOR UPDATE
+            )
+            UPDATE workspace_members
+            SET deactivated_at = NULL, left_chat_ids = '{}'
+            FROM old
+            WHERE ws_id = $1 AND user_id = $2
+            RETURNING old.left_chat_ids
+            ",
+        )
+        .bind(ws.id)
+        .bind(member_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        sqlx::query(
+            "
+            UPDATE chats
+            SET members = array_append(members, $2)
+            WHERE ws_id = $1 AND id = ANY($3) AND NOT $2 = ANY(members)
+            ",
+        )
+        .bind(ws.id)
+        .bind(member_id)
+        .bind(&chat_ids)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Delete the workspace of the user, its owner, with its chats, messages and files. Members
+    /// without another workspace are deleted as well.
+    pub async fn delete_workspace(
+        &self,
+        user: &User,
+        input: &DeleteWorkspace,
+    ) -> Result<(), AppError> {
+        let ws = self.find_owned_workspace(user).await?;
+        if ws.id == 0 {
+            return Err(AppError::PermissionDenied(
+                "workspace 0 can't be deleted".to_string(),
+            ));
+        }
+        if input.name != ws.name {
+            return Err(AppError::InvalidInput(vec![FieldError::new(
+                "name",
+                "must be the name of the workspace",
+            )]));
+        }
+
+        // members of other workspaces keep their account, everything else referring to the
+        // workspace or its remaining users is deleted in cascade
+        let mut tx = self.pool.begin().await?;
+        move_to_other_workspace(&mut tx, ws.id, None).await?;
+        // so do users deactivated or removed elsewhere, their messages there stay
+        let kept: Vec<(i64,)> = sqlx::query_as(
+            "
+            UPDATE users u
+            SET ws_id = 0, role = 'member'
+            WHERE u.ws_id = $1 AND (
+                EXISTS (
+                    SELECT 1 FROM workspace_members m WHERE m.user_id = u.id AND m.ws_id <> $1
+                ) OR EXISTS (
+                    SELECT 1 FROM messages msg JOIN chats c ON c.id = msg.chat_id
+                    WHERE msg.sender_id = u.id AND c.ws_id <> $1
+                )
+            )
+            RETURNING u.id
+            ",
+        )
+        .bind(ws.id)
+        .fetch_all(&mut *tx)
+        .await?;
+        // and users waiting to join it who already joined another one, they stay where they are
+        sqlx::query(
+            "
+            UPDATE users u
+            SET pending_ws_id = NULL
+            WHERE u.pending_ws_id = $1 AND (
+                EXISTS (
+                    SELECT 1 FROM workspace_members m WHERE m.user_id = u.id AND m.ws_id <> $1
+                ) OR EXISTS (
+                    SELECT 1 FROM messages msg JOIN chats c ON c.id = msg.chat_id
+                    WHERE msg.sender_id = u.id AND c.ws_id <> $1
+                )
+            )
+            ",
+        )
+        .bind(ws.id)
+        .execute(&mut *tx)
+        .await?;
+        // messages hold on to their sender, the ones of the workspace go first
+        sqlx::query("DELETE FROM chats WHERE ws_id = $1")
+            .bind(ws.id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM workspaces WHERE id = $1")
+            .bind(ws.id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        for (user_id,) in kept {
+            revoke_user_tokens(&self.pool, user_id).await?;
+        }
+
+        let dir = self.config.server.base_dir.join(ws.id.to_string());
+        if let Err(e) = fs::remove_dir_all(&dir).await
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            warn!("failed to remove files of workspace {}: {}", ws.id, e);
+        }
+        Ok(())
+    }
+
+    /// The workspace of the user, which may have been deleted since the user was read
+    pub(crate) async fn find_user_workspace(&self, user: &User) -> Result<Workspace, AppError> {
+        self.find_workspace_by_id(user.ws_id as _)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("workspace id: {}", user.ws_id)))
+    }
+
     #[allow(dead_code)]
     pub async fn fetch_chat_users(&self, id: u64) -> Result<Vec<ChatUser>, AppError> {
         let users = sqlx::query_as(
             "
-            SELECT id, fullname, email
-            FROM users
-            WHERE ws_id = $1 order by id
+            SELECT u.id, u.fullname, u.email, u.avatar_url,
+                m.deactivated_at IS NOT NULL AS deactivated
+            FROM workspace_members m
+            JOIN users u ON u.id = m.user_id
+            WHERE m.ws_id = $1 order by u.id
             ",
         )
         .bind(id as i64)
@@ -84,27 +707,557 @@ impl AppState {
     }
 }
 
+/// Make the users whose signins land in the workspace, or only `user_id`, land in the other
+/// workspace they joined first, if any
+async fn move_to_other_workspace(
+    conn: &mut PgConnection,
+    ws_id: i64,
+    user_id: Option<i64>,
+) -> Result<(), AppError> {
+    sqlx::query(
+        "
+        UPDATE users u
+        SET ws_id = m.ws_id, role = m.role
+        FROM (
+            SELECT DISTINCT ON (user_id) user_id, ws_id, role
+            FROM workspace_members
+            WHERE ws_id <> $1 AND deactivated_at IS NULL
+            ORDER BY user_id, created_at, ws_id
+        ) m
+        WHERE u.id = m.user_id AND u.ws_id = $1 AND ($2::BIGINT IS NULL OR u.id = $2)
+        ",
+    )
+    .bind(ws_id)
+    .bind(user_id)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Only the owner offboards admins, the owner can't be offboarded, nor can users offboard
+/// themselves
+fn ensure_can_offboard(user: &User, member_id: i64, role: WorkspaceRole) -> Result<(), AppError> {
+    if member_id == user.id || role == WorkspaceRole::Owner {
+        return Err(AppError::PermissionDenied(
+            "the owner can't be offboarded, nor can users offboard themselves".to_string(),
+        ));
+    }
+    if role == WorkspaceRole::Admin && user.role != WorkspaceRole::Owner {
+        return Err(AppError::PermissionDenied(
+            "only the workspace owner can manage admins".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// API keys and impersonation tokens act in a single workspace
+fn ensure_own_account(user: &User) -> Result<(), AppError> {
+    if user.scopes.is_some() || user.impersonated_by.is_some() {
+        Err(AppError::PermissionDenied(
+            "only available to users signed in themselves".to_string(),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Fail unless the user has at least the privileges of `role` in its workspace
+pub(crate) fn ensure_role(user: &User, role: WorkspaceRole) -> Result<(), AppError> {
+    if user.role.is_at_least(role) {
+        Ok(())
+    } else {
+        Err(AppError::PermissionDenied(format!(
+            "requires the {} role",
+            role.as_str()
+        )))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::models::CreateUser;
+    use crate::models::{
+        CreateChat, CreateInvite, CreateMessage, CreateUser, ListMessages, SigninUser,
+        UpdateAutoJoinDomains,
+    };
     use anyhow::Result;
+    use chat_core::{is_token_revoked, middlewares::TokenVerify};
 
     #[tokio::test]
     async fn workspace_create_should_work_and_set_owner() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
-        let ws = state.create_workspace("test", 0).await?;
-        let input = CreateUser::new("TeamMeng", &ws.name, "TeamMeng@123.com", "123456");
+        let input = CreateUser::new("TeamMeng", "test", "TeamMeng@123.com", "12345678");
         let user = state.create_user(&input).await?;
 
-        assert_eq!(ws.name, "test");
+        let ws = state.find_workspace_by_name("test").await?.unwrap();
         assert_eq!(user.ws_id, ws.id);
+        assert_eq!(ws.owner_id, user.id);
+        assert_eq!(
+            state.find_user_role(user.id, ws.id).await?,
+            WorkspaceRole::Owner
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_member_role_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.unwrap();
+        assert_eq!(owner.role, WorkspaceRole::Owner);
+
+        let input = UpdateMemberRole {
+            role: WorkspaceRole::Admin,
+        };
+        state.update_member_role(&owner, 2, &input).await?;
+        let admin = state.find_user_by_id(2).await?.unwrap();
+        assert_eq!(admin.role, WorkspaceRole::Admin);
+
+        // admins manage members and guests, but not other admins or the owner
+        let input = UpdateMemberRole {
+            role: WorkspaceRole::Guest,
+        };
+        state.update_member_role(&admin, 3, &input).await?;
+        assert_eq!(state.find_user_role(3, 1).await?, WorkspaceRole::Guest);
+        for id in [1, 2] {
+            let ret = state.update_member_role(&admin, id, &input).await;
+            assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        }
+        let input = UpdateMemberRole {
+            role: WorkspaceRole::Admin,
+        };
+        let ret = state.update_member_role(&admin, 3, &input).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        // members can't manage anyone
+        let member = state.find_user_by_id(4).await?.unwrap();
+        let ret = state.update_member_role(&member, 5, &input).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        // a new owner demotes the previous one to admin
+        state.update_workspace_owner(2, 1).await?;
+        assert_eq!(state.find_user_role(1, 1).await?, WorkspaceRole::Admin);
+        assert_eq!(state.find_user_role(2, 1).await?, WorkspaceRole::Owner);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rename_and_transfer_workspace_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.unwrap();
+        let member = state.find_user_by_id(2).await?.unwrap();
+
+        let input = UpdateWorkspace {
+            name: " acme-corp ".to_string(),
+        };
+        let ret = state.rename_workspace(&member, &input).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let ws = state.rename_workspace(&owner, &input).await?;
+        assert_eq!(ws.name, "acme-corp");
+        let input = UpdateWorkspace {
+            name: "foo".to_string(),
+        };
+        let ret = state.rename_workspace(&owner, &input).await;
+        assert!(matches!(ret, Err(AppError::WorkspaceAlreadyExists(_))));
 
-        let ws = state
-            .update_workspace_owner(user.id as _, ws.id as _)
+        let input = TransferOwnership { user_id: 2 };
+        let ret = state.transfer_workspace_ownership(&member, &input).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let ws = state.transfer_workspace_ownership(&owner, &input).await?;
+        assert_eq!(ws.owner_id, 2);
+        assert_eq!(state.find_user_role(1, 1).await?, WorkspaceRole::Admin);
+
+        // only members of the workspace can own it
+        let new_owner = state.find_user_by_id(2).await?.unwrap();
+        let other = state
+            .create_user(&CreateUser::new(
+                "Other",
+                "other",
+                "other@acme.org",
+                "12345678",
+            ))
             .await?;
+        let input = TransferOwnership { user_id: other.id };
+        let ret = state.transfer_workspace_ownership(&new_owner, &input).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
 
-        assert_eq!(ws.owner_id, user.id);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn remove_member_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.unwrap();
+        let member = state.find_user_by_id(2).await?.unwrap();
+
+        let ret = state.remove_member(&member, 3).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let ret = state.remove_member(&owner, 1).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        state.remove_member(&owner, 2).await?;
+        let removed = state.find_user_by_id(2).await?.unwrap();
+        assert_eq!(removed.ws_id, 0);
+        let chats = state.fetch_all_chats(2, 1).await?;
+        assert!(chats.is_empty());
+        assert!(
+            state
+                .fetch_all_chat_users(1)
+                .await?
+                .iter()
+                .all(|u| u.id != 2)
+        );
+
+        let ret = state.remove_member(&owner, 2).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_workspace_should_remove_all_data() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.unwrap();
+        state.create_session(1, &Default::default()).await?;
+
+        let input = DeleteWorkspace {
+            name: "wrong".to_string(),
+        };
+        let ret = state.delete_workspace(&owner, &input).await;
+        assert!(matches!(ret, Err(AppError::InvalidInput(_))));
+        let admin = {
+            state.update_workspace_owner(2, 1).await?;
+            state.find_user_by_id(1).await?.unwrap()
+        };
+        let input = DeleteWorkspace {
+            name: "acme".to_string(),
+        };
+        let ret = state.delete_workspace(&admin, &input).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        let owner = state.find_user_by_id(2).await?.unwrap();
+        state.delete_workspace(&owner, &input).await?;
+        assert!(state.find_workspace_by_id(1).await?.is_none());
+        assert!(state.find_user_by_id(1).await?.is_none());
+        let (chats,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM chats WHERE ws_id = 1")
+            .fetch_one(&state.pool)
+            .await?;
+        assert_eq!(chats, 0);
+        // other workspaces are untouched
+        assert!(state.find_workspace_by_name("foo").await?.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_workspace_should_keep_history_elsewhere() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let (ws, consulting_owner) = join_other_workspace(&state).await?;
+        let input = CreateChat {
+            members: vec![consulting_owner.id, 1],
+            ..Default::default()
+        };
+        let chat = state
+            .create_chat(&input, consulting_owner.id as _, ws.id as _)
+            .await?;
+        let input = CreateMessage {
+            content: "hello".to_string(),
+            files: vec![],
+        };
+        let message = state.create_message(input, chat.id as _, 1).await?;
+        state.deactivate_member(&consulting_owner, 1).await?;
+        state
+            .log_impersonation(5, 3, "GET", "/api/chats", 200)
+            .await?;
+
+        // user 1 is only deactivated in consulting, its message there stays
+        state.update_workspace_owner(2, 1).await?;
+        let owner = state.find_user_by_id(2).await?.unwrap();
+        let input = DeleteWorkspace {
+            name: "acme".to_string(),
+        };
+        state.delete_workspace(&owner, &input).await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        assert_eq!(user.ws_id, 0);
+        assert!(state.find_user_by_id(3).await?.is_none());
+        let messages = state
+            .list_messages(
+                ListMessages {
+                    last_id: None,
+                    limit: 10,
+                },
+                chat.id as _,
+            )
+            .await?;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].id, message.id);
+
+        // the audit trail is kept
+        let (logs,): (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM impersonation_logs WHERE user_id = 3")
+                .fetch_one(&state.pool)
+                .await?;
+        assert_eq!(logs, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_workspace_should_keep_pending_users_of_other_workspaces() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.unwrap();
+        let input = UpdateAutoJoinDomains {
+            domains: vec!["acme.org".to_string()],
+        };
+        state.update_auto_join_domains(&owner, &input).await?;
+        let input = CreateUser::new("TeamMeng", "acme", "TeamMeng@acme.org", "12345678");
+        let pending = state.create_user(&input).await?;
+
+        // waiting to join acme, it joins consulting and talks there meanwhile
+        let input = CreateUser::new(
+            "Consulting",
+            "consulting",
+            "boss@consulting.com",
+            "12345678",
+        );
+        let consulting_owner = state.create_user(&input).await?;
+        let consulting_owner = state.find_user_by_id(consulting_owner.id).await?.unwrap();
+        let invite = state
+            .create_invite(&consulting_owner, &CreateInvite::default())
+            .await?;
+        let input = JoinWorkspace {
+            workspace: "consulting".to_string(),
+            invite: invite.code,
+        };
+        let ws = state.join_workspace(&pending, &input).await?;
+        let input = CreateChat {
+            members: vec![consulting_owner.id, pending.id],
+            ..Default::default()
+        };
+        let chat = state
+            .create_chat(&input, consulting_owner.id as _, ws.id as _)
+            .await?;
+        let input = CreateMessage {
+            content: "hello".to_string(),
+            files: vec![],
+        };
+        state
+            .create_message(input, chat.id as _, pending.id as _)
+            .await?;
+
+        let input = DeleteWorkspace {
+            name: "acme".to_string(),
+        };
+        state.delete_workspace(&owner, &input).await?;
+        assert!(state.find_user_by_id(pending.id).await?.is_some());
+        assert!(state.find_member(pending.id, ws.id).await?.is_some());
+        let (pending_ws_id,): (Option<i64>,) =
+            sqlx::query_as("SELECT pending_ws_id FROM users WHERE id = $1")
+                .bind(pending.id)
+                .fetch_one(&state.pool)
+                .await?;
+        assert_eq!(pending_ws_id, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn deactivate_member_should_keep_messages_and_restore_chats() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.unwrap();
+        let member = state.find_user_by_id(2).await?.unwrap();
+        let token = state.ek.sign(member.clone())?;
+
+        let ret = state.deactivate_member(&member, 3).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let ret = state.deactivate_member(&owner, 1).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        state.deactivate_member(&owner, 2).await?;
+        assert!(state.verify(&token).await.is_err());
+        let signin = SigninUser::new("alice@123.com", "123456");
+        let ret = state.verify_user(&signin).await;
+        assert!(matches!(ret, Err(AppError::UserDeactivated)));
+        assert!(state.fetch_all_chats(2, 1).await?.is_empty());
+        let input = CreateChat {
+            members: vec![1, 2],
+            ..Default::default()
+        };
+        let ret = state.create_chat(&input, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::CreateChatError(_))));
+
+        // still listed, along with its messages
+        let users = state.fetch_all_chat_users(1).await?;
+        assert!(users.iter().any(|u| u.id == 2 && u.deactivated));
+        let (sent,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM messages WHERE sender_id = 2")
+            .fetch_one(&state.pool)
+            .await?;
+        assert!(sent > 0);
+
+        state.reactivate_member(&owner, 2).await?;
+        assert_eq!(state.fetch_all_chats(2, 1).await?.len(), 3);
+        assert!(state.verify_user(&signin).await?.is_some());
+        let users = state.fetch_all_chat_users(1).await?;
+        assert!(users.iter().all(|u| !u.deactivated));
+        let ret = state.reactivate_member(&owner, 2).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        Ok(())
+    }
+
+    /// User 1 of acme joins the workspace of a new user, returns the workspace and its owner
+    async fn join_other_workspace(state: &AppState) -> Result<(Workspace, User)> {
+        let input = CreateUser::new(
+            "Consulting",
+            "consulting",
+            "boss@consulting.com",
+            "12345678",
+        );
+        let owner = state.create_user(&input).await?;
+        let owner = state.find_user_by_id(owner.id).await?.unwrap();
+        let invite = state
+            .create_invite(&owner, &CreateInvite::default())
+            .await?;
+
+        let user = state.find_user_by_id(1).await?.unwrap();
+        let input = JoinWorkspace {
+            workspace: "consulting".to_string(),
+            invite: invite.code,
+        };
+        let membership = state.join_workspace(&user, &input).await?;
+        assert_eq!(membership.role, WorkspaceRole::Member);
+        let ws = state.find_workspace_by_name("consulting").await?.unwrap();
+        Ok((ws, owner))
+    }
+
+    #[tokio::test]
+    async fn join_workspace_should_need_verified_email() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test_with(|config| {
+            config.auth.require_verified_email = true;
+        })
+        .await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.unwrap();
+        let input = CreateUser::new("TeamMeng", "new-ws", "TeamMeng@123.com", "12345678");
+        let user = state.create_user(&input).await?;
+        let invite = state
+            .create_invite(&owner, &CreateInvite::default())
+            .await?;
+        let input = JoinWorkspace {
+            workspace: "acme".to_string(),
+            invite: invite.code,
+        };
+
+        let ret = state.join_workspace(&user, &input).await;
+        assert!(matches!(ret, Err(AppError::EmailNotVerified)));
+        // the invite is still there once verified
+        sqlx::query("UPDATE users SET email_verified_at = NOW() WHERE id = $1")
+            .bind(user.id)
+            .execute(&state.pool)
+            .await?;
+        let membership = state.join_workspace(&user, &input).await?;
+        assert_eq!(membership.name, "acme");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn switch_workspace_should_keep_data_isolated() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let (ws, _) = join_other_workspace(&state).await?;
+        let (sid, refresh_token) = state.create_session(1, &Default::default()).await?;
+        let user = User {
+            sid: Some(sid),
+            ..state.find_user_by_id(1).await?.unwrap()
+        };
+
+        let workspaces = state.list_memberships(&user).await?;
+        let names: Vec<_> = workspaces.iter().map(|w| w.name.as_str()).collect();
+        assert_eq!(names, ["acme", "consulting"]);
+
+        let (member, _) = state.switch_workspace(&user, ws.id).await?;
+        assert_eq!(member.ws_id, ws.id);
+        assert_eq!(member.ws_name, "consulting");
+        assert_eq!(member.role, WorkspaceRole::Member);
+        assert_eq!(member.sid, Some(sid));
+        // the session stays in the workspace
+        let (refreshed, _) = state.rotate_refresh_token(&refresh_token).await?;
+        assert_eq!(refreshed.ws_id, ws.id);
+
+        // each workspace only sees its own chats and users
+        assert!(state.fetch_all_chats(1, ws.id as _).await?.is_empty());
+        assert_eq!(state.fetch_all_chats(1, 1).await?.len(), 4);
+        let ids: Vec<_> = state
+            .fetch_all_chat_users(ws.id as _)
+            .await?
+            .iter()
+            .map(|u| u.id)
+            .collect();
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains(&1) && !ids.contains(&2));
+        let input = CreateChat {
+            members: vec![1, 2],
+            ..Default::default()
+        };
+        let ret = state.create_chat(&input, 1, ws.id as _).await;
+        assert!(matches!(ret, Err(AppError::CreateChatError(_))));
+
+        let ret = state.switch_workspace(&user, 3).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn leaving_a_workspace_should_keep_the_others() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let (ws, owner) = join_other_workspace(&state).await?;
+        let (sid, _) = state.create_session(1, &Default::default()).await?;
+        let user = User {
+            sid: Some(sid),
+            ..state.find_user_by_id(1).await?.unwrap()
+        };
+        let acme_token = state.ek.sign(user.clone())?;
+        let (switched, _) = state.switch_workspace(&user, ws.id).await?;
+        let token = state.ek.sign(switched)?;
+
+        // removed from the active workspace, the user lands back in acme
+        state.remove_member(&owner, 1).await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        assert_eq!(user.ws_id, 1);
+        // tokens for the workspace stop working everywhere, the others are kept
+        let claims = state.dk.decode(&token)?;
+        assert!(is_token_revoked(&state.pool, &claims).await?);
+        let claims = state.dk.decode(&acme_token)?;
+        assert!(!is_token_revoked(&state.pool, &claims).await?);
+        assert!(state.find_member(1, ws.id).await?.is_none());
+        let ret = state.find_user_role(1, ws.id).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        assert_eq!(state.find_user_role(1, 1).await?, WorkspaceRole::Member);
+
+        // members of other workspaces survive the deletion of one
+        let invite = state
+            .create_invite(&owner, &CreateInvite::default())
+            .await?;
+        let input = JoinWorkspace {
+            workspace: "consulting".to_string(),
+            invite: invite.code,
+        };
+        state.join_workspace(&user, &input).await?;
+        let input = DeleteWorkspace {
+            name: "consulting".to_string(),
+        };
+        state.delete_workspace(&owner, &input).await?;
+        assert!(state.find_workspace_by_id(ws.id as _).await?.is_none());
+        assert!(state.find_user_by_id(owner.id).await?.is_none());
+        assert_eq!(state.list_memberships(&user).await?.len(), 1);
 
         Ok(())
     }
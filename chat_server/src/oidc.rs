@@ -0,0 +1,449 @@
+use crate::AppError;
+use data_encoding::BASE64URL_NOPAD;
+use jwt_simple::prelude::*;
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// clock skew tolerance for ID tokens, in seconds
+const ID_TOKEN_LEEWAY: u64 = 60;
+
+/// The parts of the OpenID provider metadata we use
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+/// What we learn about the user from a validated ID token
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdToken {
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct IdTokenClaims {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ProviderJwks {
+    keys: Vec<ProviderJwk>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ProviderJwk {
+    kty: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kid: Option<String>,
+    // RSA
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    n: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    e: Option<String>,
+    // EC
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    crv: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    x: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    y: Option<String>,
+}
+
+/// Fetch the provider metadata from `{issuer}/.well-known/openid-configuration`
+pub async fn discover(http: &Client, issuer: &str) -> Result<ProviderMetadata, AppError> {
+    let url = format!(
+        "{}/.well-known/openid-configuration",
+        issuer.trim_end_matches('/')
+    );
+    let metadata: ProviderMetadata = get_json(http, &url).await?;
+    // OpenID Connect Discovery 1.0, section 4.3
+    if metadata.issuer != issuer {
+        return Err(AppError::IdpError(format!(
+            "issuer mismatch, expected {} got {}",
+            issuer, metadata.issuer
+        )));
+    }
+    Ok(metadata)
+}
+
+/// S256 PKCE challenge of a code verifier (RFC 7636)
+pub fn pkce_challenge(verifier: &str) -> String {
+    BASE64URL_NOPAD.encode(&Sha256::digest(verifier.as_bytes()))
+}
+
+/// Where to send the browser to sign in at the provider
+pub fn authorization_url(
+    metadata: &ProviderMetadata,
+    client_id: &str,
+    redirect_uri: &str,
+    state: &str,
+    nonce: &str,
+    code_verifier: &str,
+) -> Result<String, AppError> {
+    let mut url = Url::parse(&metadata.authorization_endpoint)
+        .map_err(|e| AppError::IdpError(format!("invalid authorization endpoint: {}", e)))?;
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", client_id)
+        .append_pair("redirect_uri", redirect_uri)
+        .append_pair("scope", "openid email profile")
+        .append_pair("state", state)
+        .append_pair("nonce", nonce)
+        .append_pair("code_challenge", &pkce_challenge(code_verifier))
+        .append_pair("code_challenge_method", "S256");
+    Ok(url.into())
+}
+
+/// Exchange an authorization code for the ID token
+pub async fn exchange_code(
+    http: &Client,
+    metadata: &ProviderMetadata,
+    client_id: &str,
+    client_secret: Option<&str>,
+    redirect_uri: &str,
+    code: &str,
+    code_verifier: &str,
+) -> Result<String, AppError> {
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", redirect_uri),
+        ("client_id", client_id),
+        ("code_verifier", code_verifier),
+    ];
+    if let Some(secret) = client_secret {
+        form.push(("client_secret", secret));
+    }
+
+    let ret: TokenResponse = http
+        .post(&metadata.token_endpoint)
+        .form(&form)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|e| AppError::IdpError(e.to_string()))?
+        .json()
+        .await
+        .map_err(|e| AppError::IdpError(e.to_string()))?;
+    Ok(ret.id_token)
+}
+
+/// Validate signature, issuer, audience, expiry and nonce of an ID token
+pub async fn validate_id_token(
+    http: &Client,
+    metadata: &ProviderMetadata,
+    client_id: &str,
+    nonce: &str,
+    token: &str,
+) -> Result<IdToken, AppError> {
+    let jwks: ProviderJwks = get_json(http, &metadata.jwks_uri).await?;
+    let header = Token::decode_metadata(token).map_err(invalid_id_token)?;
+    let jwk = jwks
+        .keys
+        .iter()
+        .find(|jwk| header.key_id().is_none() || jwk.kid.as_deref() == header.key_id())
+        .ok_or_else(|| AppError::SsoError("no key for the ID token".to_string()))?;
+
+    let options = VerificationOptions {
+        allowed_issuers: Some(HashSet::from_strings(&[&metadata.issuer])),
+        allowed_audiences: Some(HashSet::from_strings(&[client_id])),
+        required_nonce: Some(nonce.to_string()),
+        time_tolerance: Some(Duration::from_secs(ID_TOKEN_LEEWAY)),
+        ..Default::default()
+    };
+    let claims = match (header.algorithm(), jwk.kty.as_str()) {
+        ("RS256", "RSA") => {
+            let n = decode_member(&jwk.n)?;
+            let e = decode_member(&jwk.e)?;
+            RS256PublicKey::from_components(&n, &e)
+                .and_then(|key| key.verify_token::<IdTokenClaims>(token, Some(options)))
+        }
+        ("ES256", "EC") if jwk.crv.as_deref() == Some("P-256") => {
+            let mut point = vec![0x04];
+            point.extend(decode_member(&jwk.x)?);
+            point.extend(decode_member(&jwk.y)?);
+            ES256PublicKey::from_bytes(&point)
+                .and_then(|key| key.verify_token::<IdTokenClaims>(token, Some(options)))
+        }
+        (alg, _) => {
+            return Err(AppError::SsoError(format!(
+                "unsupported ID token algorithm: {}",
+                alg
+            )));
+        }
+    }
+    .map_err(invalid_id_token)?;
+
+    let subject = claims
+        .subject
+        .ok_or_else(|| AppError::SsoError("ID token without subject".to_string()))?;
+    Ok(IdToken {
+        subject,
+        email: claims.custom.email,
+        email_verified: claims.custom.email_verified,
+        name: claims.custom.name,
+    })
+}
+
+async fn get_json<T: for<'de> Deserialize<'de>>(http: &Client, url: &str) -> Result<T, AppError> {
+    http.get(url)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|e| AppError::IdpError(e.to_string()))?
+        .json()
+        .await
+        .map_err(|e| AppError::IdpError(e.to_string()))
+}
+
+fn decode_member(value: &Option<String>) -> Result<Vec<u8>, AppError> {
+    let value = value
+        .as_deref()
+        .ok_or_else(|| AppError::SsoError("incomplete provider key".to_string()))?;
+    BASE64URL_NOPAD
+        .decode(value.as_bytes())
+        .map_err(|e| AppError::SsoError(format!("invalid provider key: {}", e)))
+}
+
+fn invalid_id_token(e: jwt_simple::Error) -> AppError {
+    AppError::SsoError(format!("invalid ID token: {}", e))
+}
+
+/// A minimal OpenID provider to test the login flow against
+#[cfg(test)]
+pub mod mock {
+    use super::*;
+    use axum::{
+        Form, Json, Router,
+        extract::State,
+        http::StatusCode,
+        routing::{get, post},
+    };
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+    use tokio::net::TcpListener;
+
+    pub const CLIENT_ID: &str = "chat";
+
+    #[derive(Clone)]
+    pub struct MockIdp {
+        pub issuer: String,
+        inner: Arc<MockIdpInner>,
+    }
+
+    struct MockIdpInner {
+        key: ES256KeyPair,
+        // code -> (code challenge, claims of the ID token)
+        codes: Mutex<HashMap<String, (String, JWTClaims<IdTokenClaims>)>>,
+    }
+
+    #[derive(Deserialize)]
+    struct TokenRequest {
+        code: String,
+        client_id: String,
+        code_verifier: String,
+    }
+
+    impl MockIdp {
+        pub async fn start() -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let issuer = format!("http://{}", listener.local_addr().unwrap());
+            let idp = Self {
+                issuer,
+                inner: Arc::new(MockIdpInner {
+                    key: ES256KeyPair::generate().with_key_id("mock"),
+                    codes: Mutex::new(HashMap::new()),
+                }),
+            };
+
+            let app = Router::new()
+                .route("/.well-known/openid-configuration", get(metadata))
+                .route("/jwks", get(jwks))
+                .route("/token", post(token))
+                .with_state(idp.clone());
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            idp
+        }
+
+        /// What the provider does once the user signed in: issue a code for the request in `url`
+        pub fn authorize(
+            &self,
+            url: &str,
+            subject: &str,
+            email: &str,
+            name: &str,
+        ) -> (String, String) {
+            let url = Url::parse(url).unwrap();
+            let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+            let claims = Claims::with_custom_claims(
+                IdTokenClaims {
+                    email: Some(email.to_string()),
+                    email_verified: true,
+                    name: Some(name.to_string()),
+                },
+                Duration::from_mins(5),
+            )
+            .with_issuer(&self.issuer)
+            .with_audience(&params["client_id"])
+            .with_subject(subject)
+            .with_nonce(&params["nonce"]);
+
+            let code = format!("code-{}", subject);
+            let challenge = params["code_challenge"].clone();
+            self.inner
+                .codes
+                .lock()
+                .unwrap()
+                .insert(code.clone(), (challenge, claims));
+            (code, params["state"].clone())
+        }
+    }
+
+    async fn metadata(State(idp): State<MockIdp>) -> Json<ProviderMetadata> {
+        Json(ProviderMetadata {
+            issuer: idp.issuer.clone(),
+            authorization_endpoint: format!("{}/authorize", idp.issuer),
+            token_endpoint: format!("{}/token", idp.issuer),
+            jwks_uri: format!("{}/jwks", idp.issuer),
+        })
+    }
+
+    async fn jwks(State(idp): State<MockIdp>) -> Json<ProviderJwks> {
+        // a P-256 SubjectPublicKeyInfo ends with the uncompressed point: 0x04 || x || y
+        let der = idp.inner.key.public_key().to_der().unwrap();
+        let point = &der[der.len() - 65..];
+        Json(ProviderJwks {
+            keys: vec![ProviderJwk {
+                kty: "EC".to_string(),
+                kid: Some("mock".to_string()),
+                n: None,
+                e: None,
+                crv: Some("P-256".to_string()),
+                x: Some(BASE64URL_NOPAD.encode(&point[1..33])),
+                y: Some(BASE64URL_NOPAD.encode(&point[33..])),
+            }],
+        })
+    }
+
+    async fn token(
+        State(idp): State<MockIdp>,
+        Form(input): Form<TokenRequest>,
+    ) -> Result<Json<serde_json::Value>, StatusCode> {
+        let (challenge, claims) = idp
+            .inner
+            .codes
+            .lock()
+            .unwrap()
+            .remove(&input.code)
+            .ok_or(StatusCode::BAD_REQUEST)?;
+        if input.client_id != CLIENT_ID || pkce_challenge(&input.code_verifier) != challenge {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        let id_token = idp
+            .inner
+            .key
+            .sign(claims)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        Ok(Json(serde_json::json!({
+            "access_token": "mock",
+            "token_type": "Bearer",
+            "id_token": id_token,
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mock::*, *};
+    use anyhow::Result;
+
+    #[test]
+    fn pkce_challenge_should_match_rfc7636() {
+        // example from RFC 7636 appendix B
+        let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+        assert_eq!(
+            pkce_challenge(verifier),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[tokio::test]
+    async fn oidc_flow_should_work() -> Result<()> {
+        let idp = MockIdp::start().await;
+        let http = Client::new();
+        let metadata = discover(&http, &idp.issuer).await?;
+
+        let redirect_uri = "http://localhost:6688/api/sso/callback";
+        let url = authorization_url(
+            &metadata,
+            CLIENT_ID,
+            redirect_uri,
+            "state",
+            "nonce",
+            "verifier-123",
+        )?;
+        let (code, state) = idp.authorize(&url, "alice", "Alice@123.com", "Alice");
+        assert_eq!(state, "state");
+
+        // the verifier must match the challenge
+        let ret = exchange_code(
+            &http,
+            &metadata,
+            CLIENT_ID,
+            None,
+            redirect_uri,
+            &code,
+            "other",
+        )
+        .await;
+        assert!(ret.is_err());
+
+        let (code, _) = idp.authorize(&url, "alice", "Alice@123.com", "Alice");
+        let token = exchange_code(
+            &http,
+            &metadata,
+            CLIENT_ID,
+            None,
+            redirect_uri,
+            &code,
+            "verifier-123",
+        )
+        .await?;
+
+        let ret = validate_id_token(&http, &metadata, CLIENT_ID, "other", &token).await;
+        assert!(ret.is_err());
+        let ret = validate_id_token(&http, &metadata, "other", "nonce", &token).await;
+        assert!(ret.is_err());
+
+        let id_token = validate_id_token(&http, &metadata, CLIENT_ID, "nonce", &token).await?;
+        assert_eq!(
+            id_token,
+            IdToken {
+                subject: "alice".to_string(),
+                email: Some("Alice@123.com".to_string()),
+                email_verified: true,
+                name: Some("Alice".to_string()),
+            }
+        );
+
+        Ok(())
+    }
+}
@@ -1,11 +1,22 @@
 use crate::{
     AppState, AuthOutput,
-    error::ErrorOutput,
+    error::{ErrorOutput, FieldError},
     handlers::*,
-    models::{ChatFile, CreateChat, CreateMessage, ListMessages, SigninUser},
+    models::{
+        ApiKey, ApiKeyOutput, ApiScope, ChangePassword, ChatFile, CreateApiKey, CreateChat,
+        CreateInvite, CreateMessage, DeleteWorkspace, Dnd, DndWindow, EventTicket, ForgotPassword,
+        ImpersonationOutput, Invite, InviteOutput, JoinWorkspace, ListMessages, ListUsers,
+        LogoutUser, Membership, Profile, RecoveryCodes, RefreshToken, ResetPassword, Session,
+        SigninChallenge, SigninUser, TotpSetup, TransferOwnership, TwoFactorCode,
+        UpdateAutoJoinDomains, UpdateDnd, UpdateMemberRole, UpdateProfile, UpdateStatus,
+        UpdateTwoFactorPolicy, UpdateWorkspace, UpdateWorkspaceSso, VerifyEmail,
+        VerifySigninChallenge,
+    },
 };
 use axum::Router;
-use chat_core::{Chat, ChatType, ChatUser, Message, User, Workspace};
+use chat_core::{
+    Chat, ChatType, ChatUser, Jwk, Jwks, Message, User, UserStatus, Workspace, WorkspaceRole,
+};
 use utoipa::{
     Modify, OpenApi,
     openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
@@ -22,16 +33,72 @@ pub(crate) trait OpenApiRouter {
 #[openapi(
     paths(
         signin_handler,
+        signin_two_factor_handler,
+        sso_login_handler,
+        sso_callback_handler,
         signup_handler,
+        refresh_handler,
+        logout_handler,
+        logout_all_handler,
+        forgot_password_handler,
+        reset_password_handler,
+        get_profile_handler,
+        update_profile_handler,
+        update_avatar_handler,
+        delete_avatar_handler,
+        get_status_handler,
+        update_status_handler,
+        clear_status_handler,
+        get_dnd_handler,
+        update_dnd_handler,
+        change_password_handler,
+        list_sessions_handler,
+        revoke_session_handler,
+        verify_email_handler,
+        resend_email_verification_handler,
+        setup_totp_handler,
+        enable_totp_handler,
+        disable_totp_handler,
+        create_recovery_codes_handler,
+        jwks_handler,
+        create_event_ticket_handler,
+        impersonate_handler,
         list_chat_handler,
         create_chat_handler,
         get_chat_handler,
+        delete_chat_handler,
         list_messages_handler,
         send_message_handler,
-        list_chat_users_handler
+        list_chat_users_handler,
+        list_statuses_handler,
+        list_workspaces_handler,
+        join_workspace_handler,
+        switch_workspace_handler,
+        get_workspace_handler,
+        update_workspace_handler,
+        delete_workspace_handler,
+        transfer_ownership_handler,
+        remove_member_handler,
+        deactivate_member_handler,
+        reactivate_member_handler,
+        update_two_factor_policy_handler,
+        update_workspace_sso_handler,
+        create_api_key_handler,
+        list_api_keys_handler,
+        revoke_api_key_handler,
+        update_member_role_handler,
+        create_invite_handler,
+        list_invites_handler,
+        revoke_invite_handler,
+        update_auto_join_domains_handler
     ),
     components(schemas(AuthOutput, Chat, ChatType, ChatUser, ChatFile, CreateChat, ChatUser, Message,
-         CreateMessage, ListMessages, SigninUser, User, Workspace, ErrorOutput)),
+         CreateMessage, ListMessages, LogoutUser, RefreshToken, SigninUser, User, Workspace, ErrorOutput, FieldError, Jwk, Jwks, ForgotPassword, ResetPassword, ChangePassword, VerifyEmail,
+         SigninChallenge, VerifySigninChallenge, TotpSetup, TransferOwnership, TwoFactorCode, RecoveryCodes, UpdateTwoFactorPolicy,
+         UpdateWorkspaceSso, ApiKey, ApiKeyOutput, ApiScope, CreateApiKey, WorkspaceRole,
+         UpdateMemberRole, CreateInvite, Invite, InviteOutput, UpdateAutoJoinDomains, Session, EventTicket, ImpersonationOutput,
+         Profile, UpdateProfile, UserStatus, UpdateStatus, Dnd, DndWindow, UpdateDnd,
+         UpdateWorkspace, TransferOwnership, DeleteWorkspace, Membership, JoinWorkspace, ListUsers)),
     modifiers(&SecurityAddon),
     tags(
         (name = "Chat", description = "Chat related operations")
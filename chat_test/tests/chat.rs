@@ -18,7 +18,21 @@ struct AuthToken {
     token: String,
 }
 
-struct NotifyServer;
+#[derive(Debug, Deserialize)]
+struct EventTicket {
+    ticket: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Presence {
+    user_id: i64,
+    status: String,
+}
+
+struct NotifyServer {
+    addr: SocketAddr,
+}
 
 struct ChatServer {
     addr: SocketAddr,
@@ -31,25 +45,28 @@ async fn chat_server_should_work() -> Result<()> {
     let (tdb, state) = AppState::new_for_test().await?;
     let db_url = tdb.url();
     let chat_server = ChatServer::new(state).await?;
-    NotifyServer::new(db_url, &chat_server.token).await?;
+    let ticket = chat_server.event_ticket().await?;
+    let notify_server = NotifyServer::new(db_url, chat_server.addr, &ticket).await?;
     let chat = chat_server.create_chat().await?;
     chat_server.create_message(chat.id as _).await?;
     chat_server.upload().await?;
     sleep(Duration::from_secs(1)).await;
+    notify_server.presence(&chat_server).await?;
     Ok(())
 }
 
 impl NotifyServer {
-    async fn new(db_url: String, token: &str) -> Result<Self> {
+    async fn new(db_url: String, chat_addr: SocketAddr, ticket: &str) -> Result<Self> {
         let mut config = notify_server::AppConfig::load()?;
         config.server.db_url = db_url;
+        config.auth.jwks_url = Some(format!("http://{}/.well-known/jwks.json", chat_addr));
         let app = notify_server::get_router(config).await?;
         let listener = TcpListener::bind(WILD_ADDR).await?;
         let addr = listener.local_addr()?;
 
         tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
 
-        let mut es = EventSource::get(format!("http://{}/events?token={}", addr, token));
+        let mut es = EventSource::get(format!("http://{}/events?ticket={}", addr, ticket));
 
         tokio::spawn(async move {
             while let Some(event) = es.next().await {
@@ -81,7 +98,25 @@ impl NotifyServer {
             }
         });
 
-        Ok(NotifyServer)
+        Ok(NotifyServer { addr })
+    }
+
+    async fn presence(&self, chat_server: &ChatServer) -> Result<()> {
+        let res = chat_server
+            .client
+            .get(format!("http://{}/presence", self.addr))
+            .header("Authorization", format!("Bearer {}", chat_server.token))
+            .send()
+            .await?;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        let presence: Vec<Presence> = res.json().await?;
+        let status = |id| &presence.iter().find(|p| p.user_id == id).unwrap().status;
+        // user 1 holds the event stream
+        assert_eq!(status(1), "online");
+        assert_eq!(status(2), "offline");
+
+        Ok(())
     }
 }
 
@@ -126,6 +161,19 @@ impl ChatServer {
         Ok(ret.token)
     }
 
+    async fn event_ticket(&self) -> Result<String> {
+        let res = self
+            .client
+            .post(format!("http://{}/api/events/tickets", self.addr))
+            .header("Authorization", format!("Bearer {}", self.token))
+            .send()
+            .await?;
+
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let ret: EventTicket = res.json().await?;
+        Ok(ret.ticket)
+    }
+
     async fn create_chat(&self) -> Result<Chat> {
         let res = self
             .client
@@ -0,0 +1,19 @@
+-- refresh tokens, stored hashed, rotated on every use
+CREATE TABLE IF NOT EXISTS refresh_tokens(
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    -- all tokens rotated from the same signin share a family
+    family_id UUID NOT NULL,
+    -- sha256 of the token, hex encoded
+    token_hash CHAR(64) NOT NULL UNIQUE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    rotated_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+-- create index for refresh tokens for family_id
+CREATE INDEX IF NOT EXISTS refresh_tokens_family_id_index ON refresh_tokens(family_id);
+
+-- create index for refresh tokens for user_id
+CREATE INDEX IF NOT EXISTS refresh_tokens_user_id_index ON refresh_tokens(user_id);
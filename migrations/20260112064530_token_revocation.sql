@@ -0,0 +1,26 @@
+-- tokens issued before this time are no longer valid
+ALTER TABLE users
+  ADD COLUMN tokens_revoked_at TIMESTAMPTZ;
+
+-- revoked tokens by jti, kept until they expire
+CREATE TABLE IF NOT EXISTS revoked_tokens(
+    jti VARCHAR(64) PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+-- create index for revoked tokens for expires_at
+CREATE INDEX IF NOT EXISTS revoked_tokens_expires_at_index ON revoked_tokens(expires_at);
+
+-- kill every session of a user, e.g. for a compromised account:
+--   SELECT revoke_user_tokens(<user id>);
+CREATE OR REPLACE FUNCTION revoke_user_tokens(uid bigint)
+  RETURNS void
+  AS $$
+BEGIN
+  UPDATE users SET tokens_revoked_at = NOW() WHERE id = uid;
+  UPDATE refresh_tokens SET revoked_at = NOW() WHERE user_id = uid AND revoked_at IS NULL;
+END;
+$$
+LANGUAGE plpgsql;
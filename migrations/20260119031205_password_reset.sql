@@ -0,0 +1,13 @@
+-- single-use password reset tokens, stored hashed
+CREATE TABLE IF NOT EXISTS password_reset_tokens(
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    -- sha256 of the token, hex encoded
+    token_hash CHAR(64) NOT NULL UNIQUE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+-- create index for password reset tokens for user_id
+CREATE INDEX IF NOT EXISTS password_reset_tokens_user_id_index ON password_reset_tokens(user_id);
@@ -0,0 +1,23 @@
+-- unverified users have no email_verified_at, users created before verification existed are verified
+ALTER TABLE users
+  ADD COLUMN email_verified_at TIMESTAMPTZ;
+
+UPDATE users SET email_verified_at = created_at;
+
+-- workspace an unverified user signed up to join, joined once the email is verified
+ALTER TABLE users
+  ADD COLUMN pending_ws_id BIGINT REFERENCES workspaces(id);
+
+-- single-use email verification tokens, stored hashed
+CREATE TABLE IF NOT EXISTS email_verification_tokens(
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    -- sha256 of the token, hex encoded
+    token_hash CHAR(64) NOT NULL UNIQUE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+-- create index for email verification tokens for user_id
+CREATE INDEX IF NOT EXISTS email_verification_tokens_user_id_index ON email_verification_tokens(user_id);
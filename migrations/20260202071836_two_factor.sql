@@ -0,0 +1,39 @@
+-- TOTP secrets (RFC 6238), enabled once the user confirmed a first code
+CREATE TABLE IF NOT EXISTS user_totp(
+    user_id BIGINT PRIMARY KEY REFERENCES users(id),
+    -- base32 encoded secret
+    secret VARCHAR(64) NOT NULL,
+    -- time step of the last accepted code, a code can't be used twice
+    last_used_step BIGINT NOT NULL DEFAULT 0,
+    enabled_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+-- one-time recovery codes, stored hashed
+CREATE TABLE IF NOT EXISTS totp_recovery_codes(
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    -- sha256 of the code, hex encoded
+    code_hash CHAR(64) NOT NULL,
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+-- create index for recovery codes for user_id
+CREATE INDEX IF NOT EXISTS totp_recovery_codes_user_id_index ON totp_recovery_codes(user_id);
+
+-- challenges handed out after the password step of a signin, stored hashed
+CREATE TABLE IF NOT EXISTS signin_challenges(
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    -- sha256 of the challenge, hex encoded
+    token_hash CHAR(64) NOT NULL UNIQUE,
+    attempts INT NOT NULL DEFAULT 0,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+-- workspace owners can require 2fa from all members
+ALTER TABLE workspaces
+  ADD COLUMN require_two_factor BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,39 @@
+-- users signing in with sso only have no password
+ALTER TABLE users
+  ALTER COLUMN password_hash DROP NOT NULL;
+
+-- OpenID Connect provider of a workspace
+CREATE TABLE IF NOT EXISTS workspace_sso(
+    ws_id BIGINT PRIMARY KEY REFERENCES workspaces(id),
+    issuer VARCHAR(256) NOT NULL,
+    client_id VARCHAR(256) NOT NULL,
+    -- confidential clients only, public clients rely on PKCE alone
+    client_secret VARCHAR(256),
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+-- sso logins in progress, between the redirect to the provider and the callback
+CREATE TABLE IF NOT EXISTS sso_logins(
+    id BIGSERIAL PRIMARY KEY,
+    ws_id BIGINT NOT NULL REFERENCES workspaces(id),
+    -- sha256 of the state parameter, hex encoded
+    state_hash CHAR(64) NOT NULL UNIQUE,
+    nonce VARCHAR(64) NOT NULL,
+    code_verifier VARCHAR(128) NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+-- users linked to an identity at an OpenID provider
+CREATE TABLE IF NOT EXISTS user_identities(
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    issuer VARCHAR(256) NOT NULL,
+    subject VARCHAR(256) NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (issuer, subject)
+);
+
+-- create index for user identities for user_id
+CREATE INDEX IF NOT EXISTS user_identities_user_id_index ON user_identities(user_id);
@@ -0,0 +1,23 @@
+-- bot users act through API keys only, they have no password
+ALTER TABLE users
+  ADD COLUMN is_bot BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- workspace-scoped API keys of bot users, stored hashed
+CREATE TABLE IF NOT EXISTS api_keys(
+    id BIGSERIAL PRIMARY KEY,
+    ws_id BIGINT NOT NULL REFERENCES workspaces(id),
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    name VARCHAR(64) NOT NULL,
+    -- first characters of the key, to tell keys apart
+    key_prefix VARCHAR(16) NOT NULL,
+    -- sha256 of the key, hex encoded
+    key_hash CHAR(64) NOT NULL UNIQUE,
+    scopes TEXT[] NOT NULL,
+    created_by BIGINT NOT NULL REFERENCES users(id),
+    last_used_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+-- create index for api keys for ws_id
+CREATE INDEX IF NOT EXISTS api_keys_ws_id_index ON api_keys(ws_id);
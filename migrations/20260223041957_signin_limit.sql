@@ -0,0 +1,26 @@
+-- failed signins, counted per account (lowercased email) and per source ip
+CREATE TABLE IF NOT EXISTS signin_failures(
+    -- account or ip
+    scope VARCHAR(16) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    -- failures since the last success or lockout
+    failures INT NOT NULL DEFAULT 0,
+    last_failed_at TIMESTAMPTZ NOT NULL,
+    locked_until TIMESTAMPTZ,
+    PRIMARY KEY (scope, subject)
+);
+
+-- audit trail of lockouts
+CREATE TABLE IF NOT EXISTS signin_lockouts(
+    id BIGSERIAL PRIMARY KEY,
+    scope VARCHAR(16) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    -- source ip of the attempt that triggered the lockout
+    ip VARCHAR(64),
+    failures INT NOT NULL,
+    locked_until TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+-- create index for signin_lockouts for subject lookups
+CREATE INDEX IF NOT EXISTS signin_lockouts_subject_index ON signin_lockouts(scope, subject);
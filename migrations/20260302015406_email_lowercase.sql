@@ -0,0 +1,29 @@
+-- accounts whose email only differed by case from another one, before emails were normalized
+CREATE TABLE IF NOT EXISTS email_conflicts(
+    user_id BIGINT PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    -- the address the account had, now kept by another account
+    email VARCHAR(64) NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+-- the verified, then oldest, account keeps the address, the others get a reserved one nobody
+-- can sign in with or receive mails at, until they are sorted out from email_conflicts
+WITH ranked AS (
+    SELECT id, email,
+        ROW_NUMBER() OVER (
+            PARTITION BY LOWER(email)
+            ORDER BY email_verified_at IS NULL, id
+        ) AS rank
+    FROM users
+),
+conflicts AS (
+    INSERT INTO email_conflicts (user_id, email)
+    SELECT id, email FROM ranked WHERE rank > 1
+    RETURNING user_id
+)
+UPDATE users
+SET email = 'conflict-' || id || '@email.invalid'
+WHERE id IN (SELECT user_id FROM conflicts);
+
+-- emails differing only by case belong to the same user
+CREATE UNIQUE INDEX IF NOT EXISTS email_lower_index ON users(LOWER(email));
@@ -0,0 +1,16 @@
+-- role of users in their workspace
+CREATE TYPE workspace_role AS ENUM(
+    'owner',
+    'admin',
+    'member',
+    'guest'
+);
+
+ALTER TABLE users
+  ADD COLUMN role workspace_role NOT NULL DEFAULT 'member';
+
+-- workspace owners get the owner role
+UPDATE users u
+  SET role = 'owner'
+  FROM workspaces w
+  WHERE w.owner_id = u.id AND w.id = u.ws_id;
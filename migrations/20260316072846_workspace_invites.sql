@@ -0,0 +1,24 @@
+-- invites to join a workspace, stored hashed
+CREATE TABLE IF NOT EXISTS workspace_invites(
+    id BIGSERIAL PRIMARY KEY,
+    ws_id BIGINT NOT NULL REFERENCES workspaces(id),
+    -- sha256 of the code, hex encoded
+    code_hash CHAR(64) NOT NULL UNIQUE,
+    -- only this email can use the invite, shareable codes have none
+    email VARCHAR(64),
+    role workspace_role NOT NULL DEFAULT 'member',
+    -- unlimited if not set
+    max_uses INT,
+    uses INT NOT NULL DEFAULT 0,
+    expires_at TIMESTAMPTZ NOT NULL,
+    revoked_at TIMESTAMPTZ,
+    created_by BIGINT NOT NULL REFERENCES users(id),
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+-- create index for workspace_invites for ws_id
+CREATE INDEX IF NOT EXISTS workspace_invites_ws_id_index ON workspace_invites(ws_id);
+
+-- users with a verified email in one of these domains join without an invite
+ALTER TABLE workspaces
+  ADD COLUMN auto_join_domains TEXT[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,35 @@
+-- one session per signin, shared by its refresh token family and the access tokens issued from it
+CREATE TABLE IF NOT EXISTS sessions(
+    -- the family_id of its refresh tokens
+    id UUID PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    user_agent VARCHAR(256),
+    -- last known ip
+    ip VARCHAR(64),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_seen_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    revoked_at TIMESTAMPTZ
+);
+
+-- create index for sessions for user_id
+CREATE INDEX IF NOT EXISTS sessions_user_id_index ON sessions(user_id);
+
+-- signins from before sessions existed, where from is unknown
+INSERT INTO sessions (id, user_id, created_at, last_seen_at, revoked_at)
+SELECT family_id, MIN(user_id), MIN(created_at), MAX(created_at),
+  CASE WHEN bool_and(revoked_at IS NOT NULL) THEN MAX(revoked_at) END
+FROM refresh_tokens
+GROUP BY family_id
+ON CONFLICT (id) DO NOTHING;
+
+-- revoking every token of a user ends all its sessions as well
+CREATE OR REPLACE FUNCTION revoke_user_tokens(uid bigint)
+  RETURNS void
+  AS $$
+BEGIN
+  UPDATE users SET tokens_revoked_at = NOW() WHERE id = uid;
+  UPDATE refresh_tokens SET revoked_at = NOW() WHERE user_id = uid AND revoked_at IS NULL;
+  UPDATE sessions SET revoked_at = NOW() WHERE user_id = uid AND revoked_at IS NULL;
+END;
+$$
+LANGUAGE plpgsql;
@@ -0,0 +1,13 @@
+-- single-use tickets to connect to notify_server, so that access tokens stay out of urls
+CREATE TABLE IF NOT EXISTS event_tickets(
+    -- sha256 of the ticket, hex encoded
+    ticket_hash CHAR(64) PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    -- session of the token the ticket was taken with
+    session_id UUID,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+-- create index for event tickets for expires_at
+CREATE INDEX IF NOT EXISTS event_tickets_expires_at_index ON event_tickets(expires_at);
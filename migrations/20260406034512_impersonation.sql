@@ -0,0 +1,21 @@
+-- system admins run the service, across workspaces
+ALTER TABLE users
+  ADD COLUMN is_system_admin BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- every request made with an impersonation token, and the impersonations themselves
+CREATE TABLE IF NOT EXISTS impersonation_logs(
+    id BIGSERIAL PRIMARY KEY,
+    admin_id BIGINT NOT NULL REFERENCES users(id),
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    method VARCHAR(16) NOT NULL,
+    -- without the query string, which may carry secrets
+    path VARCHAR(256) NOT NULL,
+    status SMALLINT NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+-- create index for impersonation logs for admin_id
+CREATE INDEX IF NOT EXISTS impersonation_logs_admin_id_index ON impersonation_logs(admin_id);
+
+-- create index for impersonation logs for user_id
+CREATE INDEX IF NOT EXISTS impersonation_logs_user_id_index ON impersonation_logs(user_id);
@@ -0,0 +1,6 @@
+-- profile of the user, fullname is the display name
+ALTER TABLE users
+  ADD COLUMN title VARCHAR(64),
+  ADD COLUMN timezone VARCHAR(64),
+  -- url of a chat file, resized on upload
+  ADD COLUMN avatar_url VARCHAR(256);
@@ -0,0 +1,30 @@
+-- presence of users, derived from their connections to notify_server
+CREATE TYPE presence_status AS ENUM(
+    'online',
+    'away',
+    'offline'
+);
+
+-- open event streams, of every notify_server instance
+CREATE TABLE IF NOT EXISTS presence_connections(
+    id UUID PRIMARY KEY,
+    -- the instance holding the stream, which keeps last_seen_at fresh while it runs
+    instance_id UUID NOT NULL,
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    connected_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_seen_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- create index for presence connections for user_id
+CREATE INDEX IF NOT EXISTS presence_connections_user_id_index ON presence_connections(user_id);
+
+-- create index for presence connections for instance_id
+CREATE INDEX IF NOT EXISTS presence_connections_instance_id_index ON presence_connections(instance_id);
+
+-- last status sent to workspace peers, and the last activity reported by clients
+CREATE TABLE IF NOT EXISTS user_presence(
+    user_id BIGINT PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    status presence_status NOT NULL DEFAULT 'offline',
+    last_active_at TIMESTAMPTZ,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
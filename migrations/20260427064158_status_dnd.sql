@@ -0,0 +1,58 @@
+-- status set by users, cleared once expired
+ALTER TABLE users
+  ADD COLUMN status_emoji VARCHAR(32),
+  ADD COLUMN status_text VARCHAR(128),
+  ADD COLUMN status_expires_at TIMESTAMPTZ,
+  -- do-not-disturb until then, on top of the schedule
+  ADD COLUMN dnd_until TIMESTAMPTZ;
+
+-- weekly do-not-disturb windows, in the time zone of the user
+CREATE TABLE IF NOT EXISTS dnd_windows(
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- iso day of week the window starts on, 1 for monday
+    weekday SMALLINT NOT NULL CHECK (weekday BETWEEN 1 AND 7),
+    start_time TIME NOT NULL,
+    -- before start_time for windows ending the next day
+    end_time TIME NOT NULL CHECK (end_time <> start_time)
+);
+
+-- create index for dnd windows for user_id
+CREATE INDEX IF NOT EXISTS dnd_windows_user_id_index ON dnd_windows(user_id);
+
+-- notifications held back by notify_server while their user is in do-not-disturb
+CREATE TABLE IF NOT EXISTS deferred_events(
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- e.g. message:42, every instance holding a stream of the user may defer the same event
+    event_key VARCHAR(64) NOT NULL,
+    event JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (user_id, event_key)
+);
+
+-- if the status of a user changed, notify with the new status
+CREATE OR REPLACE FUNCTION user_status_updated()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF NEW.status_emoji IS DISTINCT FROM OLD.status_emoji
+    OR NEW.status_text IS DISTINCT FROM OLD.status_text
+    OR NEW.status_expires_at IS DISTINCT FROM OLD.status_expires_at THEN
+    PERFORM
+      pg_notify('user_status_updated', json_build_object(
+        'userId', NEW.id,
+        'emoji', NEW.status_emoji,
+        'text', NEW.status_text,
+        'expiresAt', NEW.status_expires_at
+      )::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER user_status_updated_trigger
+  AFTER UPDATE OF status_emoji, status_text, status_expires_at ON users
+  FOR EACH ROW
+  EXECUTE FUNCTION user_status_updated();
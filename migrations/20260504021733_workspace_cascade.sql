@@ -0,0 +1,92 @@
+-- deleting a workspace deletes its chats, messages, users and everything they own
+-- workspaces.owner_id is left as is, the owner goes away with its workspace
+-- messages.sender_id still blocks deleting users, messages elsewhere must not go with them
+
+ALTER TABLE users
+  DROP CONSTRAINT users_ws_id_fk,
+  ADD CONSTRAINT users_ws_id_fk FOREIGN KEY (ws_id) REFERENCES workspaces(id) ON DELETE CASCADE;
+
+ALTER TABLE users
+  DROP CONSTRAINT users_pending_ws_id_fkey,
+  ADD CONSTRAINT users_pending_ws_id_fkey FOREIGN KEY (pending_ws_id) REFERENCES workspaces(id) ON DELETE CASCADE;
+
+ALTER TABLE chats
+  DROP CONSTRAINT chats_ws_id_fkey,
+  ADD CONSTRAINT chats_ws_id_fkey FOREIGN KEY (ws_id) REFERENCES workspaces(id) ON DELETE CASCADE;
+
+ALTER TABLE messages
+  DROP CONSTRAINT messages_chat_id_fkey,
+  ADD CONSTRAINT messages_chat_id_fkey FOREIGN KEY (chat_id) REFERENCES chats(id) ON DELETE CASCADE;
+
+ALTER TABLE workspace_sso
+  DROP CONSTRAINT workspace_sso_ws_id_fkey,
+  ADD CONSTRAINT workspace_sso_ws_id_fkey FOREIGN KEY (ws_id) REFERENCES workspaces(id) ON DELETE CASCADE;
+
+ALTER TABLE sso_logins
+  DROP CONSTRAINT sso_logins_ws_id_fkey,
+  ADD CONSTRAINT sso_logins_ws_id_fkey FOREIGN KEY (ws_id) REFERENCES workspaces(id) ON DELETE CASCADE;
+
+ALTER TABLE api_keys
+  DROP CONSTRAINT api_keys_ws_id_fkey,
+  ADD CONSTRAINT api_keys_ws_id_fkey FOREIGN KEY (ws_id) REFERENCES workspaces(id) ON DELETE CASCADE;
+
+ALTER TABLE api_keys
+  DROP CONSTRAINT api_keys_user_id_fkey,
+  ADD CONSTRAINT api_keys_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+ALTER TABLE api_keys
+  DROP CONSTRAINT api_keys_created_by_fkey,
+  ADD CONSTRAINT api_keys_created_by_fkey FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE CASCADE;
+
+ALTER TABLE workspace_invites
+  DROP CONSTRAINT workspace_invites_ws_id_fkey,
+  ADD CONSTRAINT workspace_invites_ws_id_fkey FOREIGN KEY (ws_id) REFERENCES workspaces(id) ON DELETE CASCADE;
+
+ALTER TABLE workspace_invites
+  DROP CONSTRAINT workspace_invites_created_by_fkey,
+  ADD CONSTRAINT workspace_invites_created_by_fkey FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE CASCADE;
+
+ALTER TABLE refresh_tokens
+  DROP CONSTRAINT refresh_tokens_user_id_fkey,
+  ADD CONSTRAINT refresh_tokens_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+ALTER TABLE revoked_tokens
+  DROP CONSTRAINT revoked_tokens_user_id_fkey,
+  ADD CONSTRAINT revoked_tokens_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+ALTER TABLE password_reset_tokens
+  DROP CONSTRAINT password_reset_tokens_user_id_fkey,
+  ADD CONSTRAINT password_reset_tokens_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+ALTER TABLE email_verification_tokens
+  DROP CONSTRAINT email_verification_tokens_user_id_fkey,
+  ADD CONSTRAINT email_verification_tokens_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+ALTER TABLE user_totp
+  DROP CONSTRAINT user_totp_user_id_fkey,
+  ADD CONSTRAINT user_totp_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+ALTER TABLE totp_recovery_codes
+  DROP CONSTRAINT totp_recovery_codes_user_id_fkey,
+  ADD CONSTRAINT totp_recovery_codes_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+ALTER TABLE signin_challenges
+  DROP CONSTRAINT signin_challenges_user_id_fkey,
+  ADD CONSTRAINT signin_challenges_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+ALTER TABLE user_identities
+  DROP CONSTRAINT user_identities_user_id_fkey,
+  ADD CONSTRAINT user_identities_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+ALTER TABLE sessions
+  DROP CONSTRAINT sessions_user_id_fkey,
+  ADD CONSTRAINT sessions_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+ALTER TABLE event_tickets
+  DROP CONSTRAINT event_tickets_user_id_fkey,
+  ADD CONSTRAINT event_tickets_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+-- the audit trail outlives the users, the ids are kept as they were
+ALTER TABLE impersonation_logs
+  DROP CONSTRAINT impersonation_logs_admin_id_fkey,
+  DROP CONSTRAINT impersonation_logs_user_id_fkey;
@@ -0,0 +1,55 @@
+-- users can belong to several workspaces, with a role in each
+-- users.ws_id is the workspace signins land in, users.role the role there
+CREATE TABLE IF NOT EXISTS workspace_members(
+    ws_id BIGINT NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role workspace_role NOT NULL DEFAULT 'member',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (ws_id, user_id)
+);
+
+-- create index for workspace_members for user_id
+CREATE INDEX IF NOT EXISTS workspace_members_user_id_index ON workspace_members(user_id);
+
+-- users joined their workspace when they were created, pending ones haven't joined yet
+INSERT INTO workspace_members (ws_id, user_id, role, created_at)
+SELECT ws_id, id, role, created_at
+FROM users
+WHERE ws_id <> 0 AND pending_ws_id IS NULL
+ON CONFLICT DO NOTHING;
+
+-- users created in a workspace, or moved to one once their email is verified, join it
+CREATE OR REPLACE FUNCTION add_workspace_member()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF NEW.ws_id <> 0 AND NEW.pending_ws_id IS NULL THEN
+    INSERT INTO workspace_members (ws_id, user_id, role)
+      VALUES (NEW.ws_id, NEW.id, NEW.role)
+    ON CONFLICT DO NOTHING;
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER add_workspace_member_trigger
+  AFTER INSERT OR UPDATE OF ws_id, pending_ws_id ON users
+  FOR EACH ROW
+  EXECUTE FUNCTION add_workspace_member();
+
+-- the workspace a session switched to, NULL for the one of its user
+ALTER TABLE sessions
+  ADD COLUMN ws_id BIGINT REFERENCES workspaces(id) ON DELETE SET NULL;
+
+-- 2fa challenges sign into the workspace the password or sso login was for, the few ones in
+-- flight are dropped
+DELETE FROM signin_challenges;
+ALTER TABLE signin_challenges
+  ADD COLUMN ws_id BIGINT NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE;
+
+-- tickets connect in the workspace of the token they were taken with, not the current one of
+-- the user, the few ones in flight are dropped
+DELETE FROM event_tickets;
+ALTER TABLE event_tickets
+  ADD COLUMN ws_id BIGINT NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE;
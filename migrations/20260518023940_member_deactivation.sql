@@ -0,0 +1,5 @@
+-- deactivated members keep their account and messages, but lose access to the workspace
+ALTER TABLE workspace_members
+  ADD COLUMN deactivated_at TIMESTAMPTZ,
+  -- chats the member was dropped from when deactivated, it is added back on reactivation
+  ADD COLUMN left_chat_ids BIGINT[] NOT NULL DEFAULT '{}';
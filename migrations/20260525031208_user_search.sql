@@ -0,0 +1,6 @@
+-- fuzzy search of the user directory on name and email
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX IF NOT EXISTS users_fullname_trgm_index ON users USING GIN (fullname gin_trgm_ops);
+
+CREATE INDEX IF NOT EXISTS users_email_trgm_index ON users USING GIN (email gin_trgm_ops);
@@ -0,0 +1,15 @@
+-- the cut-off is given by the server issuing the tokens, so it's compared with token issue
+-- times from the same clock, the refresh tokens and sessions just end. By hand:
+--   SELECT revoke_user_tokens(<user id>);
+DROP FUNCTION IF EXISTS revoke_user_tokens(bigint);
+
+CREATE OR REPLACE FUNCTION revoke_user_tokens(uid bigint, cutoff timestamptz DEFAULT NOW())
+  RETURNS void
+  AS $$
+BEGIN
+  UPDATE users SET tokens_revoked_at = cutoff WHERE id = uid;
+  UPDATE refresh_tokens SET revoked_at = NOW() WHERE user_id = uid AND revoked_at IS NULL;
+  UPDATE sessions SET revoked_at = NOW() WHERE user_id = uid AND revoked_at IS NULL;
+END;
+$$
+LANGUAGE plpgsql;
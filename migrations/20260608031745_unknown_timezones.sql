@@ -0,0 +1,5 @@
+-- time zones stored before they were checked against the ones postgres knows would fail every
+-- do-not-disturb query they are part of, the users are back to UTC
+UPDATE users
+SET timezone = NULL
+WHERE timezone NOT IN (SELECT name FROM pg_timezone_names);
@@ -1,4 +1,5 @@
 use anyhow::{Result, bail};
+use chat_core::{CookiePolicy, TokenPolicy};
 use serde::{Deserialize, Serialize};
 use std::{env, fs::File};
 
@@ -6,11 +7,29 @@ use std::{env, fs::File};
 pub struct AppConfig {
     pub server: ServerConfig,
     pub auth: AuthConfig,
+    #[serde(default)]
+    pub presence: PresenceConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthConfig {
+    /// public key used until the JWKS is loaded, or for good if there is no `jwks_url`
     pub pk: String,
+    /// JWKS endpoint of chat_server, e.g. http://localhost:6688/.well-known/jwks.json
+    #[serde(default)]
+    pub jwks_url: Option<String>,
+    /// seconds between two JWKS reloads
+    #[serde(default = "default_jwks_interval")]
+    pub jwks_interval: u64,
+    /// issuer, audiences and leeway of accepted tokens, must match chat_server
+    #[serde(default)]
+    pub token: TokenPolicy,
+    /// accept access tokens in `?token=` on top of tickets, they end up in access logs and traces
+    #[serde(default)]
+    pub allow_query_token: bool,
+    /// accept the access token from the cookie set by chat_server, names must match
+    #[serde(default)]
+    pub cookie: CookiePolicy,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -19,6 +38,45 @@ pub struct ServerConfig {
     pub db_url: String,
 }
 
+/// Presence is shared by every instance through the database
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PresenceConfig {
+    /// seconds between two refreshes of the connections held by this instance
+    pub interval: u64,
+    /// seconds before connections which weren't refreshed are dropped, e.g. of a crashed
+    /// instance, must be longer than `interval`
+    pub ttl: u64,
+    /// seconds without activity reported by the clients of a user before it is away
+    pub away_after: u64,
+}
+
+impl Default for PresenceConfig {
+    fn default() -> Self {
+        Self {
+            interval: 30,
+            ttl: 90,
+            away_after: 300,
+        }
+    }
+}
+
+impl PresenceConfig {
+    fn validate(&self) -> Result<()> {
+        if self.interval == 0 {
+            bail!("presence.interval must be at least 1 second");
+        }
+        if self.ttl <= self.interval {
+            bail!("presence.ttl must be longer than presence.interval");
+        }
+        Ok(())
+    }
+}
+
+fn default_jwks_interval() -> u64 {
+    300
+}
+
 impl AppConfig {
     pub fn load() -> Result<Self> {
         // read from ./app.yaml, /etc/config/app.yaml, or from env CHAT_CONFIG
@@ -32,6 +90,8 @@ impl AppConfig {
             (_, _, Ok(path)) => serde_yaml::from_reader(File::open(path)?),
             _ => bail!("Notify config file not found"),
         };
-        Ok(ret?)
+        let config: Self = ret?;
+        config.presence.validate()?;
+        Ok(config)
     }
 }
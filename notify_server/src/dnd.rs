@@ -0,0 +1,93 @@
+use crate::{AppError, AppEvent, AppState};
+use chat_core::users_in_dnd;
+use std::collections::HashSet;
+
+impl AppState {
+    /// Hold back the event for its recipients connected here and in do-not-disturb, returns them.
+    ///
+    /// Every instance holding a stream of a recipient defers the event, it is stored once.
+    pub(crate) async fn defer_for_dnd(
+        &self,
+        user_ids: &HashSet<u64>,
+        event: &AppEvent,
+    ) -> Result<HashSet<u64>, AppError> {
+        let candidates: Vec<i64> = user_ids
+            .iter()
+            .filter(|id| self.users.contains_key(id) && event.defer_key(**id).is_some())
+            .map(|id| *id as i64)
+            .collect();
+        let in_dnd = users_in_dnd(&self.pool, &candidates).await?;
+        if in_dnd.is_empty() {
+            return Ok(HashSet::new());
+        }
+
+        let data = serde_json::to_string(event).expect("Failed to serialize event");
+        for user_id in &in_dnd {
+            sqlx::query(
+                "
+                INSERT INTO deferred_events (user_id, event_key, event)
+                VALUES ($1, $2, $3::JSONB)
+                ON CONFLICT (user_id, event_key) DO NOTHING
+                ",
+            )
+            .bind(user_id)
+            .bind(event.defer_key(*user_id as u64))
+            .bind(&data)
+            .execute(&self.pool)
+            .await?;
+        }
+        Ok(in_dnd.into_iter().map(|id| id as u64).collect())
+    }
+
+    /// Send the events held back for users out of do-not-disturb, through every instance
+    pub(crate) async fn release_deferred_events(&self) -> Result<(), AppError> {
+        let user_ids: Vec<i64> = sqlx::query_scalar("SELECT DISTINCT user_id FROM deferred_events")
+            .fetch_all(&self.pool)
+            .await?;
+        let in_dnd: HashSet<i64> = users_in_dnd(&self.pool, &user_ids)
+            .await?
+            .into_iter()
+            .collect();
+        let released: Vec<i64> = user_ids
+            .into_iter()
+            .filter(|id| !in_dnd.contains(id))
+            .collect();
+        if released.is_empty() {
+            return Ok(());
+        }
+
+        // deleting first, so that instances sweeping at once don't send them twice
+        sqlx::query(
+            "
+            WITH released AS (
+                DELETE FROM deferred_events
+                WHERE user_id = ANY($1)
+                RETURNING id, user_id, event
+            )
+            SELECT pg_notify('deferred_event_released', json_build_object(
+                'userId', user_id,
+                'event', event
+            )::text)
+            FROM (SELECT * FROM released ORDER BY id) r
+            ",
+        )
+        .bind(&released)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Clear expired statuses, the users sharing a chat are notified by the trigger
+    pub(crate) async fn clear_expired_statuses(&self) -> Result<(), AppError> {
+        sqlx::query(
+            "
+            UPDATE users
+            SET status_emoji = NULL, status_text = NULL, status_expires_at = NULL
+            WHERE status_expires_at <= NOW()
+            ",
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
@@ -12,8 +12,14 @@ pub enum AppError {
     #[error("io error: {0}")]
     IoError(#[from] std::io::Error),
 
+    #[error("sqlx error: {0}")]
+    SqlxError(#[from] sqlx::Error),
+
     #[error("jwt error: {0}")]
     JwtError(#[from] jwt_simple::Error),
+
+    #[error("token revoked")]
+    TokenRevoked,
 }
 
 impl ErrorOutput {
@@ -26,7 +32,9 @@ impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
         let status = match &self {
             Self::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::SqlxError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::JwtError(_) => StatusCode::FORBIDDEN,
+            Self::TokenRevoked => StatusCode::FORBIDDEN,
         };
 
         (status, Json(ErrorOutput::new(self.to_string()))).into_response()
@@ -0,0 +1,49 @@
+use crate::AppState;
+use anyhow::Result;
+use chat_core::Jwks;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Load the keyset from chat_server's JWKS endpoint and keep polling it, so rotated keys are
+/// picked up without a restart.
+pub async fn setup_jwks_poller(state: AppState) -> Result<()> {
+    let Some(url) = state.config.auth.jwks_url.clone() else {
+        return Ok(());
+    };
+    let client = reqwest::Client::new();
+
+    // chat_server may not be up yet, keep the configured key until the next poll
+    if let Err(e) = load_jwks(&state, &client, &url).await {
+        warn!("failed to load jwks from {}: {}", url, e);
+    }
+
+    let period = Duration::from_secs(state.config.auth.jwks_interval.max(1));
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        // the first tick completes immediately, the keyset was just loaded
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if let Err(e) = load_jwks(&state, &client, &url).await {
+                warn!("failed to reload jwks from {}: {}", url, e);
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn load_jwks(state: &AppState, client: &reqwest::Client, url: &str) -> Result<()> {
+    let jwks: Jwks = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    if jwks.keys.is_empty() {
+        anyhow::bail!("jwks has no keys");
+    }
+    state.dk.update(&jwks)?;
+    info!("Loaded {} keys from {}", jwks.keys.len(), url);
+    Ok(())
+}
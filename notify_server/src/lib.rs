@@ -1,6 +1,9 @@
 mod config;
+mod dnd;
 mod error;
+mod jwks;
 mod notif;
+mod presence;
 mod sse;
 
 use anyhow::Result;
@@ -9,21 +12,28 @@ use axum::{
     http::Method,
     middleware::from_fn_with_state,
     response::{Html, IntoResponse},
-    routing::get,
+    routing::{get, post},
 };
 use chat_core::{
-    DecodingKey, User,
-    middlewares::{TokenVerify, verify_token},
+    CookiePolicy, DecodingKey, User, is_token_revoked,
+    middlewares::{ClientInfo, TokenVerify, verify_token},
+    redeem_ticket, touch_session,
 };
 use dashmap::DashMap;
+use presence::{heartbeat_handler, presence_handler};
+use sqlx::PgPool;
 use sse::sse_handler;
 use std::{ops::Deref, sync::Arc};
 use tokio::sync::broadcast;
 use tower_http::cors::{Any, CorsLayer};
+use tracing::warn;
+use uuid::Uuid;
 
 pub use config::AppConfig;
 pub use error::AppError;
+pub use jwks::setup_jwks_poller;
 pub use notif::{AppEvent, setup_pg_listener};
+pub use presence::{Heartbeat, Presence, setup_presence_sweeper};
 
 pub type UserMap = Arc<DashMap<u64, broadcast::Sender<Arc<AppEvent>>>>;
 
@@ -34,6 +44,9 @@ pub struct AppStateInner {
     pub config: AppConfig,
     users: UserMap,
     dk: DecodingKey,
+    pool: PgPool,
+    /// owner of the presence connections of this process
+    instance_id: Uuid,
 }
 
 const INDEX_HTML: &str = include_str!("../index.html");
@@ -50,12 +63,16 @@ pub async fn get_router(config: AppConfig) -> Result<Router> {
         .allow_headers(Any)
         .allow_origin(Any);
 
-    let state = AppState::new(config);
+    let state = AppState::try_new(config)?;
 
     setup_pg_listener(state.clone()).await?;
+    setup_jwks_poller(state.clone()).await?;
+    setup_presence_sweeper(state.clone()).await?;
 
     let app = Router::new()
         .route("/events", get(sse_handler))
+        .route("/presence", get(presence_handler))
+        .route("/presence/heartbeat", post(heartbeat_handler))
         .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
         .layer(cors)
         .route("/", get(index_handler))
@@ -71,8 +88,34 @@ async fn index_handler() -> impl IntoResponse {
 impl TokenVerify for AppState {
     type Error = AppError;
 
-    fn verify(&self, token: &str) -> Result<User, Self::Error> {
-        Ok(self.dk.verify(token)?)
+    async fn verify(&self, token: &str) -> Result<User, Self::Error> {
+        let claims = self.dk.decode(token)?;
+        if is_token_revoked(&self.pool, &claims).await? {
+            return Err(AppError::TokenRevoked);
+        }
+        Ok(claims.custom)
+    }
+
+    async fn redeem_ticket(&self, ticket: &str) -> Result<Option<User>, Self::Error> {
+        Ok(redeem_ticket(&self.pool, ticket).await?)
+    }
+
+    fn allow_query_token(&self) -> bool {
+        self.config.auth.allow_query_token
+    }
+
+    fn cookie_policy(&self) -> Option<&CookiePolicy> {
+        let policy = &self.config.auth.cookie;
+        policy.enabled.then_some(policy)
+    }
+
+    async fn touch(&self, user: &User, client: &ClientInfo) {
+        let Some(sid) = user.sid else {
+            return;
+        };
+        if let Err(e) = touch_session(&self.pool, sid, client.ip).await {
+            warn!("failed to update session {}: {}", sid, e);
+        }
     }
 }
 
@@ -85,9 +128,16 @@ impl Deref for AppState {
 }
 
 impl AppState {
-    pub fn new(config: AppConfig) -> Self {
-        let dk = DecodingKey::load(&config.auth.pk).expect("Failed to load public key");
+    pub fn try_new(config: AppConfig) -> Result<Self, AppError> {
+        let dk = DecodingKey::load(&config.auth.pk)?.with_policy(config.auth.token.clone());
+        let pool = PgPool::connect_lazy(&config.server.db_url)?;
         let users = Arc::new(DashMap::new());
-        Self(Arc::new(AppStateInner { config, dk, users }))
+        Ok(Self(Arc::new(AppStateInner {
+            config,
+            dk,
+            users,
+            pool,
+            instance_id: Uuid::now_v7(),
+        })))
     }
 }
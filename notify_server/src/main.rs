@@ -1,5 +1,6 @@
 use anyhow::Result;
 use notify_server::{AppConfig, get_router};
+use std::net::SocketAddr;
 use tokio::net::TcpListener;
 use tracing::{info, level_filters::LevelFilter};
 use tracing_subscriber::{Layer as _, fmt::Layer, layer::SubscriberExt, util::SubscriberInitExt};
@@ -15,6 +16,8 @@ async fn main() -> Result<()> {
     let listener = TcpListener::bind(addr).await?;
     let app = get_router(config).await?;
 
+    // client ips are recorded as the last known ip of sessions
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
     axum::serve(listener, app).await?;
 
     Ok(())
@@ -1,6 +1,6 @@
-use crate::AppState;
+use crate::{AppState, Presence};
 use anyhow::Result;
-use chat_core::{Chat, Message};
+use chat_core::{Chat, Message, UserStatus};
 use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
 use sqlx::postgres::PgListener;
@@ -14,6 +14,8 @@ pub enum AppEvent {
     AddToChat(Chat),
     RemoveFromChat(Chat),
     NewMessage(Message),
+    Presence(Presence),
+    StatusChanged(UserStatus),
 }
 
 #[derive(Debug)]
@@ -35,10 +37,20 @@ struct ChatMessageCreated {
     members: Vec<i64>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DeferredEventReleased {
+    user_id: i64,
+    event: AppEvent,
+}
+
 pub async fn setup_pg_listener(state: AppState) -> Result<()> {
     let mut listener = PgListener::connect(&state.config.server.db_url).await?;
     listener.listen("chat_updated").await?;
     listener.listen("chat_message_created").await?;
+    listener.listen("presence_updated").await?;
+    listener.listen("user_status_updated").await?;
+    listener.listen("deferred_event_released").await?;
 
     let mut stream = listener.into_stream();
 
@@ -46,12 +58,25 @@ pub async fn setup_pg_listener(state: AppState) -> Result<()> {
         while let Some(Ok(notif)) = stream.next().await {
             info!("Received notification: {:?}", notif);
 
-            let notif = Notification::load(notif.channel(), notif.payload())?;
+            let notif = match Notification::load(&state, notif.channel(), notif.payload()).await {
+                Ok(notif) => notif,
+                Err(e) => {
+                    warn!("failed to load notification: {}", e);
+                    continue;
+                }
+            };
 
             let users = &state.users;
+            let deferred = match state.defer_for_dnd(&notif.user_ids, &notif.event).await {
+                Ok(deferred) => deferred,
+                Err(e) => {
+                    warn!("failed to defer notif for users in dnd: {}", e);
+                    HashSet::new()
+                }
+            };
 
-            for user_id in notif.user_ids {
-                if let Some(tx) = users.get(&user_id)
+            for user_id in notif.user_ids.difference(&deferred) {
+                if let Some(tx) = users.get(user_id)
                     && let Err(e) = tx.send(notif.event.clone())
                 {
                     warn!("failed to send notif to user {}: {}", user_id, e);
@@ -63,8 +88,20 @@ pub async fn setup_pg_listener(state: AppState) -> Result<()> {
     Ok(())
 }
 
+impl AppEvent {
+    /// Notifications for the user are held back while it is in do-not-disturb, under this key
+    pub(crate) fn defer_key(&self, user_id: u64) -> Option<String> {
+        match self {
+            Self::NewMessage(message) if message.sender_id as u64 != user_id => {
+                Some(format!("message:{}", message.id))
+            }
+            _ => None,
+        }
+    }
+}
+
 impl Notification {
-    fn load(r#type: &str, payload: &str) -> Result<Self> {
+    async fn load(state: &AppState, r#type: &str, payload: &str) -> Result<Self> {
         match r#type {
             "chat_updated" => {
                 let payload: ChatUpdated = serde_json::from_str(payload)?;
@@ -90,6 +127,51 @@ impl Notification {
                     event: Arc::new(AppEvent::NewMessage(payload.message)),
                 })
             }
+            "presence_updated" => {
+                let presence: Presence = serde_json::from_str(payload)?;
+                // the active peers of the user in any of the workspaces it is active in
+                let user_ids: Vec<i64> = sqlx::query_scalar(
+                    "
+                    SELECT DISTINCT peer.user_id
+                    FROM workspace_members m
+                    JOIN workspace_members peer ON peer.ws_id = m.ws_id
+                    WHERE m.user_id = $1 AND peer.user_id <> $1
+                        AND m.deactivated_at IS NULL AND peer.deactivated_at IS NULL
+                    ",
+                )
+                .bind(presence.user_id)
+                .fetch_all(&state.pool)
+                .await?;
+                Ok(Self {
+                    user_ids: user_ids.into_iter().map(|v| v as u64).collect(),
+                    event: Arc::new(AppEvent::Presence(presence)),
+                })
+            }
+            "user_status_updated" => {
+                let status: UserStatus = serde_json::from_str(payload)?;
+                // the users sharing a chat with the user
+                let user_ids: Vec<i64> = sqlx::query_scalar(
+                    "
+                    SELECT DISTINCT m
+                    FROM chats, unnest(members) AS m
+                    WHERE $1 = ANY(members) AND m <> $1
+                    ",
+                )
+                .bind(status.user_id)
+                .fetch_all(&state.pool)
+                .await?;
+                Ok(Self {
+                    user_ids: user_ids.into_iter().map(|v| v as u64).collect(),
+                    event: Arc::new(AppEvent::StatusChanged(status)),
+                })
+            }
+            "deferred_event_released" => {
+                let payload: DeferredEventReleased = serde_json::from_str(payload)?;
+                Ok(Self {
+                    user_ids: HashSet::from([payload.user_id as u64]),
+                    event: Arc::new(payload.event),
+                })
+            }
             _ => Err(anyhow::anyhow!("Invalid notification type")),
         }
     }
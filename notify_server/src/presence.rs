@@ -0,0 +1,219 @@
+use crate::{AppError, AppState};
+use anyhow::Result;
+use axum::{Extension, Json, extract::State, http::StatusCode, response::IntoResponse};
+use chat_core::{PresenceStatus, User};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::time::Duration;
+use tracing::warn;
+use uuid::Uuid;
+
+/// Presence of a user, sent to its workspace peers when it changes
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Presence {
+    pub user_id: i64,
+    pub status: PresenceStatus,
+    pub last_active_at: Option<DateTime<Utc>>,
+}
+
+/// Reported by clients while connected, `active` is false e.g. when the tab is hidden
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Heartbeat {
+    #[serde(default = "default_active")]
+    pub active: bool,
+}
+
+/// An open event stream, removed from the presence of the user when dropped
+pub(crate) struct Connection {
+    id: Uuid,
+    user_id: i64,
+    state: AppState,
+}
+
+/// List the presence of the active members of the workspace
+pub(crate) async fn presence_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let presence: Vec<Presence> = sqlx::query_as(
+        "
+        SELECT m.user_id, COALESCE(p.status, 'offline') AS status, p.last_active_at
+        FROM workspace_members m
+        LEFT JOIN user_presence p ON p.user_id = m.user_id
+        WHERE m.ws_id = $1 AND m.deactivated_at IS NULL
+        ORDER BY m.user_id
+        ",
+    )
+    .bind(user.ws_id)
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(Json(presence))
+}
+
+/// Report the activity of the user, clients send one every `presence.interval` or so
+pub(crate) async fn heartbeat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    input: Option<Json<Heartbeat>>,
+) -> Result<impl IntoResponse, AppError> {
+    let active = input.is_none_or(|Json(input)| input.active);
+    if active {
+        state.record_activity(user.id).await?;
+    }
+    state.refresh_presence(Some(&[user.id])).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Keep the connections of this instance alive, drop the stale ones of other instances and
+/// move idle users to away, or offline. Expired statuses are cleared and the events held back
+/// during do-not-disturb released along the way.
+pub async fn setup_presence_sweeper(state: AppState) -> Result<()> {
+    let mut interval = tokio::time::interval(Duration::from_secs(state.config.presence.interval));
+    tokio::spawn(async move {
+        loop {
+            interval.tick().await;
+            if let Err(e) = state.sweep_presence().await {
+                warn!("failed to sweep presence: {}", e);
+            }
+            if let Err(e) = state.clear_expired_statuses().await {
+                warn!("failed to clear expired statuses: {}", e);
+            }
+            if let Err(e) = state.release_deferred_events().await {
+                warn!("failed to release deferred events: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+impl AppState {
+    /// Register an event stream of the user, connecting counts as activity
+    pub(crate) async fn connect(&self, user: &User) -> Result<Connection, AppError> {
+        let id = Uuid::now_v7();
+        sqlx::query(
+            "
+            INSERT INTO presence_connections (id, instance_id, user_id)
+            VALUES ($1, $2, $3)
+            ",
+        )
+        .bind(id)
+        .bind(self.instance_id)
+        .bind(user.id)
+        .execute(&self.pool)
+        .await?;
+        self.record_activity(user.id).await?;
+        self.refresh_presence(Some(&[user.id])).await?;
+
+        Ok(Connection {
+            id,
+            user_id: user.id,
+            state: self.clone(),
+        })
+    }
+
+    async fn disconnect(&self, id: Uuid, user_id: i64) -> Result<(), AppError> {
+        sqlx::query("DELETE FROM presence_connections WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        self.refresh_presence(Some(&[user_id])).await
+    }
+
+    async fn record_activity(&self, user_id: i64) -> Result<(), AppError> {
+        sqlx::query(
+            "
+            INSERT INTO user_presence (user_id, last_active_at)
+            VALUES ($1, NOW())
+            ON CONFLICT (user_id) DO UPDATE SET last_active_at = NOW()
+            ",
+        )
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn sweep_presence(&self) -> Result<(), AppError> {
+        let config = &self.config.presence;
+        sqlx::query("UPDATE presence_connections SET last_seen_at = NOW() WHERE instance_id = $1")
+            .bind(self.instance_id)
+            .execute(&self.pool)
+            .await?;
+        sqlx::query(
+            "DELETE FROM presence_connections WHERE last_seen_at < NOW() - make_interval(secs => $1)",
+        )
+        .bind(config.ttl as f64)
+        .execute(&self.pool)
+        .await?;
+        self.refresh_presence(None).await
+    }
+
+    /// Derive the status of the given users, or of every user not offline, and notify every
+    /// instance of those which changed.
+    ///
+    /// Instances may refresh the same user at once, only the one which changes the row notifies.
+    async fn refresh_presence(&self, user_ids: Option<&[i64]>) -> Result<(), AppError> {
+        let config = &self.config.presence;
+        sqlx::query(
+            "
+            WITH computed AS (
+                SELECT p.user_id, p.last_active_at,
+                    CASE
+                        WHEN NOT EXISTS (
+                            SELECT 1 FROM presence_connections c
+                            WHERE c.user_id = p.user_id
+                                AND c.last_seen_at > NOW() - make_interval(secs => $2)
+                        ) THEN 'offline'
+                        WHEN p.last_active_at > NOW() - make_interval(secs => $3) THEN 'online'
+                        ELSE 'away'
+                    END::presence_status AS status
+                FROM user_presence p
+                WHERE CASE WHEN $1::BIGINT[] IS NULL THEN p.status <> 'offline'
+                    ELSE p.user_id = ANY($1) END
+            ), updated AS (
+                UPDATE user_presence p
+                SET status = c.status, updated_at = NOW()
+                FROM computed c
+                WHERE p.user_id = c.user_id AND p.status <> c.status
+                RETURNING p.user_id, p.status, p.last_active_at
+            )
+            SELECT pg_notify('presence_updated', json_build_object(
+                'userId', user_id,
+                'status', status,
+                'lastActiveAt', last_active_at
+            )::text)
+            FROM updated
+            ",
+        )
+        .bind(user_ids)
+        .bind(config.ttl as f64)
+        .bind(config.away_after as f64)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // streams may outlive the runtime on shutdown, stale connections are swept anyway
+        let Ok(handle) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let (id, user_id, state) = (self.id, self.user_id, self.state.clone());
+        handle.spawn(async move {
+            if let Err(e) = state.disconnect(id, user_id).await {
+                warn!(
+                    "failed to remove connection {} of user {}: {}",
+                    id, user_id, e
+                );
+            }
+        });
+    }
+}
+
+fn default_active() -> bool {
+    true
+}
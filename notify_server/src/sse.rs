@@ -1,4 +1,4 @@
-use crate::{AppEvent, AppState};
+use crate::{AppError, AppEvent, AppState};
 use axum::{
     Extension,
     extract::State,
@@ -18,7 +18,7 @@ pub(crate) async fn sse_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
     // TypedHeader(user_agent): TypedHeader<headers::UserAgent>,
-) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
     // info!("`{}` connected", user_agent.as_str());
 
     let user_id = user.id as u64;
@@ -32,22 +32,29 @@ pub(crate) async fn sse_handler(
         rx
     };
     info!("User {} subscribed", user_id);
+    // the user goes offline once its last stream is dropped
+    let connection = state.connect(&user).await?;
 
-    let stream = BroadcastStream::new(rx).filter_map(|v| v.ok()).map(|v| {
-        let name = match v.as_ref() {
-            AppEvent::NewChat(_) => "NewChat",
-            AppEvent::AddToChat(_) => "AddToChat",
-            AppEvent::RemoveFromChat(_) => "RemoveFromChat",
-            AppEvent::NewMessage(_) => "NewMessage",
-        };
-        let v = serde_json::to_string(&v).expect("Failed to serialize event");
-        debug!("Sending event {}: {:?}", name, v);
-        Ok(Event::default().data(v).event(name))
-    });
+    let stream = BroadcastStream::new(rx)
+        .filter_map(|v| v.ok())
+        .map(move |v| {
+            let _ = &connection;
+            let name = match v.as_ref() {
+                AppEvent::NewChat(_) => "NewChat",
+                AppEvent::AddToChat(_) => "AddToChat",
+                AppEvent::RemoveFromChat(_) => "RemoveFromChat",
+                AppEvent::NewMessage(_) => "NewMessage",
+                AppEvent::Presence(_) => "Presence",
+                AppEvent::StatusChanged(_) => "StatusChanged",
+            };
+            let v = serde_json::to_string(&v).expect("Failed to serialize event");
+            debug!("Sending event {}: {:?}", name, v);
+            Ok(Event::default().data(v).event(name))
+        });
 
-    Sse::new(stream).keep_alive(
+    Ok(Sse::new(stream).keep_alive(
         axum::response::sse::KeepAlive::new()
             .interval(Duration::from_secs(1))
             .text("keep-alive-text"),
-    )
+    ))
 }